@@ -2,5 +2,7 @@
 
 cargo build --release
 cp target/release/termal-term /usr/bin/termal
+cp assets/termal.desktop /usr/share/applications/termal.desktop
 
 
+tic -x assets/termal.terminfo
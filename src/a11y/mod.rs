@@ -0,0 +1,603 @@
+use zbus::blocking::{Connection, Proxy, connection};
+use zbus::zvariant::{OwnedObjectPath, Value};
+use zbus::interface;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// https://gitlab.gnome.org/GNOME/at-spi2-core/-/tree/main/xml
+
+const ROOT: &str = "/org/a11y/atspi/accessible/root";
+const TERMINAL: &str = "/org/a11y/atspi/accessible/terminal";
+const REGISTRY: &str = "org.a11y.atspi.Registry";
+
+const ROLE_TERMINAL: u32 = 60;
+const ROLE_APPLICATION: u32 = 75;
+
+const STATE_ENABLED: u32 = 8;
+const STATE_FOCUSABLE: u32 = 11;
+const STATE_FOCUSED: u32 = 12;
+const STATE_MULTI_LINE: u32 = 17;
+const STATE_SENSITIVE: u32 = 24;
+const STATE_SHOWING: u32 = 25;
+const STATE_VISIBLE: u32 = 30;
+
+// more changes than this in one frame are sent as the whole text being replaced, eg. when output floods the screen
+
+const MAX_CHANGES: usize = 32;
+
+type ObjectRef = (String, OwnedObjectPath);
+
+
+#[derive(Default)]
+struct Snapshot {
+    text: Vec<char>,
+    caret: i32,
+    focused: bool,
+}
+
+impl Snapshot {
+    fn line_bounds(&self, offset: usize) -> (usize, usize) {
+        let start = self.text[..offset.min(self.text.len())].iter().rposition(|c| *c == '\n').map_or(0, |index| index + 1);
+        let end = self.text[start..].iter().position(|c| *c == '\n').map_or(self.text.len(), |index| start + index);
+
+        (start, end)
+    }
+
+    fn word_bounds(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+
+        let start = self.text[..offset].iter().rposition(|c| c.is_whitespace()).map_or(0, |index| index + 1);
+        let end = self.text[offset..].iter().position(|c| c.is_whitespace()).map_or(self.text.len(), |index| offset + index);
+
+        (start, end)
+    }
+
+    fn slice(&self, start: usize, end: usize) -> (String, i32, i32) {
+        (self.text[start..end].iter().collect(), start as i32, end as i32)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Change {
+    insert: bool,
+    offset: usize,
+    text: String,
+}
+
+// the lines last reported, kept in step with the screen by replaying its scrolls and the rows draw repaints.
+// every edit is recorded as the change it makes to the joined text
+
+#[derive(Default)]
+struct Mirror {
+    lines: Vec<Vec<char>>,
+    changes: Vec<Change>,
+}
+
+impl Mirror {
+    fn offset(&self, y: usize) -> usize {
+        self.lines[..y.min(self.lines.len())].iter().map(|line| line.len() + 1).sum()
+    }
+
+    fn text(&self) -> Vec<char> {
+        self.lines.join(&'\n')
+    }
+
+    fn caret(&self, (x, y): (usize, usize)) -> usize {
+        self.offset(y) + self.lines.get(y).map_or(0, |line| line.len().min(x))
+    }
+
+    fn resize(&mut self, rows: usize) {
+        if self.lines.len() == rows {
+            return;
+        }
+
+        let text = self.text();
+
+        if !text.is_empty() {
+            self.changes.push(Change { insert: false, offset: 0, text: text.into_iter().collect() });
+        }
+
+        self.lines = vec![Vec::new(); rows];
+
+        if rows > 1 {
+            self.changes.push(Change { insert: true, offset: 0, text: "\n".repeat(rows - 1) });
+        }
+    }
+
+    fn set(&mut self, y: usize, line: Vec<char>) {
+        let Some(old) = self.lines.get(y) else { return };
+
+        let prefix = old.iter().zip(&line).take_while(|(old, new)| old == new).count();
+        let offset = self.offset(y) + prefix;
+
+        if old.len() > prefix {
+            self.changes.push(Change { insert: false, offset, text: old[prefix..].iter().collect() });
+        }
+
+        if line.len() > prefix {
+            self.changes.push(Change { insert: true, offset, text: line[prefix..].iter().collect() });
+        }
+
+        self.lines[y] = line;
+    }
+
+    // the lines from top to bottom moved by count, the ones pushed out are deleted and blank lines come in on the other side
+
+    fn scroll(&mut self, top: usize, bottom: usize, count: usize, up: bool) {
+        let bottom = bottom.min(self.lines.len().saturating_sub(1));
+
+        if self.lines.is_empty() || top > bottom || count == 0 {
+            return;
+        }
+
+        let count = count.min(bottom + 1 - top);
+
+        if up {
+            self.remove(top, count);
+            self.insert(bottom + 1 - count, count);
+        } else {
+            self.remove(bottom + 1 - count, count);
+            self.insert(top, count);
+        }
+    }
+
+    fn remove(&mut self, y: usize, count: usize) {
+        let removed = self.lines[y..y + count].join(&'\n').into_iter().collect::<String>();
+
+        // the newline after the last line belongs to the one before it
+
+        let change = match (y + count < self.lines.len(), y > 0) {
+            (true, _) => Change { insert: false, offset: self.offset(y), text: removed + "\n" },
+            (false, true) => Change { insert: false, offset: self.offset(y) - 1, text: String::from("\n") + &removed },
+            (false, false) => Change { insert: false, offset: 0, text: removed },
+        };
+
+        if !change.text.is_empty() {
+            self.changes.push(change);
+        }
+
+        self.lines.drain(y..y + count);
+    }
+
+    fn insert(&mut self, y: usize, count: usize) {
+        let change = match (y < self.lines.len(), self.lines.is_empty()) {
+            (true, _) => Change { insert: true, offset: self.offset(y), text: "\n".repeat(count) },
+            (false, false) => Change { insert: true, offset: self.offset(self.lines.len()) - 1, text: "\n".repeat(count) },
+            (false, true) => Change { insert: true, offset: 0, text: "\n".repeat(count - 1) },
+        };
+
+        if !change.text.is_empty() {
+            self.changes.push(change);
+        }
+
+        self.lines.splice(y..y, vec![Vec::new(); count]);
+    }
+}
+
+struct Accessible {
+    snapshot: Arc<Mutex<Snapshot>>,
+    name: String,
+    root: bool,
+}
+
+impl Accessible {
+    fn object(&self, path: &str) -> ObjectRef {
+        (self.name.clone(), OwnedObjectPath::try_from(path).expect("static object path"))
+    }
+}
+
+#[interface(name = "org.a11y.atspi.Accessible")]
+impl Accessible {
+    #[zbus(property)]
+    fn name(&self) -> String {
+        String::from(if self.root { "termal" } else { "Terminal" })
+    }
+
+    #[zbus(property)]
+    fn description(&self) -> String {
+        String::new()
+    }
+
+    #[zbus(property)]
+    fn parent(&self) -> ObjectRef {
+        if self.root {
+            (REGISTRY.to_string(), OwnedObjectPath::try_from(ROOT).expect("static object path"))
+        } else {
+            self.object(ROOT)
+        }
+    }
+
+    #[zbus(property)]
+    fn child_count(&self) -> i32 {
+        self.root as i32
+    }
+
+    #[zbus(property)]
+    fn locale(&self) -> String {
+        String::new()
+    }
+
+    #[zbus(property)]
+    fn accessible_id(&self) -> String {
+        String::new()
+    }
+
+    fn get_child_at_index(&self, index: i32) -> (ObjectRef,) {
+        if self.root && index == 0 {
+            (self.object(TERMINAL),)
+        } else {
+            (self.object("/org/a11y/atspi/null"),)
+        }
+    }
+
+    fn get_children(&self) -> Vec<ObjectRef> {
+        if self.root {
+            vec![self.object(TERMINAL)]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn get_index_in_parent(&self) -> i32 {
+        if self.root { -1 } else { 0 }
+    }
+
+    fn get_relation_set(&self) -> Vec<(u32, Vec<ObjectRef>)> {
+        Vec::new()
+    }
+
+    fn get_role(&self) -> u32 {
+        if self.root { ROLE_APPLICATION } else { ROLE_TERMINAL }
+    }
+
+    fn get_role_name(&self) -> String {
+        String::from(if self.root { "application" } else { "terminal" })
+    }
+
+    fn get_localized_role_name(&self) -> String {
+        self.get_role_name()
+    }
+
+    fn get_state(&self) -> Vec<u32> {
+        let mut states = [0u32; 2];
+
+        if !self.root {
+            let mut set = vec![STATE_ENABLED, STATE_FOCUSABLE, STATE_MULTI_LINE, STATE_SENSITIVE, STATE_SHOWING, STATE_VISIBLE];
+
+            if self.snapshot.lock().is_ok_and(|snapshot| snapshot.focused) {
+                set.push(STATE_FOCUSED);
+            }
+
+            for state in set {
+                states[state as usize / 32] |= 1 << (state % 32);
+            }
+        }
+
+        states.to_vec()
+    }
+
+    fn get_attributes(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+
+    fn get_application(&self) -> (ObjectRef,) {
+        (self.object(ROOT),)
+    }
+
+    fn get_interfaces(&self) -> Vec<String> {
+        if self.root {
+            vec![String::from("org.a11y.atspi.Accessible"), String::from("org.a11y.atspi.Application")]
+        } else {
+            vec![String::from("org.a11y.atspi.Accessible"), String::from("org.a11y.atspi.Text")]
+        }
+    }
+}
+
+struct Application {
+    id: i32,
+}
+
+#[interface(name = "org.a11y.atspi.Application")]
+impl Application {
+    #[zbus(property)]
+    fn toolkit_name(&self) -> String {
+        String::from("termal")
+    }
+
+    #[zbus(property)]
+    fn version(&self) -> String {
+        String::from(env!("CARGO_PKG_VERSION"))
+    }
+
+    #[zbus(property)]
+    fn atspi_version(&self) -> String {
+        String::from("2.1")
+    }
+
+    #[zbus(property)]
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    #[zbus(property)]
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn get_locale(&self, _lctype: u32) -> String {
+        String::new()
+    }
+}
+
+struct Text {
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+#[interface(name = "org.a11y.atspi.Text")]
+impl Text {
+    #[zbus(property)]
+    fn character_count(&self) -> i32 {
+        self.snapshot.lock().map_or(0, |snapshot| snapshot.text.len() as i32)
+    }
+
+    #[zbus(property)]
+    fn caret_offset(&self) -> i32 {
+        self.snapshot.lock().map_or(0, |snapshot| snapshot.caret)
+    }
+
+    fn get_text(&self, start: i32, end: i32) -> String {
+        self.snapshot.lock().map_or(String::new(), |snapshot| {
+            let end = if end < 0 { snapshot.text.len() } else { (end as usize).min(snapshot.text.len()) };
+
+            snapshot.text[(start.max(0) as usize).min(end)..end].iter().collect()
+        })
+    }
+
+    fn get_character_at_offset(&self, offset: i32) -> i32 {
+        self.snapshot.lock().ok()
+            .and_then(|snapshot| snapshot.text.get(offset.max(0) as usize).copied())
+            .map_or(0, |c| c as i32)
+    }
+
+    // granularity: 0 char, 1 word, 2 sentence, 3 line, 4 paragraph
+
+    fn get_string_at_offset(&self, offset: i32, granularity: u32) -> (String, i32, i32) {
+        self.snapshot.lock().map_or((String::new(), 0, 0), |snapshot| {
+            let offset = offset.max(0) as usize;
+
+            match granularity {
+                0 if offset < snapshot.text.len() => snapshot.slice(offset, offset + 1),
+                0 => (String::new(), offset as i32, offset as i32),
+                1 => {
+                    let (start, end) = snapshot.word_bounds(offset);
+
+                    snapshot.slice(start, end)
+                },
+                _ => {
+                    let (start, end) = snapshot.line_bounds(offset);
+
+                    snapshot.slice(start, end)
+                },
+            }
+        })
+    }
+
+    // boundary: 0 char, 1-2 word, 3-4 sentence, 5-6 line
+
+    fn get_text_at_offset(&self, offset: i32, boundary: u32) -> (String, i32, i32) {
+        match boundary {
+            0 => self.get_string_at_offset(offset, 0),
+            1 | 2 => self.get_string_at_offset(offset, 1),
+            _ => self.get_string_at_offset(offset, 3),
+        }
+    }
+
+    fn get_n_selections(&self) -> i32 {
+        0
+    }
+
+    fn set_caret_offset(&self, _offset: i32) -> bool {
+        false
+    }
+}
+
+pub struct Accessibility {
+    connection: Connection,
+    snapshot: Arc<Mutex<Snapshot>>,
+    mirror: Mirror,
+}
+
+impl Accessibility {
+    pub fn connect() -> Result<Option<Accessibility>, Box<dyn std::error::Error>> {
+        let session = Connection::session()?;
+
+        // the accessibility bus is only worth talking to when an assistive technology asked for it
+
+        let status = Proxy::new(&session, "org.a11y.Bus", "/org/a11y/bus", "org.a11y.Status")?;
+
+        if !status.get_property::<bool>("IsEnabled")? {
+            return Ok(None);
+        }
+
+        let bus = Proxy::new(&session, "org.a11y.Bus", "/org/a11y/bus", "org.a11y.Bus")?;
+        let address: String = bus.call("GetAddress", &())?;
+
+        let connection = connection::Builder::address(address.as_str())?.build()?;
+        let name = connection.unique_name().ok_or("no unique name on the accessibility bus")?.to_string();
+
+        let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+
+        connection.object_server().at(ROOT, Accessible { snapshot: snapshot.clone(), name: name.clone(), root: true })?;
+        connection.object_server().at(ROOT, Application { id: 0 })?;
+        connection.object_server().at(TERMINAL, Accessible { snapshot: snapshot.clone(), name: name.clone(), root: false })?;
+        connection.object_server().at(TERMINAL, Text { snapshot: snapshot.clone() })?;
+
+        connection.call_method(
+            Some(REGISTRY),
+            ROOT,
+            Some("org.a11y.atspi.Socket"),
+            "Embed",
+            &((name.as_str(), OwnedObjectPath::try_from(ROOT)?),),
+        )?;
+
+        Ok(Some(Accessibility {
+            connection,
+            snapshot,
+            mirror: Mirror::default(),
+        }))
+    }
+
+    fn emit(&self, signal: &str, kind: &str, detail1: i32, detail2: i32, data: Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.connection.emit_signal(
+            None::<&str>,
+            TERMINAL,
+            "org.a11y.atspi.Event.Object",
+            signal,
+            &(kind, detail1, detail2, data, HashMap::<&str, Value>::new()),
+        )?;
+
+        Ok(())
+    }
+
+    // the screen moved lines without repainting them, they are moved here as well so draw only reports the rows it redraws
+
+    pub fn scroll(&mut self, top: usize, bottom: usize, count: usize, up: bool) {
+        self.mirror.scroll(top, bottom, count, up);
+    }
+
+    // rows holds the rows draw repainted this frame, the others are taken to be unchanged
+
+    pub fn update(&mut self, height: usize, rows: Vec<(usize, Vec<char>)>, cursor: (usize, usize), focused: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.mirror.resize(height);
+
+        for (y, line) in rows {
+            self.mirror.set(y, line);
+        }
+
+        let caret = self.mirror.caret(cursor);
+        let mut changes = std::mem::take(&mut self.mirror.changes);
+
+        let (moved, focus) = {
+            let mut snapshot = self.snapshot.lock().map_err(|_| "accessibility snapshot poisoned")?;
+
+            if !changes.is_empty() {
+                let text = self.mirror.text();
+
+                if changes.len() > MAX_CHANGES {
+                    changes = vec![
+                        Change { insert: false, offset: 0, text: snapshot.text.iter().collect() },
+                        Change { insert: true, offset: 0, text: text.iter().collect() },
+                    ];
+                }
+
+                snapshot.text = text;
+            }
+
+            let moved = snapshot.caret != caret as i32;
+            let focus = snapshot.focused != focused;
+
+            snapshot.caret = caret as i32;
+            snapshot.focused = focused;
+
+            (moved, focus)
+        };
+
+        if focus {
+            self.emit("StateChanged", "focused", focused as i32, 0, Value::from(0))?;
+        }
+
+        for change in changes.into_iter().filter(|change| !change.text.is_empty()) {
+            let length = change.text.chars().count() as i32;
+
+            self.emit("TextChanged", if change.insert { "insert" } else { "delete" }, change.offset as i32, length, Value::from(change.text))?;
+        }
+
+        if moved {
+            self.emit("TextCaretMoved", "", caret as i32, 0, Value::from(0))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(line: &str) -> Vec<char> {
+        line.chars().collect()
+    }
+
+    // what a screen reader does with the changes it is sent
+
+    fn apply(text: &mut Vec<char>, mirror: &mut Mirror) {
+        for change in mirror.changes.drain(..) {
+            if change.insert {
+                text.splice(change.offset..change.offset, change.text.chars());
+            } else {
+                text.drain(change.offset..change.offset + change.text.chars().count());
+            }
+        }
+
+        assert_eq!(*text, mirror.text());
+    }
+
+    #[test]
+    fn changes() {
+        let mut mirror = Mirror::default();
+        let mut text = Vec::new();
+
+        mirror.resize(3);
+        mirror.set(0, line("a"));
+        mirror.set(1, line("b"));
+        mirror.set(2, line("$ l"));
+
+        apply(&mut text, &mut mirror);
+
+        mirror.set(1, line("b"));
+        mirror.set(2, line("$ ls"));
+
+        assert_eq!(mirror.changes, vec![Change { insert: true, offset: 7, text: String::from("s") }]);
+
+        apply(&mut text, &mut mirror);
+
+        // output scrolls the screen, only the line pushed out and the new row are reported
+
+        mirror.scroll(0, 2, 1, true);
+        mirror.set(2, line("c"));
+
+        assert_eq!(mirror.changes, vec![
+            Change { insert: false, offset: 0, text: String::from("a\n") },
+            Change { insert: true, offset: 6, text: String::from("\n") },
+            Change { insert: true, offset: 7, text: String::from("c") },
+        ]);
+
+        apply(&mut text, &mut mirror);
+
+        assert_eq!(mirror.caret((1, 2)), 8);
+
+        mirror.scroll(1, 2, 1, false);
+
+        apply(&mut text, &mut mirror);
+
+        assert_eq!(text, line("b\n\n$ ls"));
+
+        mirror.resize(2);
+
+        apply(&mut text, &mut mirror);
+    }
+
+    #[test]
+    fn bounds() {
+        let snapshot = Snapshot {
+            text: "foo bar\nbaz".chars().collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(snapshot.line_bounds(5), (0, 7));
+        assert_eq!(snapshot.line_bounds(9), (8, 11));
+        assert_eq!(snapshot.word_bounds(5), (4, 7));
+        assert_eq!(snapshot.word_bounds(0), (0, 3));
+    }
+}
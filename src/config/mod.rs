@@ -1,10 +1,32 @@
+use crate::keybind::{self, Keybind};
 use crate::xlib;
+use crate::terminal::Frontend;
 
 use toml::Table;
+use regex::Regex;
 
 use std::env;
 use std::fs;
 
+const HIGH_CONTRAST: [&str; 16] = [
+    "00-00-00", // black
+    "ff-55-55", // red
+    "55-ff-55", // green
+    "ff-ff-55", // brown
+    "55-aa-ff", // blue
+    "ff-55-ff", // magneta
+    "55-ff-ff", // cyan
+    "ff-ff-ff", // white
+    "aa-aa-aa", // bright black
+    "ff-88-88", // bright red
+    "88-ff-88", // bright green
+    "ff-ff-88", // bright brown
+    "88-cc-ff", // bright blue
+    "ff-88-ff", // bright magneta
+    "88-ff-ff", // bright cyan
+    "ff-ff-ff", // bright white
+];
+
 #[derive(Clone, Copy)]
 pub struct UniColor {
     pub raw: xlib::Color,
@@ -21,19 +43,80 @@ impl PartialEq for UniColor {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum Urgency {
+    None,
+    Hints,
+    DemandsAttention,
+}
+
+// legacy follows the wcwidth of systems from before unicode 9, where most emoji were still narrow
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WidthTable {
+    Current,
+    Legacy,
+}
+
+// how modified keys are reported once an application enables modifyOtherKeys
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum KeyFormat {
+    Xterm,
+    CsiU,
+}
+
+#[derive(Clone, Copy)]
+pub struct PasteTransform {
+    pub strip_newline: bool,
+    pub crlf: bool,
+    pub dedent: bool,
+}
+
 pub struct Config {
     pub colors: Vec<UniColor>,
-    pub tab_max: usize,
+    pub keybinds: Vec<Keybind>,
+    pub tab_interval: usize,
+    pub scrollback: usize,
+    pub alternate_scroll_lines: usize,
+    pub notifications: bool,
+    pub notification_interval: u64,
+    pub min_font_size: f64,
+    pub render_scale: u32,
+    pub high_contrast: bool,
+    pub blink: bool,
+    pub blink_timeout: u64,
+    pub idle_timeout: u64,
+    pub frame_interval: u64,
+    pub layout_indicator: bool,
+    pub layout_hook: String,
+    pub print_command: String,
+    pub title: String,
+    pub search_url: String,
+    pub prompt_pattern: Option<Regex>,
+    pub command_history: bool,
+    pub ipc: bool,
+    pub width_table: WidthTable,
+    pub ambiguous_wide: bool,
+    pub urgency: Urgency,
+    pub key_format: KeyFormat,
+    pub alt_sends_escape: bool,
+    pub paste: PasteTransform,
+    pub selection_limit: usize,
     pub font: String,
     pub bell: String,
+    pub bell_frequency: u32,
+    pub bell_duration: u64,
     pub fg: UniColor,
     pub bg: UniColor,
 }
 
 impl Config {
-    pub fn load(display: &xlib::Display) -> Result<Config, Box<dyn std::error::Error>> {
-        let home = env::var("HOME")?;
+    pub fn load(display: &dyn Frontend) -> Result<Config, Box<dyn std::error::Error>> {
+        Self::from_table(display, &read_table(&env::var("HOME")?)?)
+    }
 
+    pub fn from_table(display: &dyn Frontend, config: &Table) -> Result<Config, Box<dyn std::error::Error>> {
         let colors = vec![
             "28-28-28", // black
             "cc-24-1d", // red
@@ -43,46 +126,119 @@ impl Config {
             "b1-62-86", // magneta
             "83-a5-98", // cyan
             "eb-db-b2", // white
+            "92-83-74", // bright black
+            "fb-49-34", // bright red
+            "b8-bb-26", // bright green
+            "fa-bd-2f", // bright brown
+            "83-a5-98", // bright blue
+            "d3-86-9b", // bright magneta
+            "8e-c0-7c", // bright cyan
+            "fb-f1-c7", // bright white
         ];
 
-        if let Ok(content) = fs::read_to_string(format!("{}/.config/termal/config.toml", home)) {
-            let config = content.parse::<Table>()?;
-            let fg = xlib::Color::from_str(&Self::get_str(&config, "foreground", "d7-e0-da"))?;
-            let bg = xlib::Color::from_str(&Self::get_str(&config, "background", "0d-16-17"))?;
-
-            Ok(Config {
-                colors: Self::load_colors(display, Self::get_colors(&config, colors)?.iter().map(|x| x.as_str()).collect::<Vec<&str>>())?,
-                tab_max: Self::get_int(&config, "tab_max", 400),
-                font: Self::get_str(&config, "font", "Iosevka Nerd Font Mono:style=Regular"),
-                bell: Self::get_str(&config, "bell", "assets/pluh.wav"),
-                fg: UniColor {
-                    raw: fg,
-                    xft: display.xft_color_alloc_value(fg)?,
-                },
-                bg: UniColor {
-                    raw: bg,
-                    xft: display.xft_color_alloc_value(bg)?,
-                },
-            })
+        let high_contrast = Self::get_bool(config, "high_contrast", false);
+
+        // high contrast mode ignores the configured colors, black and white with saturated colors reads best
+
+        let (fg, bg, colors) = if high_contrast {
+            (xlib::Color::new(0xff, 0xff, 0xff), xlib::Color::new(0, 0, 0), HIGH_CONTRAST.iter().map(|x| x.to_string()).collect::<Vec<String>>())
         } else {
-            Ok(Config {
-                colors: Self::load_colors(display, colors)?,
-                tab_max: 400,
-                font: String::from("Iosevka Nerd Font Mono:style=Regular"),
-                bell: String::from("assets/pluh.wav"),
-                fg: UniColor {
-                    raw: xlib::Color::from_str("d7-e0-da")?,
-                    xft: display.xft_color_alloc_value(xlib::Color::from_str("d7-e0-da")?)?,
-                },
-                bg: UniColor {
-                    raw: xlib::Color::from_str("0d-16-17")?,
-                    xft: display.xft_color_alloc_value(xlib::Color::from_str("0d-16-17")?)?,
-                },
+            (
+                xlib::Color::from_str(&Self::get_str(config, "foreground", "d7-e0-da"))?,
+                xlib::Color::from_str(&Self::get_str(config, "background", "0d-16-17"))?,
+                Self::get_colors(config, colors)?,
+            )
+        };
+
+        let min_font_size = Self::get_float(config, "min_font_size", 0.0);
+
+        let font = Self::get_str(config, "font", "Iosevka Nerd Font Mono:style=Regular");
+
+        Ok(Config {
+            colors: Self::load_colors(display, colors.iter().map(|x| x.as_str()).collect::<Vec<&str>>())?,
+            keybinds: keybind::load(config.get("keybinds").and_then(|x| x.as_table()))?,
+            tab_interval: Self::get_int(config, "tab_interval", 8).max(1),
+            scrollback: Self::get_int(config, "scrollback", 400),
+            alternate_scroll_lines: Self::get_int(config, "alternate_scroll_lines", 3),
+            notifications: Self::get_bool(config, "notifications", true),
+            notification_interval: Self::get_int(config, "notification_interval", 2000) as u64,
+            min_font_size,
+            render_scale: Self::get_int(config, "render_scale", 1).max(1) as u32,
+            high_contrast,
+            blink: Self::get_bool(config, "blink", true),
+            blink_timeout: Self::get_int(config, "blink_timeout", 15) as u64,
+            idle_timeout: Self::get_int(config, "idle_timeout", 0) as u64,
+            frame_interval: Self::get_int(config, "frame_interval", 8) as u64,
+            layout_indicator: Self::get_bool(config, "layout_indicator", false),
+            layout_hook: Self::get_str(config, "layout_hook", ""),
+            print_command: Self::get_str(config, "print_command", ""),
+            title: Self::get_str(config, "title", "termal"),
+            search_url: Self::get_str(config, "search_url", "https://duckduckgo.com/?q={}"),
+            prompt_pattern: match Self::get_str(config, "prompt_pattern", "").as_str() {
+                "" => None,
+                pattern => Some(Regex::new(pattern)?),
+            },
+            command_history: Self::get_bool(config, "command_history", false),
+            ipc: Self::get_bool(config, "ipc", false),
+            width_table: match Self::get_str(config, "width_table", "current").as_str() {
+                "current" => WidthTable::Current,
+                "legacy" => WidthTable::Legacy,
+                table => return Err(format!("unknown width table: {}", table).into()),
+            },
+            ambiguous_wide: match Self::get_str(config, "ambiguous_width", "narrow").as_str() {
+                "narrow" => false,
+                "wide" => true,
+                width => return Err(format!("unknown ambiguous width: {}", width).into()),
+            },
+            urgency: match Self::get_str(config, "urgency", "hints").as_str() {
+                "none" => Urgency::None,
+                "hints" => Urgency::Hints,
+                "demands_attention" => Urgency::DemandsAttention,
+                urgency => return Err(format!("unknown urgency: {}", urgency).into()),
+            },
+            key_format: match Self::get_str(config, "key_format", "xterm").as_str() {
+                "xterm" => KeyFormat::Xterm,
+                "csi_u" => KeyFormat::CsiU,
+                format => return Err(format!("unknown key format: {}", format).into()),
+            },
+            alt_sends_escape: Self::get_bool(config, "alt_sends_escape", true),
+            paste: PasteTransform {
+                strip_newline: Self::get_bool(config, "paste_strip_newline", false),
+                crlf: Self::get_bool(config, "paste_crlf", false),
+                dedent: Self::get_bool(config, "paste_dedent", false),
+            },
+            selection_limit: Self::get_int(config, "selection_limit", 16 * 1024 * 1024),
+            font: if font_size(&font) < min_font_size { font_with_size(&font, min_font_size) } else { font },
+            bell: Self::get_str(config, "bell", "assets/pluh.wav"),
+            bell_frequency: Self::get_int(config, "bell_frequency", 880).clamp(20, 20000) as u32,
+            bell_duration: Self::get_int(config, "bell_duration", 150) as u64,
+            fg: UniColor {
+                raw: fg,
+                xft: display.xft_color_alloc_value(fg)?,
+            },
+            bg: UniColor {
+                raw: bg,
+                xft: display.xft_color_alloc_value(bg)?,
+            },
+        })
+    }
+
+    // COLORFGBG as rxvt sets it, vim and others read the background index to tell dark themes from light ones.
+    // a color outside the palette passes for black or white by its brightness
+
+    pub fn colorfgbg(&self) -> String {
+        let index = |color: &UniColor| {
+            self.colors.iter().take(16).position(|other| other == color).unwrap_or_else(|| {
+                let (r, g, b) = color.raw.rgb();
+
+                if r * 299 + g * 587 + b * 114 > 127_500 { 15 } else { 0 }
             })
-        }
+        };
+
+        format!("{};{}", index(&self.fg), index(&self.bg))
     }
 
-    fn load_colors(display: &xlib::Display, colors: Vec<&str>) -> Result<Vec<UniColor>, Box<dyn std::error::Error>> {
+    fn load_colors(display: &dyn Frontend, colors: Vec<&str>) -> Result<Vec<UniColor>, Box<dyn std::error::Error>> {
         let mut unicolors: Vec<UniColor> = Vec::new();
 
         for color in colors {
@@ -96,7 +252,17 @@ impl Config {
 
     fn get_colors(table: &toml::map::Map<String, toml::Value>, default: Vec<&str>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
         if let Some(colors) = table.get("colors") {
-            Ok(colors.as_array().unwrap_or(&Vec::new()).iter().map(|x| x.as_str().unwrap_or_default().to_string()).collect::<Vec<String>>())
+            let mut colors = colors.as_array().unwrap_or(&Vec::new()).iter().map(|x| x.as_str().unwrap_or_default().to_string()).collect::<Vec<String>>();
+
+            colors.extend(default[colors.len().min(8)..8].iter().map(|x| x.to_string()));
+
+            // palettes with only the 8 normal colors use them for the bright colors as well
+
+            while colors.len() < 16 {
+                colors.push(colors[colors.len() - 8].clone());
+            }
+
+            Ok(colors)
         } else {
             Ok(default.iter().map(|x| x.to_string()).collect::<Vec<String>>())
         }
@@ -106,9 +272,82 @@ impl Config {
         table.get(key).map_or(default, |x| x.as_str().unwrap_or(default)).to_string()
     }
 
+    fn get_bool(config: &toml::map::Map<String, toml::Value>, key: &str, default: bool) -> bool {
+        config.get(key).map_or(default, |x| x.as_bool().unwrap_or(default))
+    }
+
+    fn get_float(config: &toml::map::Map<String, toml::Value>, key: &str, default: f64) -> f64 {
+        config.get(key).map_or(default, |x| x.as_float().or(x.as_integer().map(|x| x as f64)).unwrap_or(default))
+    }
+
     fn get_int(config: &toml::map::Map<String, toml::Value>, key: &str, default: usize) -> usize {
-        config.get(key).map_or(default, |x| x.as_integer().unwrap_or_default() as usize)
+        config.get(key).map_or(default, |x| match x.as_integer() {
+            Some(x) if x < 0 => {
+                println!("[+] {} can't be negative, using {}", key, default);
+
+                default
+            },
+            x => x.unwrap_or_default() as usize,
+        })
     }
 }
 
+fn read_table(home: &str) -> Result<Table, Box<dyn std::error::Error>> {
+    match fs::read_to_string(format!("{}/.config/termal/config.toml", home)) {
+        Ok(content) => Ok(content.parse::<Table>()?),
+        Err(_) => Ok(Table::new()),
+    }
+}
+
+// options needed before the display is open, these are read ahead of the rest of the config
+
+pub struct Startup {
+    pub follow_pointer: bool,
+    pub single_instance: bool,
+}
+
+pub fn startup() -> Result<Startup, Box<dyn std::error::Error>> {
+    let config = read_table(&env::var("HOME")?)?;
 
+    Ok(Startup {
+        follow_pointer: match Config::get_str(&config, "screen", "pointer").as_str() {
+            "pointer" => true,
+            "default" => false,
+            screen => return Err(format!("unknown screen: {}", screen).into()),
+        },
+        single_instance: Config::get_bool(&config, "single_instance", false),
+    })
+}
+
+// xft font names are fontconfig patterns, eg. "Iosevka:style=Regular:size=12"
+
+pub fn font_size(font: &str) -> f64 {
+    font.split(':')
+        .find_map(|element| element.strip_prefix("size="))
+        .and_then(|size| size.parse::<f64>().ok())
+        .unwrap_or(12.0)
+}
+
+pub fn font_with_size(font: &str, size: f64) -> String {
+    let mut elements = font.split(':')
+        .filter(|element| !element.starts_with("size=") && !element.starts_with("pixelsize="))
+        .map(|element| element.to_string())
+        .collect::<Vec<String>>();
+
+    elements.push(format!("size={}", size));
+
+    elements.join(":")
+}
+
+pub fn font_with_style(font: &str, style: &str) -> String {
+    // an explicit style=Regular would win over the weight and slant we ask for
+
+    let mut elements = font.split(':')
+        .filter(|element| !element.starts_with("style="))
+        .map(|element| element.to_string())
+        .collect::<Vec<String>>();
+
+    elements.push(style.to_string());
+
+    elements.join(":")
+}
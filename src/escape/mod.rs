@@ -5,15 +5,19 @@ use utf8::*;
 
 const MAX_INTERMEDIATES: usize = 2;
 const MAX_CSI: usize = 128;
+// osc strings grow as needed since inline images take up megabytes, the next sequence shrinks the buffer back
+const MAX_OSC: usize = 16 * 1024 * 1024;
+const OSC_CAPACITY: usize = 1024;
 
 
 #[derive(Debug)]
 pub enum Action<'a> {
     Print(char),
     Execute(u8),
-    CsiDispatch(&'a [u16], &'a [u8], char),
+    CsiDispatch(Option<char>, &'a [u16], u128, &'a [u8], char),
     EscDispatch(&'a [u8], u8),
     OscDispatch(&'a [u8]),
+    DcsDispatch(&'a [u8]),
 }
 
 #[derive(Debug)]
@@ -23,17 +27,23 @@ pub enum State {
     CsiParams,
     EscParams,
     OscParams,
+    OscEscape,
+    DcsParams,
+    DcsEscape,
 }
 
 pub struct Params {
+    prefix: Option<char>,
     csi: [u16; MAX_CSI],
-    osc: [u8; 1024],
+    subparams: u128,
+    osc: Vec<u8>,
     index: usize,
 }
 
 pub struct Intermediates {
     buf: [u8; MAX_INTERMEDIATES],
     index: usize,
+    overflowed: bool,
 }
 
 impl Intermediates {
@@ -48,7 +58,7 @@ impl Intermediates {
             self.index = 0;
 
             return Ok(Some(action));
-        } else if byte >= 0x20 && byte <= 0x2f {
+        } else if byte >= 0x20 && byte <= 0x2f && self.index < MAX_INTERMEDIATES {
             self.buf[self.index] = byte;
 
             self.index += 1;
@@ -70,29 +80,81 @@ impl<'a> Parser {
         Parser {
             state: State::Anywhere,
             params: Params {
+                prefix: None,
                 csi: [0; MAX_CSI],
-                osc: [0; 1024],
+                subparams: 0,
+                osc: Vec::with_capacity(OSC_CAPACITY),
                 index: 0,
             },
             intermediates: Intermediates {
                 buf: [0; MAX_INTERMEDIATES],
                 index: 0,
+                overflowed: false,
             },
             utf8: Utf8::new(),
         }
     }
 
+    fn reset(&mut self) {
+        self.intermediates.index = 0;
+        self.intermediates.overflowed = false;
+        self.params.index = 0;
+
+        self.intermediates.buf = [0; MAX_INTERMEDIATES];
+        self.params.csi = [0; MAX_CSI];
+        self.params.subparams = 0;
+        self.params.prefix = None;
+
+        self.params.osc.clear();
+        self.params.osc.shrink_to(OSC_CAPACITY);
+
+        self.state = State::Entry;
+    }
+
     pub fn advance(&'a mut self, byte: u8) -> Result<Option<Action>, Box<dyn std::error::Error>> {
-        match byte {
-            0x1b => {
-                self.intermediates.index = 0;
-                self.params.index = 0;
+        if let State::OscEscape = self.state {
+            if byte as char == '\\' {
+                self.state = State::Anywhere;
+
+                return Ok(Some(Action::OscDispatch(&self.params.osc)));
+            }
+
+            // the osc was cut off by another escape sequence, this byte belongs to it
+
+            self.reset();
+        }
+
+        // a dcs is terminated the same way, its body is handed over raw and left to the screen to parse
+
+        if let State::DcsEscape = self.state {
+            if byte as char == '\\' {
+                self.state = State::Anywhere;
+
+                return Ok(Some(Action::DcsDispatch(&self.params.osc)));
+            }
 
-                self.intermediates.buf = [0; MAX_INTERMEDIATES];
-                self.params.csi = [0; MAX_CSI];
+            // tmux passes sequences through wrapped in "DCS tmux; ... ST" with every escape inside doubled
+
+            if byte == 0x1b && self.params.osc.starts_with(b"tmux;") {
+                self.params.osc.push(byte);
+                self.state = State::DcsParams;
+
+                return Ok(None);
+            }
+
+            self.reset();
+        }
 
-                self.state = State::Entry;
+        match byte {
+            0x1b if matches!(self.state, State::OscParams) => {
+                // the osc may be terminated by ST (ESC \\)
+
+                self.state = State::OscEscape;
             },
+            0x1b if matches!(self.state, State::DcsParams) => self.state = State::DcsEscape,
+            0x1b => self.reset(),
+            // https://vt100.net/emu/dec_ansi_parser, DEL is ignored everywhere, it used to be sent as padding
+            0x7f => {},
             _ => {
                 match self.state {
                     State::Anywhere => {
@@ -116,6 +178,8 @@ impl<'a> Parser {
                             self.state = State::CsiParams;
                         } else if byte as char == ']' {
                             self.state = State::OscParams;
+                        } else if byte as char == 'P' {
+                            self.state = State::DcsParams;
                         } else {
                             if let Ok(Some(action)) = self.intermediates.esc_param(byte, &mut self.state) {
                                 return Ok(Some(action));
@@ -127,9 +191,13 @@ impl<'a> Parser {
                     State::CsiParams => {
                         // https://www.gnu.org/software/teseq/manual/html_node/Escape-Sequence-Recognition.html
 
-                        if byte >= 0x40 && byte < 0x7e {
+                        if byte >= 0x40 && byte <= 0x7e && self.intermediates.overflowed {
+                            self.state = State::Anywhere;
+                        } else if byte >= 0x40 && byte <= 0x7e {
                             let action = Action::CsiDispatch(
+                                self.params.prefix,
                                 &self.params.csi[..=self.params.index],
+                                self.params.subparams,
                                 &self.intermediates.buf[..self.intermediates.index],
                                 byte as char
                             );
@@ -137,21 +205,41 @@ impl<'a> Parser {
                             self.state = State::Anywhere;
 
                             return Ok(Some(action));
-                        } else if byte >= 0x30 && byte < 0x3f {
-                            if byte as char == ';' || byte as char == ':' {
+                        } else if byte >= 0x30 && byte < 0x3c {
+                            if (byte as char == ';' || byte as char == ':') && self.params.index + 1 < MAX_CSI {
                                 self.params.index += 1;
-                            } else {
+
+                                // bit n is set when param n is a sub-parameter of the one before it, eg. the 3 in 4:3
+
+                                if byte as char == ':' {
+                                    self.params.subparams |= 1 << self.params.index;
+                                }
+                            } else if byte as char != ';' && byte as char != ':' {
                                 if self.params.csi[self.params.index] != 0 {
                                     self.params.csi[self.params.index] = ((self.params.csi[self.params.index] as usize * 10) + byte as usize - 0x30).min(u16::MAX as usize) as u16;
                                 } else {
                                     self.params.csi[self.params.index] = byte as u16 - 0x30;
                                 }
                             }
-                        } else if byte >= 0x20 && byte < 0x2f && self.intermediates.index <= MAX_INTERMEDIATES {
+                        } else if byte >= 0x3c && byte <= 0x3f {
+                            // private markers select another table, "CSI ? 4 h" is DECSCLM where "CSI 4 h" is IRM and "CSI > c" asks for DA2
+
+                            self.params.prefix = Some(byte as char);
+                        } else if byte >= 0x20 && byte <= 0x2f && self.intermediates.index < MAX_INTERMEDIATES {
                             self.intermediates.buf[self.intermediates.index] = byte;
 
                             self.intermediates.index += 1;
-                        } else if byte < 0x0f {
+                        } else if byte >= 0x20 && byte <= 0x2f {
+                            // https://vt100.net/emu/dec_ansi_parser, a sequence with more intermediates than fit is ignored up to its final byte
+
+                            self.intermediates.overflowed = true;
+                        } else if byte == 0x18 || byte == 0x1a {
+                            // CAN and SUB cancel the sequence, the other controls are executed while it stays open
+
+                            self.state = State::Anywhere;
+
+                            return Ok(Some(Action::Execute(byte)));
+                        } else if byte < 0x20 {
                             return Ok(Some(Action::Execute(byte)));
                         }
                     },
@@ -160,17 +248,27 @@ impl<'a> Parser {
                     },
                     State::OscParams => {
                         if byte == 0x07 || byte == 0x9c {
-                            let action = Action::OscDispatch(&self.params.osc[..self.params.index]);
+                            let action = Action::OscDispatch(&self.params.osc);
 
                             self.state = State::Anywhere;
 
                             return Ok(Some(action));
-                        } else {
-                            self.params.osc[self.params.index] = byte;
+                        } else if self.params.osc.len() < MAX_OSC {
+                            self.params.osc.push(byte);
+                        }
+                    },
+                    State::DcsParams => {
+                        if byte == 0x9c {
+                            let action = Action::DcsDispatch(&self.params.osc);
+
+                            self.state = State::Anywhere;
 
-                            self.params.index += 1;
+                            return Ok(Some(action));
+                        } else if self.params.osc.len() < MAX_OSC {
+                            self.params.osc.push(byte);
                         }
                     },
+                    State::OscEscape | State::DcsEscape => {},
                 }
             },
         }
@@ -243,6 +341,217 @@ mod tests {
         }
     }
 
+    #[test]
+    fn osc() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<Vec<u8>> = Vec::new();
+
+        for byte in b"\x1b]133;A\x07\x1b]133;B\x1b\\\x1b]2;title\x1b[1m" {
+            if let Some(Action::OscDispatch(data)) = parser.advance(*byte)? {
+                dispatched.push(data.to_vec());
+            }
+        }
+
+        assert_eq!(dispatched, vec![b"133;A".to_vec(), b"133;B".to_vec()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn long_osc() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut bytes = b"\x1b]1337;File=inline=1:".to_vec();
+
+        bytes.extend(std::iter::repeat_n(b'A', 100_000));
+        bytes.extend(b"\x07\x1b]2;x\x07");
+
+        let mut lengths = Vec::new();
+
+        for byte in bytes {
+            if let Some(Action::OscDispatch(data)) = parser.advance(byte)? {
+                lengths.push(data.len());
+            }
+        }
+
+        assert_eq!(lengths, vec![100_000 + 19, 3]);
+        assert_eq!(parser.params.osc.capacity(), OSC_CAPACITY);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dcs() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<Vec<u8>> = Vec::new();
+        let mut printed = String::new();
+
+        for byte in b"\x1bP$qm\x1b\\\x1bP+q544e\x1b\\a" {
+            match parser.advance(*byte)? {
+                Some(Action::DcsDispatch(data)) => dispatched.push(data.to_vec()),
+                Some(Action::Print(c)) => printed.push(c),
+                _ => {},
+            }
+        }
+
+        assert_eq!(dispatched, vec![b"$qm".to_vec(), b"+q544e".to_vec()]);
+        assert_eq!(printed, "a");
+
+        Ok(())
+    }
+
+    #[test]
+    fn tmux_passthrough() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<Vec<u8>> = Vec::new();
+
+        for byte in b"\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\\\x1bPtmux;\x1b\x1bP$qm\x1b\x1b\\\x1b\\\x1bPq\x1b\x1b\\" {
+            if let Some(Action::DcsDispatch(data)) = parser.advance(*byte)? {
+                dispatched.push(data.to_vec());
+            }
+        }
+
+        // only tmux doubles its escapes, anywhere else the second escape starts a new sequence
+
+        assert_eq!(dispatched, vec![b"tmux;\x1b]52;c;aGk=\x07".to_vec(), b"tmux;\x1bP$qm\x1b\\".to_vec()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn column_editing() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<(Vec<u16>, Vec<u8>, char)> = Vec::new();
+
+        for byte in b"\x1b[2'}\x1b[3'~" {
+            if let Some(Action::CsiDispatch(_, params, _, intermediates, c)) = parser.advance(*byte)? {
+                dispatched.push((params.to_vec(), intermediates.to_vec(), c));
+            }
+        }
+
+        assert_eq!(dispatched, vec![(vec![2], b"'".to_vec(), '}'), (vec![3], b"'".to_vec(), '~')]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn final_bytes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<char> = Vec::new();
+
+        for c in 0x40..=0x7e_u8 {
+            for byte in [0x1b, b'[', b'2', 0x7f, b';', b'3', c] {
+                if let Some(Action::CsiDispatch(_, params, _, _, c)) = parser.advance(byte)? {
+                    assert_eq!(params, &[2, 3]);
+
+                    dispatched.push(c);
+                }
+            }
+        }
+
+        assert_eq!(dispatched, (0x40..=0x7e_u8).map(char::from).collect::<Vec<char>>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<Vec<u8>> = Vec::new();
+        let mut printed = String::new();
+
+        for byte in b"a\x7f\x1b]2;ti\x7ftle\x07\x1bP$\x7fqm\x1b\\\x1b[1 /!p\x1b#/!8b" {
+            match parser.advance(*byte)? {
+                Some(Action::OscDispatch(data)) | Some(Action::DcsDispatch(data)) => dispatched.push(data.to_vec()),
+                Some(Action::CsiDispatch(_, _, _, intermediates, c)) => dispatched.push([intermediates, &[c as u8]].concat()),
+                Some(Action::EscDispatch(intermediates, c)) => dispatched.push([intermediates, &[c]].concat()),
+                Some(Action::Print(c)) => printed.push(c),
+                _ => {},
+            }
+        }
+
+        // a csi with more intermediates than fit is ignored, an escape drops the ones past the second
+
+        assert_eq!(dispatched, vec![b"2;title".to_vec(), b"$qm".to_vec(), b"#/8".to_vec()]);
+        assert_eq!(printed, "ab");
+
+        Ok(())
+    }
+
+    #[test]
+    fn cancel() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<char> = Vec::new();
+        let mut printed = String::new();
+
+        for byte in b"\x1b[1\x18m\x1b[2\x1am\x1b[3\x08m" {
+            match parser.advance(*byte)? {
+                Some(Action::CsiDispatch(_, _, _, _, c)) => dispatched.push(c),
+                Some(Action::Print(c)) => printed.push(c),
+                _ => {},
+            }
+        }
+
+        // backspace is executed inside the sequence, CAN and SUB end it and the final byte is printed
+
+        assert_eq!(dispatched, vec!['m']);
+        assert_eq!(printed, "mm");
+
+        Ok(())
+    }
+
+    #[test]
+    fn subparams() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<(Vec<u16>, u128)> = Vec::new();
+
+        for byte in b"\x1b[4:3;58:2::1:2:3m\x1b[4;3m" {
+            if let Some(Action::CsiDispatch(_, params, subparams, _, _)) = parser.advance(*byte)? {
+                dispatched.push((params.to_vec(), subparams));
+            }
+        }
+
+        assert_eq!(dispatched, vec![(vec![4, 3, 58, 2, 0, 1, 2, 3], 0b11111010), (vec![4, 3], 0)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn param_limit() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<(Vec<u16>, u128)> = Vec::new();
+
+        for byte in format!("\x1b[{}38m", "0;".repeat(MAX_CSI - 1)).as_bytes() {
+            if let Some(Action::CsiDispatch(_, params, subparams, _, _)) = parser.advance(*byte)? {
+                dispatched.push((params.to_vec(), subparams));
+            }
+        }
+
+        // the color lands in the last slot, the terminal must not look past it for the color arguments
+
+        assert_eq!(dispatched.len(), 1);
+        assert_eq!(dispatched[0].0.len(), MAX_CSI);
+        assert_eq!(dispatched[0].0.last(), Some(&38));
+        assert_eq!(dispatched[0].1.checked_shr(MAX_CSI as u32).unwrap_or(0), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn private_marker() -> Result<(), Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let mut dispatched: Vec<(Option<char>, Vec<u16>, char)> = Vec::new();
+
+        for byte in b"\x1b[4h\x1b[?4;1049l\x1b[?2004$p\x1b[h\x1b[>4;1m\x1b[=c" {
+            if let Some(Action::CsiDispatch(prefix, params, _, _, c)) = parser.advance(*byte)? {
+                dispatched.push((prefix, params.to_vec(), c));
+            }
+        }
+
+        assert_eq!(dispatched, vec![(None, vec![4], 'h'), (Some('?'), vec![4, 1049], 'l'), (Some('?'), vec![2004], 'p'), (None, vec![0], 'h'), (Some('>'), vec![4, 1], 'm'), (Some('='), vec![0], 'c')]);
+
+        Ok(())
+    }
+
     #[test]
     fn escape() {
         let mut parser = Parser::new();
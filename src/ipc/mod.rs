@@ -0,0 +1,261 @@
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::fs::PermissionsExt;
+use std::os::fd::{AsRawFd, RawFd};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+use std::path::PathBuf;
+use std::thread;
+use std::fs;
+use std::env;
+use std::process;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extent {
+    Screen,
+    Scrollback,
+    Selection,
+    LastCommand,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    GetText {
+        extent: Extent,
+        ansi: bool,
+    },
+    GetUserVar {
+        name: Option<String>,
+    },
+    Activate,
+}
+
+// a request read off a connection, the connection waits on the serving thread until the main loop replies
+
+pub struct Pending {
+    pub request: Result<Request, String>,
+    reply: Sender<Result<String, String>>,
+}
+
+impl Pending {
+    pub fn reply(self, reply: Result<String, String>) {
+        let _ = self.reply.send(reply);
+    }
+}
+
+// one request per connection, a single line like "get-text --extent=scrollback --ansi". the reply is written back and the connection closed.
+// connections are served on their own thread so a client that never sends or never reads can't hold up the terminal, a byte on the
+// wake socket tells poll in the main loop that a request is waiting
+
+pub struct Ipc {
+    pub instance: bool,
+    pending: Receiver<Pending>,
+    wake: UnixStream,
+    path: PathBuf,
+}
+
+impl Drop for Ipc {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Ipc {
+    pub fn bind(instance: bool) -> Result<Ipc, Box<dyn std::error::Error>> {
+        let path = if instance { instance_path() } else { path() };
+
+        if instance && UnixStream::connect(&path).is_ok() {
+            return Err("another window holds the instance socket".into());
+        }
+
+        let _ = fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path)?;
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+        let (wake, notify) = UnixStream::pair()?;
+
+        wake.set_nonblocking(true)?;
+
+        let (sender, pending) = mpsc::channel();
+
+        thread::Builder::new().name(String::from("ipc")).spawn(move || serve(listener, sender, notify))?;
+
+        println!("[+] listening for ipc on {}", path.display());
+
+        Ok(Ipc {
+            instance,
+            pending,
+            wake,
+            path,
+        })
+    }
+
+    pub fn accept(&self) -> Option<Pending> {
+        let mut drained = [0; 64];
+
+        // the wake socket is emptied before the channel, a request sent after this still leaves its byte behind
+
+        while (&self.wake).read(&mut drained).is_ok_and(|bytes| bytes > 0) {}
+
+        self.pending.try_recv().ok()
+    }
+}
+
+impl AsRawFd for Ipc {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wake.as_raw_fd()
+    }
+}
+
+fn serve(listener: UnixListener, pending: Sender<Pending>, mut notify: UnixStream) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+
+        let mut line = String::new();
+
+        let request = stream.set_read_timeout(Some(READ_TIMEOUT))
+            .and_then(|_| BufReader::new(&stream).read_line(&mut line))
+            .map_err(|err| err.to_string())
+            .and_then(|_| parse(line.trim()));
+
+        let (reply, replied) = mpsc::channel();
+
+        if pending.send(Pending { request, reply }).is_err() {
+            return;
+        }
+
+        let _ = notify.write(&[1]);
+
+        if let Ok(reply) = replied.recv() {
+            write_reply(stream, reply);
+        }
+    }
+}
+
+// a client that stops reading is given up on after WRITE_TIMEOUT, the next connection waits at most that long
+
+fn write_reply(mut stream: UnixStream, reply: Result<String, String>) {
+    let content = match reply {
+        Ok(content) => content,
+        Err(err) => format!("error: {}\n", err),
+    };
+
+    if let Err(err) = stream.set_write_timeout(Some(WRITE_TIMEOUT)).and_then(|_| stream.write_all(content.as_bytes())) {
+        println!("[+] failed to answer ipc request: {}", err);
+    }
+}
+
+pub fn parse(line: &str) -> Result<Request, String> {
+    let mut words = line.split_whitespace();
+
+    match words.next() {
+        Some("get-text") => {
+            let mut extent = Extent::Screen;
+            let mut ansi = false;
+
+            for word in words {
+                match word {
+                    "--ansi" => ansi = true,
+                    "--extent=screen" => extent = Extent::Screen,
+                    "--extent=scrollback" => extent = Extent::Scrollback,
+                    "--extent=selection" => extent = Extent::Selection,
+                    "--extent=last_command" => extent = Extent::LastCommand,
+                    option => return Err(format!("unknown option: {}", option)),
+                }
+            }
+
+            Ok(Request::GetText { extent, ansi })
+        },
+        Some("get-user-var") => {
+            let name = words.next().map(String::from);
+
+            match words.next() {
+                Some(word) => Err(format!("unexpected argument: {}", word)),
+                None => Ok(Request::GetUserVar { name }),
+            }
+        },
+        Some("activate") => Ok(Request::Activate),
+        Some(command) => Err(format!("unknown command: {}", command)),
+        None => Err(String::from("empty request")),
+    }
+}
+
+// the shell finds the socket through $TERMAL_SOCKET, it only exists while ipc is enabled
+
+pub fn path() -> PathBuf {
+    runtime_dir().join(format!("termal-{}.sock", process::id()))
+}
+
+// the socket launches in single instance mode activate, the first window keeps it until it closes
+
+pub fn instance_path() -> PathBuf {
+    runtime_dir().join("termal.sock")
+}
+
+fn runtime_dir() -> PathBuf {
+    env::var("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(|_| env::temp_dir())
+}
+
+// true when a running window took the request, a stale socket from a crashed window just fails to connect
+
+pub fn activate_instance() -> bool {
+    let Ok(mut stream) = UnixStream::connect(instance_path()) else { return false };
+
+    let mut reply = String::new();
+
+    stream.set_read_timeout(Some(READ_TIMEOUT)).is_ok()
+        && stream.write_all(b"activate\n").is_ok()
+        && BufReader::new(&stream).read_line(&mut reply).is_ok()
+        && !reply.starts_with("error")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests() {
+        assert_eq!(parse("get-text"), Ok(Request::GetText { extent: Extent::Screen, ansi: false }));
+        assert_eq!(parse("get-text --ansi --extent=last_command"), Ok(Request::GetText { extent: Extent::LastCommand, ansi: true }));
+        assert_eq!(parse("get-text --extent=everything"), Err(String::from("unknown option: --extent=everything")));
+        assert_eq!(parse("get-user-var"), Ok(Request::GetUserVar { name: None }));
+        assert_eq!(parse("get-user-var branch"), Ok(Request::GetUserVar { name: Some(String::from("branch")) }));
+        assert_eq!(parse("get-user-var branch host"), Err(String::from("unexpected argument: host")));
+        assert_eq!(parse("activate"), Ok(Request::Activate));
+        assert_eq!(parse("set-text"), Err(String::from("unknown command: set-text")));
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn serving() {
+        let ipc = Ipc::bind(false).unwrap();
+
+        let mut client = UnixStream::connect(path()).unwrap();
+
+        client.write_all(b"activate\n").unwrap();
+
+        let pending = (0..500).find_map(|_| ipc.accept().or_else(|| {
+            thread::sleep(Duration::from_millis(10));
+
+            None
+        }));
+
+        let pending = pending.unwrap();
+
+        assert_eq!(pending.request, Ok(Request::Activate));
+
+        pending.reply(Ok(String::from("done\n")));
+
+        let mut reply = String::new();
+
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+
+        assert_eq!(reply, "done\n");
+    }
+}
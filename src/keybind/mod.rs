@@ -0,0 +1,222 @@
+use x11::xlib;
+
+use toml::Table;
+
+use std::ffi;
+
+
+const MODIFIERS: u32 = xlib::ShiftMask | xlib::ControlMask | xlib::Mod1Mask | xlib::Mod4Mask;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Copy,
+    CopyLink,
+    CopyWithLink,
+    Paste,
+    PasteRaw,
+    Palette,
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    NewWindow,
+    ScrollPageUp,
+    ScrollPageDown,
+    ScrollLineUp,
+    ScrollLineDown,
+    PromptPrev,
+    PromptNext,
+    SelectLastOutput,
+    CopyLastOutput,
+    Inspect,
+    OpenUrl,
+    SearchSelection,
+    Fullscreen,
+    RenderScale,
+    HistorySearch,
+    Search,
+}
+
+impl Command {
+    pub const ALL: [Command; 25] = [
+        Command::Copy,
+        Command::CopyLink,
+        Command::CopyWithLink,
+        Command::Paste,
+        Command::PasteRaw,
+        Command::Palette,
+        Command::ZoomIn,
+        Command::ZoomOut,
+        Command::ZoomReset,
+        Command::NewWindow,
+        Command::ScrollPageUp,
+        Command::ScrollPageDown,
+        Command::ScrollLineUp,
+        Command::ScrollLineDown,
+        Command::PromptPrev,
+        Command::PromptNext,
+        Command::SelectLastOutput,
+        Command::CopyLastOutput,
+        Command::Inspect,
+        Command::OpenUrl,
+        Command::SearchSelection,
+        Command::Fullscreen,
+        Command::RenderScale,
+        Command::HistorySearch,
+        Command::Search,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Copy => "copy",
+            Command::CopyLink => "copy_link",
+            Command::CopyWithLink => "copy_with_link",
+            Command::Paste => "paste",
+            Command::PasteRaw => "paste_raw",
+            Command::Palette => "palette",
+            Command::ZoomIn => "zoom_in",
+            Command::ZoomOut => "zoom_out",
+            Command::ZoomReset => "zoom_reset",
+            Command::NewWindow => "new_window",
+            Command::ScrollPageUp => "scroll_page_up",
+            Command::ScrollPageDown => "scroll_page_down",
+            Command::ScrollLineUp => "scroll_line_up",
+            Command::ScrollLineDown => "scroll_line_down",
+            Command::PromptPrev => "prompt_prev",
+            Command::PromptNext => "prompt_next",
+            Command::SelectLastOutput => "select_last_output",
+            Command::CopyLastOutput => "copy_last_output",
+            Command::Inspect => "inspect",
+            Command::OpenUrl => "open_url",
+            Command::SearchSelection => "search_selection",
+            Command::Fullscreen => "fullscreen",
+            Command::RenderScale => "render_scale",
+            Command::HistorySearch => "history_search",
+            Command::Search => "search",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Command::Copy => "Copy selection to clipboard",
+            Command::CopyLink => "Copy selection with hyperlinks replaced by their uri",
+            Command::CopyWithLink => "Copy selection with hyperlink uris after their text",
+            Command::Paste => "Paste from clipboard",
+            Command::PasteRaw => "Paste from clipboard without transforms",
+            Command::Palette => "Open command palette",
+            Command::ZoomIn => "Increase font size",
+            Command::ZoomOut => "Decrease font size",
+            Command::ZoomReset => "Reset font size",
+            Command::NewWindow => "Open a new window",
+            Command::ScrollPageUp => "Scroll up one page",
+            Command::ScrollPageDown => "Scroll down one page",
+            Command::ScrollLineUp => "Scroll up, faster while held",
+            Command::ScrollLineDown => "Scroll down, faster while held",
+            Command::PromptPrev => "Jump to previous prompt",
+            Command::PromptNext => "Jump to next prompt",
+            Command::SelectLastOutput => "Select last command output",
+            Command::CopyLastOutput => "Copy last command output to clipboard",
+            Command::Inspect => "Inspect character under pointer",
+            Command::OpenUrl => "Open url under pointer",
+            Command::SearchSelection => "Search the web for selection",
+            Command::Fullscreen => "Toggle fullscreen",
+            Command::RenderScale => "Toggle render scale",
+            Command::HistorySearch => "Search command history",
+            Command::Search => "Search scrollback",
+        }
+    }
+
+    fn default_binding(&self) -> &'static str {
+        match self {
+            Command::Copy => "ctrl+shift+c",
+            Command::CopyLink => "ctrl+shift+u",
+            Command::CopyWithLink => "ctrl+shift+l",
+            Command::Paste => "ctrl+shift+v",
+            Command::PasteRaw => "ctrl+alt+shift+v",
+            Command::Palette => "ctrl+shift+p",
+            Command::ZoomIn => "ctrl+shift+equal",
+            Command::ZoomOut => "ctrl+shift+minus",
+            Command::ZoomReset => "ctrl+shift+BackSpace",
+            Command::NewWindow => "ctrl+shift+n",
+            Command::ScrollPageUp => "shift+Prior",
+            Command::ScrollPageDown => "shift+Next",
+            Command::ScrollLineUp => "ctrl+shift+Up",
+            Command::ScrollLineDown => "ctrl+shift+Down",
+            Command::PromptPrev => "ctrl+shift+z",
+            Command::PromptNext => "ctrl+shift+x",
+            Command::SelectLastOutput => "ctrl+shift+g",
+            Command::CopyLastOutput => "ctrl+shift+o",
+            Command::Inspect => "ctrl+shift+i",
+            Command::OpenUrl => "",
+            Command::SearchSelection => "",
+            Command::Fullscreen => "F11",
+            Command::RenderScale => "",
+            Command::HistorySearch => "ctrl+shift+h",
+            Command::Search => "ctrl+shift+f",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Keybind {
+    pub command: Command,
+    pub binding: String,
+    mods: u32,
+    keysym: u32,
+}
+
+impl Keybind {
+    pub fn parse(command: Command, binding: &str) -> Result<Keybind, Box<dyn std::error::Error>> {
+        let mut mods = 0;
+        let mut keysym = None;
+
+        for part in binding.split('+') {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" => mods |= xlib::ControlMask,
+                "shift" => mods |= xlib::ShiftMask,
+                "alt" | "mod1" => mods |= xlib::Mod1Mask,
+                "super" | "mod4" => mods |= xlib::Mod4Mask,
+                _ => {
+                    let name = ffi::CString::new(part)?;
+
+                    match unsafe { xlib::XStringToKeysym(name.as_ptr()) } as u32 {
+                        0 => return Err(format!("unknown key in binding: {}", binding).into()),
+                        sym => keysym = Some(sym),
+                    }
+                },
+            }
+        }
+
+        Ok(Keybind {
+            command,
+            binding: binding.to_string(),
+            mods,
+            keysym: keysym.ok_or(format!("binding has no key: {}", binding))?,
+        })
+    }
+
+    pub fn matches(&self, keysym: u32, state: u32) -> bool {
+        self.keysym == keysym && self.mods == state & MODIFIERS
+    }
+}
+
+pub fn load(table: Option<&Table>) -> Result<Vec<Keybind>, Box<dyn std::error::Error>> {
+    let mut keybinds: Vec<Keybind> = Vec::new();
+
+    for command in Command::ALL {
+        let binding = table.and_then(|table| table.get(command.name()))
+            .and_then(|binding| binding.as_str())
+            .unwrap_or(command.default_binding());
+
+        // an empty binding unbinds the command, it is still reachable from the palette
+
+        if !binding.is_empty() {
+            keybinds.push(Keybind::parse(command, binding)?);
+        }
+    }
+
+    Ok(keybinds)
+}
+
+pub fn lookup(keybinds: &[Keybind], keysym: u32, state: u32) -> Option<Command> {
+    keybinds.iter().find(|keybind| keybind.matches(keysym, state)).map(|keybind| keybind.command)
+}
@@ -1,16 +1,64 @@
 mod terminal;
+mod keybind;
 mod escape;
 mod config;
 mod xlib;
 mod pty;
+mod a11y;
+mod ipc;
 
 use terminal::Terminal;
 
 use std::process;
+use std::env;
 
 
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    oneshot: bool,
+    command: Vec<String>,
+}
+
+// everything after -e is the command and its arguments
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--oneshot" => parsed.oneshot = true,
+            "-e" => {
+                parsed.command = args.by_ref().collect();
+
+                if parsed.command.is_empty() {
+                    return Err(String::from("-e expects a command"));
+                }
+            },
+            arg => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+
+    Ok(parsed)
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut terminal = match Terminal::new() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            println!("[+] {}", err);
+            process::exit(1);
+        },
+    };
+
+    // a plain launch hands over to the running window in single instance mode, commands always get their own window
+
+    let single_instance = config::startup().is_ok_and(|startup| startup.single_instance);
+
+    if single_instance && args.command.is_empty() && ipc::activate_instance() {
+        return Ok(());
+    }
+
+    let mut terminal = match Terminal::new(&args.command) {
         Ok(terminal) => terminal,
         Err(err) => {
             println!("[+] failed to create terminal: {}", err);
@@ -23,6 +71,32 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         process::exit(1);
     }
 
+    // a oneshot window reports how its command went, like running the command directly would
+
+    if args.oneshot {
+        let status = terminal.exit_status().unwrap_or(1);
+
+        drop(terminal);
+
+        process::exit(status);
+    }
+
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn args() {
+        assert_eq!(parse(&[]), Ok(Args::default()));
+        assert_eq!(parse(&["--oneshot", "-e", "ls", "-e"]), Ok(Args { oneshot: true, command: vec![String::from("ls"), String::from("-e")] }));
+        assert!(parse(&["-e"]).is_err());
+        assert!(parse(&["--hold"]).is_err());
+    }
+}
@@ -1,37 +1,127 @@
+mod reader;
+
+pub use reader::Reader;
+
 use nix::libc;
 use nix::pty;
 
-use std::process::{Command, Stdio, Child};
-use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio, Child, ExitStatus};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::os::fd::{FromRawFd, AsRawFd};
-use std::io::{Error, ErrorKind};
+use std::io::{self, Read, Write, Error, ErrorKind};
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::fs::File;
+use std::thread;
+use std::env;
 
 nix::ioctl_write_ptr_bad!(set_window_size, libc::TIOCSWINSZ, pty::Winsize);
 
+// assets/termal.terminfo compiled with tic, xterm-kitty is the closest widely installed entry until it is
+
+const TERMINFO: &str = "termal";
+const FALLBACK_TERM: &str = "xterm-kitty";
+
+const HANGUP_TIMEOUT: Duration = Duration::from_secs(1);
+
+
+// the terminal only talks to the shell through this, which lets tests swap in a fake
+
+pub trait PtyLike: Read + Write + AsRawFd {
+    fn resize(&mut self, width: u16, height: u16) -> Result<(), Box<dyn std::error::Error>>;
+
+    fn exit_status(&mut self) -> Option<i32> {
+        None
+    }
+
+    fn foreground_job(&self) -> bool {
+        false
+    }
+}
 
 pub struct Pty {
     pub child: Child,
     pub file: File,
+    shell: bool,
+}
+
+// the compiled entry is looked up like ncurses does, with the directory named by the first letter or its hex code
+
+pub fn term() -> &'static str {
+    static TERM: OnceLock<&'static str> = OnceLock::new();
+
+    TERM.get_or_init(|| {
+        let home = env::var("HOME").map(|home| PathBuf::from(home).join(".terminfo"));
+        let dirs = env::var("TERMINFO_DIRS").unwrap_or_default();
+
+        let dirs = env::var("TERMINFO").map(PathBuf::from).into_iter()
+            .chain(home)
+            .chain(dirs.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from))
+            .chain(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].map(PathBuf::from));
+
+        if has_entry(dirs, TERMINFO) { TERMINFO } else { FALLBACK_TERM }
+    })
+}
+
+fn has_entry(mut dirs: impl Iterator<Item = PathBuf>, name: &str) -> bool {
+    let Some(first) = name.chars().next() else { return false };
+
+    dirs.any(|dir| [first.to_string(), format!("{:x}", first as u32)].iter().any(|sub| Path::new(&dir).join(sub).join(name).exists()))
 }
 
 impl Drop for Pty {
     fn drop(&mut self) {
+        let _ = self.hang_up();
+    }
+}
+
+impl Pty {
+    // a child still running when the window goes away gets SIGHUP like from a closed terminal, and SIGKILL once it
+    // ignored that for HANGUP_TIMEOUT
+
+    fn hang_up(&mut self) -> Option<ExitStatus> {
+        if let Some(status) = self.child.try_wait().ok()? {
+            return Some(status);
+        }
+
         unsafe {
             libc::kill(self.child.id() as i32, libc::SIGHUP);
         }
 
-        let _ = self.child.wait();
+        let started = Instant::now();
+
+        while started.elapsed() < HANGUP_TIMEOUT {
+            if let Some(status) = self.child.try_wait().ok()? {
+                return Some(status);
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let _ = self.child.kill();
+
+        self.child.wait().ok()
     }
-}
 
-impl Pty {
-    pub fn new() -> Result<Pty, Box<dyn std::error::Error>> {
+    // an empty command starts the shell, env is set on top of what the terminal sets for every child
+
+    pub fn new(command: &[String], env: &[(&str, String)]) -> Result<Pty, Box<dyn std::error::Error>> {
         let fd = pty::openpty(None, None)?;
         let master = fd.master.as_raw_fd();
         let slave = fd.master.as_raw_fd();
 
-        let mut builder = Command::new("/bin/bash");
+        let mut builder = match command.split_first() {
+            Some((program, args)) => {
+                let mut builder = Command::new(program);
+
+                builder.args(args);
+
+                builder
+            },
+            None => Command::new("/bin/bash"),
+        };
 
         builder.stdin(unsafe { Stdio::from_raw_fd(fd.slave.as_raw_fd()) });
         builder.stdout(unsafe { Stdio::from_raw_fd(fd.slave.as_raw_fd()) });
@@ -39,8 +129,14 @@ impl Pty {
 
         builder.env_remove("LINES");
         builder.env_remove("COLUMNS");
+        builder.env_remove("TERMAL_SOCKET");
 
-        builder.env("TERM", "xterm-kitty");
+        builder.env("TERM", term());
+        builder.env("COLORTERM", "truecolor");
+        builder.env("TERM_PROGRAM", "termal");
+        builder.env("TERM_PROGRAM_VERSION", env!("CARGO_PKG_VERSION"));
+
+        builder.envs(env.iter().map(|(key, value)| (*key, value)));
 
         unsafe {
             builder.pre_exec(move || {
@@ -65,10 +161,13 @@ impl Pty {
         Ok(Pty {
             child,
             file: File::from(fd.master),
+            shell: command.is_empty(),
         })
     }
+}
 
-    pub fn resize(&mut self, width: u16, height: u16) -> Result<(), Box<dyn std::error::Error>> {
+impl PtyLike for Pty {
+    fn resize(&mut self, width: u16, height: u16) -> Result<(), Box<dyn std::error::Error>> {
         unsafe {
             let winsize = libc::winsize {
                 ws_row: height,
@@ -82,6 +181,155 @@ impl Pty {
 
         Ok(())
     }
+
+    // like a shell reports it, a child killed by a signal exits with 128 + the signal
+
+    fn exit_status(&mut self) -> Option<i32> {
+        let status = self.hang_up()?;
+
+        status.code().or(status.signal().map(|signal| 128 + signal))
+    }
+
+    // the shell leads its own process group, anything else in the foreground is a job it started. a command from -e is
+    // the job itself, like an editor with unsaved work, so it counts for as long as anything holds the foreground
+
+    fn foreground_job(&self) -> bool {
+        let group = unsafe { libc::tcgetpgrp(self.file.as_raw_fd()) };
+
+        group > 0 && (!self.shell || group as u32 != self.child.id())
+    }
 }
 
+impl Read for Pty {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for Pty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
 
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl AsRawFd for Pty {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+// reads are served from input and everything the terminal writes ends up in output, both are shared
+// so a test can keep a handle after moving the fake into the terminal
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct FakePty {
+    pub input: std::rc::Rc<std::cell::RefCell<std::collections::VecDeque<u8>>>,
+    pub output: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+    pub size: std::rc::Rc<std::cell::Cell<(u16, u16)>>,
+}
+
+#[cfg(test)]
+impl FakePty {
+    pub fn take_output(&self) -> String {
+        String::from_utf8_lossy(&self.output.take()).to_string()
+    }
+}
+
+#[cfg(test)]
+impl PtyLike for FakePty {
+    fn resize(&mut self, width: u16, height: u16) -> Result<(), Box<dyn std::error::Error>> {
+        self.size.set((width, height));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Read for FakePty {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = self.input.borrow_mut();
+
+        if input.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+
+        let len = buf.len().min(input.len());
+
+        for (byte, input) in buf.iter_mut().zip(input.drain(..len)) {
+            *byte = input;
+        }
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+impl Write for FakePty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.borrow_mut().extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl AsRawFd for FakePty {
+    // poll ignores negative descriptors
+
+    fn as_raw_fd(&self) -> RawFd {
+        -1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hang_up() {
+        let command = |command: &str| vec![String::from("sh"), String::from("-c"), String::from(command)];
+
+        // a child still running is hung up on instead of waited for, one that ignores SIGHUP is killed
+
+        let mut pty = Pty::new(&command("sleep 30"), &[]).unwrap();
+
+        assert_eq!(pty.exit_status(), Some(128 + libc::SIGHUP));
+
+        let mut pty = Pty::new(&command("trap '' HUP; sleep 30"), &[]).unwrap();
+
+        thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(pty.exit_status(), Some(128 + libc::SIGKILL));
+    }
+
+    #[test]
+    fn terminfo_entry() {
+        let dir = env::temp_dir().join(format!("termal-terminfo-{}", std::process::id()));
+
+        std::fs::create_dir_all(dir.join("74")).unwrap();
+        std::fs::write(dir.join("74").join("termal"), b"").unwrap();
+
+        assert!(has_entry([PathBuf::from("/nonexistent"), dir.clone()].into_iter(), "termal"));
+        assert!(!has_entry([dir.clone()].into_iter(), "xterm-termal"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn foreground_job() {
+        let pty = Pty::new(&[String::from("sleep"), String::from("30")], &[]).unwrap();
+
+        thread::sleep(Duration::from_millis(100));
+
+        assert!(pty.foreground_job());
+    }
+}
@@ -0,0 +1,189 @@
+use nix::libc;
+
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::io::{self, Read, Write, ErrorKind};
+use std::fs::File;
+use std::thread;
+
+// chunks in flight before the thread stops reading, the child then blocks on its writes until the terminal caught up
+
+const CAPACITY: usize = 64;
+const CHUNK_SIZE: usize = 16384;
+
+// bytes handed out per call, what is parsed in one pass of the main loop before it looks at x events again
+
+const PASS_BYTES: usize = 256 * 1024;
+
+
+pub struct Received {
+    pub chunks: Vec<Vec<u8>>,
+    pub closed: bool,
+}
+
+// reads the pty on its own thread, a byte on the wake pipe tells poll in the main loop that chunks arrived
+
+pub struct Reader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    wake: File,
+    notify: File,
+}
+
+impl Reader {
+    // the thread reads a duplicate of fd, which shares the nonblocking flag with the original. it is closed on exec so
+    // helper processes like the print command don't hold the pty open
+
+    pub fn spawn(fd: RawFd) -> Result<Reader, Box<dyn std::error::Error>> {
+        let mut master = unsafe { File::from_raw_fd(cvt(libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0))?) };
+
+        let mut pipe = [0; 2];
+
+        cvt(unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) })?;
+
+        let (wake, notify) = unsafe { (File::from_raw_fd(pipe[0]), File::from_raw_fd(pipe[1])) };
+
+        let mut sender_notify = notify.try_clone()?;
+
+        let (sender, chunks) = mpsc::sync_channel(CAPACITY);
+
+        thread::Builder::new().name(String::from("pty reader")).spawn(move || {
+            let mut buffer = vec![0; CHUNK_SIZE];
+
+            loop {
+                let chunk = match master.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(bytes) => Ok(buffer[..bytes].to_vec()),
+                    Err(err) => match err.kind() {
+                        ErrorKind::WouldBlock => {
+                            let mut pollfd = libc::pollfd { fd: master.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+
+                            unsafe { libc::poll(&mut pollfd, 1, -1) };
+
+                            continue;
+                        },
+                        ErrorKind::Interrupted => continue,
+                        // the master reads EIO once the child and everything it left running closed the slave side
+                        _ if err.raw_os_error() == Some(libc::EIO) => break,
+                        _ => Err(err),
+                    },
+                };
+
+                let failed = chunk.is_err();
+
+                if sender.send(chunk).is_err() || failed {
+                    break;
+                }
+
+                let _ = sender_notify.write(&[1]);
+            }
+
+            // dropping the sender disconnects the channel, the last byte wakes the main loop to notice
+
+            drop(sender);
+
+            let _ = sender_notify.write(&[1]);
+        })?;
+
+        Ok(Reader {
+            chunks,
+            wake,
+            notify,
+        })
+    }
+
+    // at most PASS_BYTES are handed out per call so a flood of output still leaves the loop time for x events,
+    // whatever is left over wakes the next poll right away
+
+    pub fn receive(&mut self) -> io::Result<Received> {
+        let mut drained = [0; 64];
+
+        // the pipe is emptied before the channel, a chunk sent after this still leaves its byte behind
+
+        while self.wake.read(&mut drained).is_ok_and(|bytes| bytes > 0) {}
+
+        let mut received = Received {
+            chunks: Vec::new(),
+            closed: false,
+        };
+
+        let mut bytes = 0;
+
+        while bytes < PASS_BYTES {
+            match self.chunks.try_recv() {
+                Ok(chunk) => {
+                    let chunk = chunk?;
+
+                    bytes += chunk.len();
+
+                    received.chunks.push(chunk);
+                },
+                Err(TryRecvError::Empty) => return Ok(received),
+                Err(TryRecvError::Disconnected) => {
+                    received.closed = true;
+
+                    return Ok(received);
+                },
+            }
+        }
+
+        let _ = self.notify.write(&[1]);
+
+        Ok(received)
+    }
+}
+
+impl AsRawFd for Reader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wake.as_raw_fd()
+    }
+}
+
+fn cvt(result: i32) -> io::Result<i32> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait(reader: &Reader) {
+        let mut pollfd = libc::pollfd { fd: reader.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 1000) }, 1);
+    }
+
+    #[test]
+    fn chunks() {
+        let mut pipe = [0; 2];
+
+        cvt(unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_NONBLOCK) }).unwrap();
+
+        let (output, mut input) = unsafe { (File::from_raw_fd(pipe[0]), File::from_raw_fd(pipe[1])) };
+
+        let mut reader = Reader::spawn(output.as_raw_fd()).unwrap();
+
+        input.write_all(b"hello").unwrap();
+
+        let mut bytes = Vec::new();
+
+        while bytes.len() < 5 {
+            wait(&reader);
+
+            bytes.extend(reader.receive().unwrap().chunks.concat());
+        }
+
+        assert_eq!(bytes, b"hello");
+
+        // closing the other end ends the thread and disconnects the channel
+
+        drop(input);
+
+        while !reader.receive().unwrap().closed {
+            wait(&reader);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::config::Config;
+
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Source};
+
+use std::io::{self, Read};
+use std::time::Duration;
+use std::sync::Arc;
+use std::f32::consts::TAU;
+use std::fs::File;
+
+const SAMPLE_RATE: u32 = 44100;
+const FADE: f32 = 0.005;
+const VOLUME: f32 = 0.25;
+
+
+// anything the bell can play, a new source is made for every bell since rodio consumes it while playing
+
+pub trait BellSource {
+    fn source(&self) -> Result<Box<dyn Source<Item = f32> + Send>, Box<dyn std::error::Error>>;
+}
+
+struct Sound {
+    data: Arc<Vec<u8>>
+}
+
+impl AsRef<[u8]> for Sound {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Sound {
+    pub fn load(file: &str) -> Result<Sound, Box<dyn std::error::Error>> {
+        let mut bell: Vec<u8> = Vec::new();
+        File::open(file)?.read_to_end(&mut bell)?;
+
+        Ok(Sound {
+            data: Arc::new(bell),
+        })
+    }
+}
+
+impl BellSource for Sound {
+    fn source(&self) -> Result<Box<dyn Source<Item = f32> + Send>, Box<dyn std::error::Error>> {
+        let decoder = Decoder::new(io::Cursor::new(Sound { data: self.data.clone(), }))?;
+
+        Ok(Box::new(decoder.convert_samples()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wave {
+    Sine,
+    Square,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub wave: Wave,
+    pub frequency: u32,
+    pub duration: Duration,
+}
+
+impl BellSource for Tone {
+    fn source(&self) -> Result<Box<dyn Source<Item = f32> + Send>, Box<dyn std::error::Error>> {
+        Ok(Box::new(ToneSource { tone: *self, sample: 0 }))
+    }
+}
+
+// the edges fade in and out over a few milliseconds, cutting a wave off mid swing clicks
+
+struct ToneSource {
+    tone: Tone,
+    sample: u32,
+}
+
+impl ToneSource {
+    fn len(&self) -> u32 {
+        (self.tone.duration.as_secs_f32() * SAMPLE_RATE as f32) as u32
+    }
+}
+
+impl Iterator for ToneSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.sample >= self.len() {
+            return None;
+        }
+
+        let time = self.sample as f32 / SAMPLE_RATE as f32;
+        let phase = (time * self.tone.frequency as f32).fract();
+
+        let value = match self.tone.wave {
+            Wave::Sine => (phase * TAU).sin(),
+            Wave::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+        };
+
+        let remaining = (self.len() - self.sample) as f32 / SAMPLE_RATE as f32;
+        let envelope = (time / FADE).min(remaining / FADE).min(1.0);
+
+        self.sample += 1;
+
+        Some(value * envelope * VOLUME)
+    }
+}
+
+impl Source for ToneSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some((self.len() - self.sample.min(self.len())) as usize)
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(self.tone.duration)
+    }
+}
+
+// the output device is only opened on the first bell, it is slow to open and missing entirely in containers
+
+pub struct Audio {
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    bell: Option<Box<dyn BellSource>>,
+    unavailable: bool,
+}
+
+impl Audio {
+    pub fn new(config: &Config) -> Result<Audio, Box<dyn std::error::Error>> {
+        let tone = |wave| Tone {
+            wave,
+            frequency: config.bell_frequency,
+            duration: Duration::from_millis(config.bell_duration),
+        };
+
+        let bell: Option<Box<dyn BellSource>> = match config.bell.as_str() {
+            "none" => None,
+            "sine" => Some(Box::new(tone(Wave::Sine))),
+            "square" => Some(Box::new(tone(Wave::Square))),
+            file => Some(Box::new(Sound::load(file)?)),
+        };
+
+        Ok(Audio {
+            output: None,
+            bell,
+            unavailable: false,
+        })
+    }
+
+    pub fn play_bell(&mut self) {
+        let Some(bell) = self.bell.as_ref().filter(|_| !self.unavailable) else { return };
+
+        if self.output.is_none() {
+            match OutputStream::try_default() {
+                Ok(output) => self.output = Some(output),
+                Err(err) => {
+                    println!("[+] failed to open audio device: {}", err);
+
+                    self.unavailable = true;
+
+                    return;
+                },
+            }
+        }
+
+        if let (Some((_, stream_handle)), Ok(source)) = (self.output.as_ref(), bell.source()) {
+            if let Err(err) = stream_handle.play_raw(source) {
+                println!("[+] failed to play bell: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tones() {
+        let tone = Tone { wave: Wave::Square, frequency: 441, duration: Duration::from_millis(100) };
+        let samples = ToneSource { tone, sample: 0 }.collect::<Vec<f32>>();
+
+        assert_eq!(samples.len(), 4410);
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[2010], VOLUME);
+        assert_eq!(samples[2060], -VOLUME);
+        assert!(samples.iter().all(|sample| sample.abs() <= VOLUME));
+
+        let tone = Tone { wave: Wave::Sine, ..tone };
+        let peak = ToneSource { tone, sample: 0 }.fold(0.0_f32, |peak, sample| peak.max(sample));
+
+        assert!((peak - VOLUME).abs() < 0.001);
+    }
+}
@@ -0,0 +1,49 @@
+// https://vt100.net/docs/vt220-rm/chapter2.html#S2.4.3, 0x5f to 0x7e
+
+const DEC_SPECIAL: [char; 32] = [
+    ' ', '◆', '▒', '␉', '␌', '␍', '␊', '°', '±', '␤', '␋', '┘', '┐', '┌', '└', '┼',
+    '⎺', '⎻', '─', '⎼', '⎽', '├', '┤', '┴', '┬', '│', '≤', '≥', 'π', '≠', '£', '·',
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Charset {
+    Ascii,
+    Uk,
+    DecSpecial,
+}
+
+impl Charset {
+    pub fn from_designator(byte: u8) -> Option<Charset> {
+        match byte as char {
+            'B' => Some(Charset::Ascii),
+            'A' => Some(Charset::Uk),
+            '0' => Some(Charset::DecSpecial),
+            _ => None,
+        }
+    }
+
+    pub fn translate(&self, c: char) -> char {
+        match self {
+            Charset::DecSpecial if ('\x5f'..='\x7e').contains(&c) => DEC_SPECIAL[c as usize - 0x5f],
+            Charset::Uk if c == '#' => '£',
+            _ => c,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate() {
+        assert_eq!("lqqkx".chars().map(|c| Charset::DecSpecial.translate(c)).collect::<String>(), "┌──┐│");
+        assert_eq!(Charset::DecSpecial.translate('~'), '·');
+        assert_eq!(Charset::DecSpecial.translate('A'), 'A');
+        assert_eq!(Charset::Ascii.translate('q'), 'q');
+        assert_eq!(Charset::Uk.translate('#'), '£');
+        assert_eq!(Charset::from_designator(b'0'), Some(Charset::DecSpecial));
+        assert_eq!(Charset::from_designator(b'A'), Some(Charset::Uk));
+        assert_eq!(Charset::from_designator(b'Z'), None);
+    }
+}
@@ -0,0 +1,149 @@
+const BITS: usize = u64::BITS as usize;
+
+
+// one bit per cell, rows are padded to whole words so a clean row can be skipped by looking at a few words
+
+pub struct Dirty {
+    bits: Vec<u64>,
+    words: usize,
+    rows: usize,
+    columns: usize,
+}
+
+impl Dirty {
+    pub fn new(rows: usize, columns: usize) -> Dirty {
+        let words = columns.div_ceil(BITS);
+
+        let mut dirty = Dirty {
+            bits: vec![0; words * rows],
+            words,
+            rows,
+            columns,
+        };
+
+        dirty.fill(rows, columns);
+
+        dirty
+    }
+
+    // a resize keeps the allocation, the bits only move to a new layout
+
+    pub fn fill(&mut self, rows: usize, columns: usize) {
+        if rows != self.rows || columns != self.columns {
+            self.words = columns.div_ceil(BITS);
+            self.rows = rows;
+            self.columns = columns;
+
+            self.bits.resize(self.words * rows, 0);
+        }
+
+        // the padding after the last column has to stay clear, otherwise the row never looks clean
+
+        let last = match columns % BITS {
+            0 => u64::MAX,
+            bits => (1 << bits) - 1,
+        };
+
+        for (index, word) in self.bits.iter_mut().enumerate() {
+            *word = if index % self.words == self.words - 1 { last } else { u64::MAX };
+        }
+    }
+
+    #[inline]
+    pub fn set(&mut self, y: usize, x: usize) {
+        if y < self.rows && x < self.columns {
+            self.bits[y * self.words + x / BITS] |= 1 << (x % BITS);
+        }
+    }
+
+    #[inline]
+    pub fn clear(&mut self, y: usize, x: usize) {
+        if y < self.rows && x < self.columns {
+            self.bits[y * self.words + x / BITS] &= !(1 << (x % BITS));
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, y: usize, x: usize) -> bool {
+        y < self.rows && x < self.columns && self.bits[y * self.words + x / BITS] & (1 << (x % BITS)) != 0
+    }
+
+    #[inline]
+    pub fn row(&self, y: usize) -> bool {
+        y < self.rows && self.bits[y * self.words..(y + 1) * self.words].iter().any(|word| *word != 0)
+    }
+
+    pub fn set_row(&mut self, y: usize, start: usize) {
+        for x in start..self.columns {
+            self.set(y, x);
+        }
+    }
+
+    // the rows from top to bottom moved by count along with their pixels, the rows left behind have nothing drawn yet
+
+    pub fn shift(&mut self, top: usize, bottom: usize, count: usize, up: bool) {
+        let bottom = bottom.min(self.rows.saturating_sub(1));
+
+        if top > bottom {
+            return;
+        }
+
+        let count = count.min(bottom + 1 - top);
+        let (start, end, offset) = (top * self.words, (bottom + 1) * self.words, count * self.words);
+
+        if up {
+            self.bits.copy_within(start + offset..end, start);
+        } else {
+            self.bits.copy_within(start..end - offset, start + offset);
+        }
+
+        let exposed = if up { bottom + 1 - count..bottom + 1 } else { top..top + count };
+
+        for y in exposed {
+            self.set_row(y, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits() {
+        let mut dirty = Dirty::new(3, 130);
+
+        assert!(dirty.get(2, 129));
+
+        for y in 0..3 {
+            for x in 0..130 {
+                dirty.clear(y, x);
+            }
+        }
+
+        assert!(!dirty.row(0) && !dirty.row(1) && !dirty.row(2));
+
+        dirty.set(1, 64);
+        dirty.set(5, 0);
+
+        assert!(dirty.get(1, 64));
+        assert!(!dirty.get(1, 63) && !dirty.get(1, 65));
+        assert!(!dirty.row(0) && dirty.row(1) && !dirty.row(2));
+
+        dirty.shift(0, 2, 1, true);
+
+        assert!(dirty.get(0, 64) && !dirty.row(1) && dirty.get(2, 129));
+
+        dirty.clear(2, 129);
+        dirty.shift(1, 2, 1, false);
+
+        assert!(dirty.get(0, 64) && dirty.get(1, 0) && !dirty.row(2));
+
+        let capacity = dirty.bits.capacity();
+
+        dirty.fill(4, 10);
+
+        assert!(dirty.row(3) && dirty.get(3, 9) && !dirty.get(3, 10));
+        assert_eq!(dirty.bits.capacity(), capacity);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::xlib::Color;
+
+use x11::xft;
+
+use super::Window;
+
+// everything the screen asks of the window it lives in. the x display draws into its back buffer, the headless one
+// draws nothing and only hands titles and copies to the embedder
+
+pub trait Frontend {
+    fn resize_back_buffer(&mut self, window: &Window);
+
+    fn flush(&mut self);
+
+    fn pending(&mut self) -> i32;
+
+    fn connection_number(&self) -> i32;
+
+    fn select_layout_events(&mut self) -> Option<i32>;
+
+    fn layout(&mut self) -> Option<String>;
+
+    fn set_urgency_hint(&mut self, urgent: bool);
+
+    fn set_demands_attention(&mut self, urgent: bool);
+
+    fn toggle_fullscreen(&mut self);
+
+    fn activate(&mut self);
+
+    fn map_window(&mut self);
+
+    fn resize_window(&mut self, width: u32, height: u32);
+
+    fn iconify(&mut self);
+
+    fn lookup_string(&mut self, event: x11::xlib::XKeyEvent) -> Result<String, Box<dyn std::error::Error>>;
+
+    fn set_window_name(&mut self, name: &str);
+
+    fn poll_event(&mut self) -> Option<Vec<x11::xlib::XEvent>>;
+
+    fn keycode_to_keysym(&mut self, keycode: u8) -> u64;
+
+    fn select_input(&mut self);
+
+    fn define_cursor(&mut self, shape: u32);
+
+    fn copy_area(&mut self, source: (i32, i32), size: (u32, u32), dest: (i32, i32));
+
+    fn swap_buffers(&mut self, window: &Window);
+
+    #[allow(clippy::too_many_arguments)]
+    fn xft_draw_string(&mut self, text: &str, x: i32, y: i32, height: u32, width: u32, font: *mut xft::XftFont, color: *const xft::XftColor);
+
+    fn xft_draw_string_clipped(&mut self, text: &str, x: i32, y: i32, clip: (i32, i32, u32, u32), font: *mut xft::XftFont, color: *const xft::XftColor);
+
+    fn xft_draw_glyphs(&mut self, glyphs: &[(char, i32)], y: i32, clip: (i32, i32, u32, u32), font: *mut xft::XftFont, color: *const xft::XftColor);
+
+    fn xft_color_alloc_value(&self, rgb: Color) -> Result<xft::XftColor, Box<dyn std::error::Error>>;
+
+    fn load_font(&mut self, font: &str) -> Result<*mut xft::XftFont, Box<dyn std::error::Error>>;
+
+    fn close_font(&mut self, font: *mut xft::XftFont);
+
+    // the width, height and baseline of a cell in the font
+
+    fn font_metrics(&self, font: *mut xft::XftFont) -> (i32, i32, i32);
+
+    fn outline_rec(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color);
+
+    fn pixel(&self, color: Color) -> u64;
+
+    fn draw_lines(&mut self, points: &[(i32, i32)], color: Color);
+
+    fn draw_image(&mut self, pixels: &[u32], size: (u32, u32), source: (i32, u32), x: i32, y: i32);
+
+    fn draw_rec(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color);
+
+    fn clipboard(&mut self) -> Option<String>;
+
+    fn set_clipboard(&mut self, text: String) -> Result<(), Box<dyn std::error::Error>>;
+}
@@ -0,0 +1,515 @@
+use image::imageops::{self, FilterType};
+use image::io::{Limits, Reader};
+use image::RgbaImage;
+use base64::Engine;
+
+use std::ops::Range;
+use std::io::Cursor;
+
+// files larger than this are refused before their pixels are allocated
+
+const MAX_IMAGE_SIDE: u32 = 16384;
+const MAX_IMAGE_BYTES: u64 = 256 * 1024 * 1024;
+
+// decoded sources kept around for rescaling, the oldest placements are dropped once all of them take more than this
+
+const MAX_SOURCE_BYTES: usize = 64 * 1024 * 1024;
+
+
+// https://iterm2.com/documentation-images.html, width and height are given in cells, pixels, percent of the window or left to the image
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extent {
+    Auto,
+    Cells(u32),
+    Pixels(u32),
+    Percent(u32),
+}
+
+impl Extent {
+    fn parse(value: &str) -> Result<Extent, String> {
+        let number = |number: &str| number.parse::<u32>().map_err(|_| format!("invalid extent: {}", value));
+
+        match value {
+            "auto" => Ok(Extent::Auto),
+            value if value.ends_with("px") => Ok(Extent::Pixels(number(&value[..value.len() - 2])?)),
+            value if value.ends_with('%') => Ok(Extent::Percent(number(&value[..value.len() - 1])?)),
+            value => Ok(Extent::Cells(number(value)?)),
+        }
+    }
+
+    // the size in pixels, cell is the size of one cell and screen the size of the window along the same axis
+
+    fn resolve(&self, cell: u32, screen: u32) -> Option<u32> {
+        match self {
+            Extent::Auto => None,
+            Extent::Cells(cells) => Some(cells.checked_mul(cell).unwrap_or(u32::MAX)),
+            Extent::Pixels(pixels) => Some(*pixels),
+            Extent::Percent(percent) => Some(screen * percent.min(&100) / 100),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Size {
+    pub width: Extent,
+    pub height: Extent,
+    pub preserve_aspect: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineImage {
+    pub size: Size,
+    pub data: Vec<u8>,
+}
+
+// the arguments after "File=" up to the colon, then the base64 file. without inline=1 the file is a download, which is ignored
+
+pub fn parse_inline(args: &str) -> Result<Option<InlineImage>, String> {
+    let (args, data) = args.split_once(':').ok_or("missing file contents")?;
+
+    let mut image = InlineImage {
+        size: Size {
+            width: Extent::Auto,
+            height: Extent::Auto,
+            preserve_aspect: true,
+        },
+        data: Vec::new(),
+    };
+
+    let mut inline = false;
+
+    for arg in args.split(';').filter(|arg| !arg.is_empty()) {
+        match arg.split_once('=') {
+            Some(("inline", value)) => inline = value == "1",
+            Some(("width", value)) => image.size.width = Extent::parse(value)?,
+            Some(("height", value)) => image.size.height = Extent::parse(value)?,
+            Some(("preserveAspectRatio", value)) => image.size.preserve_aspect = value != "0",
+            Some(("name" | "size" | "type", _)) => {},
+            _ => println!("[+] unknown inline image argument: {}", arg),
+        }
+    }
+
+    if !inline {
+        return Ok(None);
+    }
+
+    image.data = base64::engine::general_purpose::STANDARD.decode(data.trim()).map_err(|err| err.to_string())?;
+
+    Ok(Some(image))
+}
+
+// a decoded image scaled to the pixels it covers, in the pixel format of the window
+
+pub struct Picture {
+    pub rows: usize,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u32>,
+}
+
+pub fn decode(data: &[u8]) -> Result<RgbaImage, String> {
+    let mut reader = Reader::new(Cursor::new(data)).with_guessed_format().map_err(|err| err.to_string())?;
+
+    let mut limits = Limits::default();
+
+    limits.max_image_width = Some(MAX_IMAGE_SIDE);
+    limits.max_image_height = Some(MAX_IMAGE_SIDE);
+    limits.max_alloc = Some(MAX_IMAGE_BYTES);
+
+    reader.limits(limits);
+
+    Ok(reader.decode().map_err(|err| err.to_string())?.to_rgba8())
+}
+
+// transparent pixels are blended with the background up front, the window has no alpha channel to draw them with
+
+pub fn scale(
+    image: &RgbaImage,
+    size: Size,
+    cell: (u32, u32),
+    screen: (usize, usize),
+    bg: (u8, u8, u8),
+    pixel: impl Fn(u8, u8, u8) -> u32,
+) -> Picture {
+    let window = (screen.0 as u32 * cell.0, screen.1 as u32 * cell.1);
+    let (width, height) = fit(size, image.dimensions(), cell, window);
+
+    let scaled = imageops::resize(image, width, height, FilterType::Triangle);
+
+    let blend = |channel: u8, bg: u8, alpha: u8| ((channel as u32 * alpha as u32 + bg as u32 * (255 - alpha as u32)) / 255) as u8;
+
+    Picture {
+        rows: height.div_ceil(cell.1) as usize,
+        width,
+        height,
+        pixels: scaled.pixels()
+            .map(|rgba| pixel(blend(rgba[0], bg.0, rgba[3]), blend(rgba[1], bg.1, rgba[3]), blend(rgba[2], bg.2, rgba[3])))
+            .collect(),
+    }
+}
+
+// a missing extent follows the aspect ratio of the other one, an image sized by neither is only ever shrunk to fit the window width.
+// nothing ends up larger than the window, an extent like 100000px would otherwise have resize allocate gigabytes
+
+fn fit(size: Size, image: (u32, u32), cell: (u32, u32), window: (u32, u32)) -> (u32, u32) {
+    let (image_width, image_height) = (image.0.max(1) as u64, image.1.max(1) as u64);
+
+    let (width, height) = match (size.width.resolve(cell.0, window.0), size.height.resolve(cell.1, window.1)) {
+        (Some(width), Some(height)) if size.preserve_aspect => {
+            let fitted = (height as u64 * image_width / image_height) as u32;
+
+            if fitted <= width { (fitted, height) } else { (width, (width as u64 * image_height / image_width) as u32) }
+        },
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, (width as u64 * image_height / image_width) as u32),
+        (None, Some(height)) => ((height as u64 * image_width / image_height) as u32, height),
+        (None, None) if image_width > window.0 as u64 => (window.0, (window.0 as u64 * image_height / image_width) as u32),
+        (None, None) => (image_width as u32, image_height as u32),
+    };
+
+    let (width, height) = match (width > window.0 || height > window.1, size.preserve_aspect) {
+        (true, true) => {
+            let (width, height) = (width.max(1) as u64, height.max(1) as u64);
+            let (window_width, window_height) = (window.0 as u64, window.1 as u64);
+
+            if width * window_height > height * window_width {
+                (window.0, (height * window_width / width) as u32)
+            } else {
+                ((width * window_height / height) as u32, window.1)
+            }
+        },
+        _ => (width.min(window.0), height.min(window.1)),
+    };
+
+    (width.max(1), height.max(1))
+}
+
+// an image anchored at the cell holding its top left corner, y is an absolute row like the selection uses. visible holds
+// the picture rows that are still shown, scroll regions cut off the rows they push past their margins
+
+pub struct Placement {
+    pub picture: Picture,
+    pub x: usize,
+    pub y: i32,
+    pub visible: Range<usize>,
+    pub alt: bool,
+    source: RgbaImage,
+    size: Size,
+    stale: bool,
+}
+
+impl Placement {
+    // the absolute rows the visible part of the picture covers
+
+    pub fn rows(&self) -> Range<i32> {
+        self.y + self.visible.start as i32..self.y + self.visible.end as i32
+    }
+}
+
+// the placements of both screens, the screen keeps them in step with its lines as they scroll, get erased and resize
+
+#[derive(Default)]
+pub struct Placements {
+    placements: Vec<Placement>,
+}
+
+impl Placements {
+    pub fn push(&mut self, source: RgbaImage, size: Size, picture: Picture, (x, y): (usize, i32), alt: bool) {
+        self.placements.push(Placement {
+            visible: 0..picture.rows,
+            picture,
+            x,
+            y,
+            alt,
+            source,
+            size,
+            stale: false,
+        });
+
+        // the new placement is always kept, even when it alone is over the limit
+
+        let mut bytes = self.placements.iter().map(|placement| placement.source.as_raw().len()).sum::<usize>();
+        let mut evicted = 0;
+
+        while bytes > MAX_SOURCE_BYTES && evicted + 1 < self.placements.len() {
+            bytes -= self.placements[evicted].source.as_raw().len();
+            evicted += 1;
+        }
+
+        self.placements.drain(..evicted);
+    }
+
+    pub fn iter(&self, alt: bool) -> impl Iterator<Item = &Placement> {
+        self.placements.iter().filter(move |placement| placement.alt == alt)
+    }
+
+    // count lines were dropped from the top of the scrollback, an image goes with the line it is anchored to
+
+    pub fn trim(&mut self, count: usize) {
+        self.placements.retain_mut(|placement| {
+            placement.y -= count as i32;
+
+            placement.y + placement.visible.start as i32 >= 0
+        });
+    }
+
+    // the lines from top to bottom moved by count without passing through the scrollback, images anchored there move along
+
+    pub fn shift(&mut self, (top, bottom): (i32, i32), count: usize, up: bool, alt: bool) {
+        let distance = if up { -(count as i32) } else { count as i32 };
+
+        self.placements.retain_mut(|placement| {
+            if placement.alt != alt || !(top..=bottom).contains(&placement.rows().start) {
+                return true;
+            }
+
+            placement.y += distance;
+
+            let start = (top - placement.y).max(placement.visible.start as i32);
+            let end = (bottom + 1 - placement.y).min(placement.visible.end as i32);
+
+            placement.visible = start.max(0) as usize..end.max(0) as usize;
+
+            start < end
+        });
+    }
+
+    pub fn erase(&mut self, rows: Range<i32>, alt: bool) {
+        self.placements.retain(|placement| placement.alt != alt || !rows.contains(&placement.rows().start));
+    }
+
+    pub fn clear(&mut self, alt: bool) {
+        self.placements.retain(|placement| placement.alt != alt);
+    }
+
+    // the cells changed size, the pictures are scaled again from their source the next time they are drawn so a resize
+    // doesn't wait on every image in the scrollback
+
+    pub fn rescale(&mut self) {
+        for placement in self.placements.iter_mut() {
+            placement.stale = true;
+        }
+    }
+
+    // scales the stale pictures anchored in rows again. a picture is never taller than the window, so rows starting a
+    // screen above the view covers everything that can reach into it
+
+    pub fn refresh(&mut self, rows: Range<i32>, alt: bool, scale: impl Fn(&RgbaImage, Size) -> Picture) {
+        for placement in self.placements.iter_mut().filter(|placement| placement.stale && placement.alt == alt && rows.contains(&placement.y)) {
+            placement.stale = false;
+
+            let full = placement.visible == (0..placement.picture.rows);
+
+            placement.picture = scale(&placement.source, placement.size);
+
+            placement.visible = match full {
+                true => 0..placement.picture.rows,
+                false => placement.visible.start.min(placement.picture.rows)..placement.visible.end.min(placement.picture.rows),
+            };
+        }
+    }
+}
+
+// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html, XTSMGRAPHICS. there is no sixel decoder so nothing reads these,
+// they are kept so a program reads back what it set. the geometry never grows past the window
+
+const COLOR_REGISTERS: u32 = 256;
+const MAX_COLOR_REGISTERS: u32 = 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GraphicsAttributes {
+    registers: Option<u32>,
+    geometry: Option<(u32, u32)>,
+}
+
+impl GraphicsAttributes {
+    // the reply is item;status;values, a status of 1 is an unknown item, 2 an unknown action and 3 a failed set
+
+    pub fn query(&mut self, item: u16, action: u16, values: &[u16], window: (u32, u32)) -> String {
+        let geometry = |(width, height): (u32, u32)| (width.min(window.0), height.min(window.1));
+
+        let reply = match (item, action) {
+            (1, 1) => Ok(vec![self.registers.unwrap_or(COLOR_REGISTERS)]),
+            (1, 2) => {
+                self.registers = None;
+
+                Ok(vec![COLOR_REGISTERS])
+            },
+            (1, 3) => match values.first() {
+                Some(&registers) if registers > 0 => {
+                    self.registers = Some((registers as u32).min(MAX_COLOR_REGISTERS));
+
+                    Ok(vec![self.registers.unwrap_or(COLOR_REGISTERS)])
+                },
+                _ => Err(3),
+            },
+            (1, 4) => Ok(vec![MAX_COLOR_REGISTERS]),
+            (2, 1) => {
+                let (width, height) = geometry(self.geometry.unwrap_or(window));
+
+                Ok(vec![width, height])
+            },
+            (2, 2) => {
+                self.geometry = None;
+
+                Ok(vec![window.0, window.1])
+            },
+            (2, 3) => match values {
+                [width, height, ..] if *width > 0 && *height > 0 => {
+                    let (width, height) = geometry((*width as u32, *height as u32));
+
+                    self.geometry = Some((width, height));
+
+                    Ok(vec![width, height])
+                },
+                _ => Err(3),
+            },
+            (2, 4) => Ok(vec![window.0, window.1]),
+            (1 | 2, _) => Err(2),
+            _ => Err(1),
+        };
+
+        match reply {
+            Ok(values) => format!("\x1b[?{};0;{}S", item, values.iter().map(|value| value.to_string()).collect::<Vec<String>>().join(";")),
+            Err(status) => format!("\x1b[?{};{};0S", item, status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 2x1 png, the left pixel is opaque red and the right one fully transparent
+
+    const PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAYAAAD0In+KAAAAD0lEQVR42mP4z8AARAwMAAz8Af/lhqjSAAAAAElFTkSuQmCC";
+
+    #[test]
+    fn arguments() {
+        let image = parse_inline(&format!("name=YS5wbmc=;size=72;width=10;height=50%;preserveAspectRatio=0;inline=1:{}", PNG)).unwrap().unwrap();
+
+        assert_eq!(image.size, Size { width: Extent::Cells(10), height: Extent::Percent(50), preserve_aspect: false });
+        assert_eq!(image.data.len(), 72);
+        assert_eq!(parse_inline(&format!("width=20px:{}", PNG)), Ok(None));
+        assert_eq!(parse_inline("inline=1;width=wide:AAAA"), Err(String::from("invalid extent: wide")));
+        assert!(parse_inline("inline=1").is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        let image = |width, height, preserve_aspect| Size { width, height, preserve_aspect };
+
+        let (cell, window) = ((10, 20), (800, 600));
+
+        assert_eq!(fit(image(Extent::Auto, Extent::Auto, true), (400, 300), cell, window), (400, 300));
+        assert_eq!(fit(image(Extent::Auto, Extent::Auto, true), (1600, 300), cell, window), (800, 150));
+        assert_eq!(fit(image(Extent::Cells(20), Extent::Auto, true), (400, 300), cell, window), (200, 150));
+        assert_eq!(fit(image(Extent::Auto, Extent::Percent(50), true), (400, 300), cell, window), (400, 300));
+        assert_eq!(fit(image(Extent::Pixels(100), Extent::Cells(10), true), (400, 300), cell, window), (100, 75));
+        assert_eq!(fit(image(Extent::Pixels(100), Extent::Cells(10), false), (400, 300), cell, window), (100, 200));
+        assert_eq!(fit(image(Extent::Pixels(100000), Extent::Pixels(100000), false), (400, 300), cell, window), (800, 600));
+        assert_eq!(fit(image(Extent::Cells(u32::MAX), Extent::Cells(1), false), (400, 300), cell, window), (800, 20));
+        assert_eq!(fit(image(Extent::Auto, Extent::Auto, true), (300, 1200), cell, window), (150, 600));
+    }
+
+    #[test]
+    fn decoding() {
+        let image = parse_inline(&format!("inline=1;width=4px;height=2px;preserveAspectRatio=0:{}", PNG)).unwrap().unwrap();
+        let picture = scale(&decode(&image.data).unwrap(), image.size, (3, 2), (80, 24), (0, 0, 255), |r, g, b| u32::from_be_bytes([0, r, g, b]));
+
+        assert_eq!((picture.rows, picture.width, picture.height), (1, 4, 2));
+        assert_eq!(picture.pixels[0], 0xff0000);
+        assert_eq!(picture.pixels[3], 0x0000ff);
+        assert!(decode(b"text").is_err());
+    }
+
+    #[test]
+    fn placements() {
+        let size = Size { width: Extent::Cells(1), height: Extent::Cells(4), preserve_aspect: false };
+        let picture = |_: &RgbaImage, size: Size| scale(&RgbaImage::new(1, 1), size, (1, 1), (80, 24), (0, 0, 0), |_, _, _| 0);
+
+        let mut placements = Placements::default();
+
+        placements.push(RgbaImage::new(1, 1), size, picture(&RgbaImage::new(1, 1), size), (0, 2), false);
+        placements.push(RgbaImage::new(1, 1), size, picture(&RgbaImage::new(1, 1), size), (0, 20), false);
+        placements.push(RgbaImage::new(1, 1), size, picture(&RgbaImage::new(1, 1), size), (0, 2), true);
+
+        // a region from row 1 to 4 scrolls up twice, the first image loses the row pushed past the top margin
+
+        placements.shift((1, 4), 2, true, false);
+
+        let rows = placements.iter(false).map(|placement| placement.rows()).collect::<Vec<Range<i32>>>();
+
+        assert_eq!(rows, vec![1..4, 20..24]);
+        assert_eq!(placements.iter(true).next().map(Placement::rows), Some(2..6));
+
+        placements.shift((1, 4), 3, true, false);
+
+        assert_eq!(placements.iter(false).count(), 1);
+
+        placements.trim(19);
+
+        assert_eq!(placements.iter(false).next().map(Placement::rows), Some(1..5));
+
+        placements.trim(2);
+        placements.erase(2..3, true);
+
+        assert_eq!(placements.iter(false).count() + placements.iter(true).count(), 0);
+    }
+
+    #[test]
+    fn retention() {
+        let size = Size { width: Extent::Cells(1), height: Extent::Cells(4), preserve_aspect: false };
+        let picture = |_: &RgbaImage, size: Size| scale(&RgbaImage::new(1, 1), size, (1, 1), (80, 24), (0, 0, 0), |_, _, _| 0);
+
+        // 4096x4096 rgba is 64MiB, a second one pushes the first out but is kept itself
+
+        let mut placements = Placements::default();
+
+        placements.push(RgbaImage::new(1, 1), size, picture(&RgbaImage::new(1, 1), size), (0, 0), false);
+        placements.push(RgbaImage::new(4096, 4096), size, picture(&RgbaImage::new(1, 1), size), (0, 1), false);
+
+        assert_eq!(placements.iter(false).map(|placement| placement.y).collect::<Vec<i32>>(), vec![1]);
+
+        placements.push(RgbaImage::new(1, 1), size, picture(&RgbaImage::new(1, 1), size), (0, 50), false);
+
+        assert_eq!(placements.iter(false).map(|placement| placement.y).collect::<Vec<i32>>(), vec![50]);
+
+        // only the placements anchored in the given rows are scaled again
+
+        placements.push(RgbaImage::new(1, 1), size, picture(&RgbaImage::new(1, 1), size), (0, 2), false);
+        placements.rescale();
+
+        let taller = Size { height: Extent::Cells(6), ..size };
+
+        placements.refresh(0..10, false, |source, _| picture(source, taller));
+
+        let rows = placements.iter(false).map(|placement| placement.rows()).collect::<Vec<Range<i32>>>();
+
+        assert_eq!(rows, vec![50..54, 2..8]);
+
+        placements.refresh(40..60, false, |source, _| picture(source, taller));
+        placements.refresh(0..10, false, |_, _| unreachable!());
+
+        assert_eq!(placements.iter(false).next().map(Placement::rows), Some(50..56));
+    }
+
+    #[test]
+    fn graphics_attributes() {
+        let mut attributes = GraphicsAttributes::default();
+        let window = (800, 600);
+
+        assert_eq!(attributes.query(1, 1, &[], window), "\x1b[?1;0;256S");
+        assert_eq!(attributes.query(1, 3, &[4096], window), "\x1b[?1;0;1024S");
+        assert_eq!(attributes.query(1, 1, &[], window), "\x1b[?1;0;1024S");
+        assert_eq!(attributes.query(1, 2, &[], window), "\x1b[?1;0;256S");
+        assert_eq!(attributes.query(1, 3, &[], window), "\x1b[?1;3;0S");
+        assert_eq!(attributes.query(2, 1, &[], window), "\x1b[?2;0;800;600S");
+        assert_eq!(attributes.query(2, 3, &[1000, 300], window), "\x1b[?2;0;800;300S");
+        assert_eq!(attributes.query(2, 1, &[], (640, 480)), "\x1b[?2;0;640;300S");
+        assert_eq!(attributes.query(2, 4, &[], window), "\x1b[?2;0;800;600S");
+        assert_eq!(attributes.query(2, 5, &[], window), "\x1b[?2;2;0S");
+        assert_eq!(attributes.query(3, 1, &[], window), "\x1b[?3;1;0S");
+    }
+}
@@ -0,0 +1,349 @@
+// the termal window never runs headless, this is the entry point for embedders and the tests
+
+#![allow(dead_code)]
+
+use crate::xlib::Color;
+use crate::pty::PtyLike;
+use crate::config::{Config, UniColor};
+
+use x11::xft;
+use x11::xrender;
+use toml::Table;
+
+use std::io::{self, Read, Write, ErrorKind};
+use std::os::fd::{AsRawFd, RawFd};
+use std::ptr;
+use std::mem;
+
+use super::{Frontend, Terminal, Window};
+
+// cells of the headless frontend, there are no fonts to measure so every face gets the same size
+
+const CELL_WIDTH: i32 = 8;
+const CELL_HEIGHT: i32 = 16;
+const CELL_BASELINE: i32 = 12;
+
+
+pub struct Headless {
+    title: Option<Box<dyn FnMut(&str)>>,
+    copy: Option<Box<dyn FnMut(&str)>>,
+    selection: Option<String>,
+}
+
+impl Headless {
+    pub fn new(title: Option<Box<dyn FnMut(&str)>>, copy: Option<Box<dyn FnMut(&str)>>) -> Headless {
+        Headless {
+            title,
+            copy,
+            selection: None,
+        }
+    }
+
+    pub fn window(cols: usize, rows: usize) -> Window {
+        Window {
+            width: cols as u32 * CELL_WIDTH as u32,
+            height: rows as u32 * CELL_HEIGHT as u32,
+        }
+    }
+}
+
+impl Frontend for Headless {
+    fn resize_back_buffer(&mut self, _window: &Window) {}
+
+    fn flush(&mut self) {}
+
+    fn pending(&mut self) -> i32 {
+        0
+    }
+
+    // poll ignores negative descriptors
+
+    fn connection_number(&self) -> i32 {
+        -1
+    }
+
+    fn select_layout_events(&mut self) -> Option<i32> {
+        None
+    }
+
+    fn layout(&mut self) -> Option<String> {
+        None
+    }
+
+    fn set_urgency_hint(&mut self, _urgent: bool) {}
+
+    fn set_demands_attention(&mut self, _urgent: bool) {}
+
+    fn toggle_fullscreen(&mut self) {}
+
+    fn activate(&mut self) {}
+
+    fn map_window(&mut self) {}
+
+    fn resize_window(&mut self, _width: u32, _height: u32) {}
+
+    fn iconify(&mut self) {}
+
+    fn lookup_string(&mut self, _event: x11::xlib::XKeyEvent) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(String::new())
+    }
+
+    fn set_window_name(&mut self, name: &str) {
+        if let Some(title) = self.title.as_mut() {
+            title(name);
+        }
+    }
+
+    fn poll_event(&mut self) -> Option<Vec<x11::xlib::XEvent>> {
+        None
+    }
+
+    fn keycode_to_keysym(&mut self, _keycode: u8) -> u64 {
+        0
+    }
+
+    fn select_input(&mut self) {}
+
+    fn define_cursor(&mut self, _shape: u32) {}
+
+    fn copy_area(&mut self, _source: (i32, i32), _size: (u32, u32), _dest: (i32, i32)) {}
+
+    fn swap_buffers(&mut self, _window: &Window) {}
+
+    fn xft_draw_string(&mut self, _text: &str, _x: i32, _y: i32, _height: u32, _width: u32, _font: *mut xft::XftFont, _color: *const xft::XftColor) {}
+
+    fn xft_draw_string_clipped(&mut self, _text: &str, _x: i32, _y: i32, _clip: (i32, i32, u32, u32), _font: *mut xft::XftFont, _color: *const xft::XftColor) {}
+
+    fn xft_draw_glyphs(&mut self, _glyphs: &[(char, i32)], _y: i32, _clip: (i32, i32, u32, u32), _font: *mut xft::XftFont, _color: *const xft::XftColor) {}
+
+    fn xft_color_alloc_value(&self, rgb: Color) -> Result<xft::XftColor, Box<dyn std::error::Error>> {
+        let (r, g, b) = rgb.rgb();
+
+        let mut color: xft::XftColor = unsafe { mem::zeroed() };
+
+        color.pixel = self.pixel(rgb);
+        color.color = xrender::XRenderColor {
+            red: r as u16 * 257,
+            green: g as u16 * 257,
+            blue: b as u16 * 257,
+            alpha: 0xffff,
+        };
+
+        Ok(color)
+    }
+
+    // the faces are only ever handed back to the frontend, a null font is never looked into
+
+    fn load_font(&mut self, _font: &str) -> Result<*mut xft::XftFont, Box<dyn std::error::Error>> {
+        Ok(ptr::null_mut())
+    }
+
+    fn close_font(&mut self, _font: *mut xft::XftFont) {}
+
+    fn font_metrics(&self, _font: *mut xft::XftFont) -> (i32, i32, i32) {
+        (CELL_WIDTH, CELL_HEIGHT, CELL_BASELINE)
+    }
+
+    fn outline_rec(&mut self, _x: i32, _y: i32, _width: u32, _height: u32, _color: Color) {}
+
+    fn pixel(&self, color: Color) -> u64 {
+        color.encode()
+    }
+
+    fn draw_lines(&mut self, _points: &[(i32, i32)], _color: Color) {}
+
+    fn draw_image(&mut self, _pixels: &[u32], _size: (u32, u32), _source: (i32, u32), _x: i32, _y: i32) {}
+
+    fn draw_rec(&mut self, _x: i32, _y: i32, _width: u32, _height: u32, _color: Color) {}
+
+    fn clipboard(&mut self) -> Option<String> {
+        self.selection.clone()
+    }
+
+    fn set_clipboard(&mut self, text: String) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(copy) = self.copy.as_mut() {
+            copy(&text);
+        }
+
+        self.selection = Some(text);
+
+        Ok(())
+    }
+}
+
+// a pty for embedders without a child, whatever the screen replies is handed to the callback and nothing is ever read
+
+pub struct WriteBack {
+    write: Box<dyn FnMut(&[u8])>,
+}
+
+impl WriteBack {
+    pub fn new(write: impl FnMut(&[u8]) + 'static) -> WriteBack {
+        WriteBack {
+            write: Box::new(write),
+        }
+    }
+}
+
+impl PtyLike for WriteBack {
+    fn resize(&mut self, _width: u16, _height: u16) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+impl Read for WriteBack {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(ErrorKind::WouldBlock.into())
+    }
+}
+
+impl Write for WriteBack {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.write)(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for WriteBack {
+    fn as_raw_fd(&self) -> RawFd {
+        -1
+    }
+}
+
+// the terminal without the x11 frontend, for embedding the grid and parser elsewhere. bytes from the application go
+// in through Terminal::handle_bytes, replies come out through on_write or the given pty
+
+pub struct ScreenBuilder {
+    cols: usize,
+    rows: usize,
+    palette: Vec<Color>,
+    pty: Option<Box<dyn PtyLike>>,
+    title: Option<Box<dyn FnMut(&str)>>,
+    copy: Option<Box<dyn FnMut(&str)>>,
+}
+
+impl ScreenBuilder {
+    pub fn new(cols: usize, rows: usize) -> ScreenBuilder {
+        ScreenBuilder {
+            cols: cols.max(1),
+            rows: rows.max(1),
+            palette: Vec::new(),
+            pty: None,
+            title: None,
+            copy: None,
+        }
+    }
+
+    // replaces the first colors of the 16 color palette
+
+    pub fn palette(mut self, colors: &[Color]) -> ScreenBuilder {
+        self.palette = colors.iter().take(16).copied().collect();
+        self
+    }
+
+    pub fn pty(mut self, pty: Box<dyn PtyLike>) -> ScreenBuilder {
+        self.pty = Some(pty);
+        self
+    }
+
+    pub fn on_write(self, write: impl FnMut(&[u8]) + 'static) -> ScreenBuilder {
+        self.pty(Box::new(WriteBack::new(write)))
+    }
+
+    pub fn on_title(mut self, title: impl FnMut(&str) + 'static) -> ScreenBuilder {
+        self.title = Some(Box::new(title));
+        self
+    }
+
+    pub fn on_clipboard(mut self, copy: impl FnMut(&str) + 'static) -> ScreenBuilder {
+        self.copy = Some(Box::new(copy));
+        self
+    }
+
+    pub fn build(self) -> Result<Terminal, Box<dyn std::error::Error>> {
+        let display = Headless::new(self.title, self.copy);
+
+        // the defaults stand in for the config file, which belongs to the termal window and not the embedder
+
+        let mut config = Config::from_table(&display, &Table::new())?;
+
+        for (color, raw) in config.colors.iter_mut().zip(self.palette) {
+            *color = UniColor { raw, xft: display.xft_color_alloc_value(raw)? };
+        }
+
+        config.bell = String::from("none");
+
+        let pty = self.pty.unwrap_or_else(|| Box::new(WriteBack::new(|_| {})));
+
+        Terminal::assemble(Box::new(display), config, Headless::window(self.cols, self.rows), pty, Vec::new(), None)
+    }
+}
+
+// what a few full redraws allocate once the first frame has sized the buffers, the allocations test target counts it
+// with its own allocator and expects nothing
+
+pub fn frame_allocations(allocations: impl Fn() -> usize) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut terminal = ScreenBuilder::new(80, 24).build()?;
+
+    terminal.handle_bytes("\x1b[1;31mhello\x1b[0m \x1b[4mworld\x1b[0m \x1b[2mfaint\x1b[0m\r\n中".as_bytes())?;
+    terminal.screen.draw()?;
+
+    let before = allocations();
+
+    for _ in 0..4 {
+        terminal.screen.full_dirt();
+        terminal.screen.draw()?;
+    }
+
+    Ok(allocations() - before)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::keybind::Command;
+    use crate::terminal::Position;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn callbacks() {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let titles = Rc::new(RefCell::new(Vec::new()));
+        let copies = Rc::new(RefCell::new(Vec::new()));
+
+        let mut terminal = ScreenBuilder::new(20, 5)
+            .palette(&[Color::new(1, 2, 3)])
+            .on_write({ let written = written.clone(); move |bytes: &[u8]| written.borrow_mut().extend_from_slice(bytes) })
+            .on_title({ let titles = titles.clone(); move |title: &str| titles.borrow_mut().push(title.to_string()) })
+            .on_clipboard({ let copies = copies.clone(); move |text: &str| copies.borrow_mut().push(text.to_string()) })
+            .build()
+            .unwrap();
+
+        assert_eq!((terminal.screen.cols, terminal.screen.rows), (20, 5));
+        assert_eq!(terminal.screen.config.colors[0].raw, Color::new(1, 2, 3));
+
+        terminal.handle_bytes(b"hello\x1b]2;embedded\x07\x1b[5n").unwrap();
+
+        assert_eq!(titles.borrow().last().map(String::as_str), Some("embedded"));
+        assert_eq!(written.take(), b"\x1b[0n");
+
+        // a copy goes out through the callback and is what a paste reads back
+
+        terminal.screen.selection.start = Position { x: 0, y: 0 };
+        terminal.screen.selection.end = Position { x: 5, y: 0 };
+
+        terminal.screen.run_command(Command::Copy).unwrap();
+        terminal.screen.run_command(Command::PasteRaw).unwrap();
+
+        assert_eq!(*copies.borrow(), ["hello"]);
+        assert_eq!(written.take(), b"hello");
+    }
+}
@@ -0,0 +1,205 @@
+use super::palette::{self, MAX_ENTRIES};
+
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::env;
+
+
+const MAX_COMMANDS: usize = 10000;
+
+// command lines read back from the screen between the OSC 133 B and C marks, one per line in the history file. the file is
+// only readable by the user, commands can hold secrets, and is cut back to MAX_COMMANDS once it holds twice that many
+
+pub struct CommandHistory {
+    pub open: bool,
+    pub query: String,
+    pub selected: usize,
+    commands: Vec<String>,
+    unique: Vec<usize>,
+    entries: RefCell<Option<(String, Vec<usize>)>>,
+    lines: usize,
+    path: Option<PathBuf>,
+}
+
+impl CommandHistory {
+    pub fn load(enabled: bool) -> CommandHistory {
+        let path = enabled.then(path).flatten();
+
+        let mut commands = path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|content| content.lines().map(|line| line.to_string()).collect::<Vec<String>>())
+            .unwrap_or_default();
+
+        let lines = commands.len();
+
+        commands.drain(..commands.len().saturating_sub(MAX_COMMANDS));
+
+        let mut history = CommandHistory {
+            open: false,
+            query: String::new(),
+            selected: 0,
+            unique: Vec::new(),
+            entries: RefCell::new(None),
+            commands,
+            lines,
+            path,
+        };
+
+        history.dedup();
+
+        history
+    }
+
+    pub fn record(&mut self, command: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(path) = self.path.as_ref() else { return Ok(()) };
+
+        if command.is_empty() || command.contains('\n') || self.commands.last().is_some_and(|last| last == command) {
+            return Ok(());
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        writeln!(OpenOptions::new().create(true).append(true).mode(0o600).open(path)?, "{}", command)?;
+
+        self.commands.push(command.to_string());
+        self.lines += 1;
+
+        if self.commands.len() > MAX_COMMANDS {
+            self.commands.drain(..self.commands.len() - MAX_COMMANDS);
+        }
+
+        if self.lines > MAX_COMMANDS * 2 {
+            rewrite(path, &self.commands)?;
+
+            self.lines = self.commands.len();
+        }
+
+        self.dedup();
+
+        Ok(())
+    }
+
+    // the newest run of every command, oldest first
+
+    fn dedup(&mut self) {
+        let mut seen = HashSet::new();
+
+        self.unique = (0..self.commands.len()).rev()
+            .filter(|index| seen.insert(self.commands[*index].as_str()))
+            .collect();
+
+        self.unique.reverse();
+
+        self.entries.replace(None);
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.query.clear();
+        self.selected = 0;
+    }
+
+    // newest first, a command that was run several times only shows up once. the result is kept until the query or the
+    // commands change, it is asked for on every key and every frame
+
+    pub fn entries(&self) -> Vec<&str> {
+        let mut cache = self.entries.borrow_mut();
+
+        if !cache.as_ref().is_some_and(|(query, _)| *query == self.query) {
+            let mut entries = self.unique.iter()
+                .rev()
+                .filter_map(|index| palette::fuzzy_score(&self.query, &self.commands[*index]).map(|score| (score, *index)))
+                .collect::<Vec<(i32, usize)>>();
+
+            entries.sort_by_key(|entry| Reverse(entry.0));
+
+            *cache = Some((self.query.clone(), entries.into_iter().take(MAX_ENTRIES).map(|(_, index)| index).collect()));
+        }
+
+        cache.as_ref().map_or(Vec::new(), |(_, entries)| entries.iter().map(|index| self.commands[*index].as_str()).collect())
+    }
+
+    pub fn select_next(&mut self, count: usize) {
+        if count > 0 {
+            self.selected = (self.selected + 1) % count;
+        }
+    }
+
+    pub fn select_prev(&mut self, count: usize) {
+        if count > 0 {
+            self.selected = (self.selected + count - 1) % count;
+        }
+    }
+}
+
+pub fn path() -> Option<PathBuf> {
+    let state = env::var("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .ok()?;
+
+    Some(state.join("termal/history"))
+}
+
+// written next to the history and renamed over it, a crash halfway leaves the old file
+
+fn rewrite(path: &Path, commands: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let temporary = path.with_extension("tmp");
+
+    let mut file = OpenOptions::new().create(true).write(true).truncate(true).mode(0o600).open(&temporary)?;
+
+    for command in commands {
+        writeln!(file, "{}", command)?;
+    }
+
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let mut history = CommandHistory::load(false);
+
+        history.commands = ["cargo build", "ls -la", "cargo test", "cargo build"].map(String::from).to_vec();
+        history.dedup();
+
+        assert_eq!(history.entries(), vec!["cargo build", "cargo test", "ls -la"]);
+
+        history.query = String::from("ct");
+
+        assert_eq!(history.entries(), vec!["cargo test"]);
+    }
+
+    #[test]
+    fn rotation() {
+        let path = env::temp_dir().join(format!("termal-history-{}", std::process::id()));
+
+        let mut history = CommandHistory::load(false);
+
+        history.path = Some(path.clone());
+        history.lines = MAX_COMMANDS * 2;
+        history.commands = (0..MAX_COMMANDS).map(|command| command.to_string()).collect();
+
+        history.record("last").unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+
+        assert_eq!(content.lines().count(), MAX_COMMANDS);
+        assert_eq!(content.lines().last(), Some("last"));
+        assert_eq!(history.entries().first(), Some(&"last"));
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::xlib;
+
+use x11::keysym;
+
+
+// https://sw.kovidgoyal.net/kitty/keyboard-protocol, alternate keys and associated text are not reported
+
+pub const DISAMBIGUATE: u16 = 1;
+pub const EVENT_TYPES: u16 = 2;
+pub const ALL_KEYS: u16 = 8;
+pub const SUPPORTED: u16 = DISAMBIGUATE | EVENT_TYPES | ALL_KEYS;
+
+pub const MAX_STACK: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyEvent {
+    Press,
+    Repeat,
+    Release,
+}
+
+// keys without text, as the number and final byte of their escape code
+
+fn functional(keysym: u32) -> Option<(u32, char)> {
+    match keysym {
+        keysym::XK_Escape => Some((27, 'u')),
+        keysym::XK_Return => Some((13, 'u')),
+        keysym::XK_Tab | keysym::XK_ISO_Left_Tab => Some((9, 'u')),
+        keysym::XK_BackSpace => Some((127, 'u')),
+        keysym::XK_KP_Enter => Some((57414, 'u')),
+        keysym::XK_Insert => Some((2, '~')),
+        keysym::XK_Delete => Some((3, '~')),
+        keysym::XK_Prior => Some((5, '~')),
+        keysym::XK_Next => Some((6, '~')),
+        keysym::XK_Up => Some((1, 'A')),
+        keysym::XK_Down => Some((1, 'B')),
+        keysym::XK_Right => Some((1, 'C')),
+        keysym::XK_Left => Some((1, 'D')),
+        keysym::XK_End => Some((1, 'F')),
+        keysym::XK_Home => Some((1, 'H')),
+        keysym::XK_F1 => Some((1, 'P')),
+        keysym::XK_F2 => Some((1, 'Q')),
+        keysym::XK_F3 => Some((13, '~')),
+        keysym::XK_F4 => Some((1, 'S')),
+        keysym::XK_F5 => Some((15, '~')),
+        keysym::XK_F6 => Some((17, '~')),
+        keysym::XK_F7 => Some((18, '~')),
+        keysym::XK_F8 => Some((19, '~')),
+        keysym::XK_F9 => Some((20, '~')),
+        keysym::XK_F10 => Some((21, '~')),
+        keysym::XK_F11 => Some((23, '~')),
+        keysym::XK_F12 => Some((24, '~')),
+        keysym::XK_Shift_L => Some((57441, 'u')),
+        keysym::XK_Control_L => Some((57442, 'u')),
+        keysym::XK_Alt_L => Some((57443, 'u')),
+        keysym::XK_Super_L => Some((57444, 'u')),
+        keysym::XK_Shift_R => Some((57447, 'u')),
+        keysym::XK_Control_R => Some((57448, 'u')),
+        keysym::XK_Alt_R => Some((57449, 'u')),
+        keysym::XK_Super_R => Some((57450, 'u')),
+        _ => None,
+    }
+}
+
+// None leaves the key to the legacy encoding, enter, tab and backspace stay legacy unless modified so a shell is still usable after a crash
+
+pub fn sequence(keysym: u32, state: u32, flags: u16, event: KeyEvent) -> Option<String> {
+    let (number, last) = functional(keysym).or_else(|| xlib::keysym_char(keysym).map(|c| (c as u32, 'u')))?;
+
+    let modifiers = (state & x11::xlib::ShiftMask != 0) as u32
+        | ((state & x11::xlib::Mod1Mask != 0) as u32) << 1
+        | ((state & x11::xlib::ControlMask != 0) as u32) << 2
+        | ((state & x11::xlib::Mod4Mask != 0) as u32) << 3;
+
+    let text = functional(keysym).is_none();
+    let modifier_key = (57441..=57452).contains(&number);
+
+    let report = if flags & ALL_KEYS != 0 {
+        true
+    } else if text {
+        flags & DISAMBIGUATE != 0 && modifiers & !1 != 0
+    } else if matches!(number, 13 | 9 | 127) && last == 'u' {
+        modifiers != 0
+    } else {
+        !modifier_key
+    };
+
+    if !report || (event == KeyEvent::Release && flags & EVENT_TYPES == 0) {
+        return None;
+    }
+
+    let event = match event {
+        KeyEvent::Repeat if flags & EVENT_TYPES != 0 => ":2",
+        KeyEvent::Release => ":3",
+        _ => "",
+    };
+
+    if modifiers == 0 && event.is_empty() {
+        Some(match last {
+            'u' | '~' => format!("\x1b[{}{}", number, last),
+            _ => format!("\x1b[{}", last),
+        })
+    } else {
+        Some(format!("\x1b[{};{}{}{}", number, modifiers + 1, event, last))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences() {
+        let ctrl = x11::xlib::ControlMask;
+        let shift = x11::xlib::ShiftMask;
+
+        assert_eq!(sequence(keysym::XK_a, 0, DISAMBIGUATE, KeyEvent::Press), None);
+        assert_eq!(sequence(keysym::XK_a, shift, DISAMBIGUATE, KeyEvent::Press), None);
+        assert_eq!(sequence(keysym::XK_a, ctrl, DISAMBIGUATE, KeyEvent::Press).as_deref(), Some("\x1b[97;5u"));
+        assert_eq!(sequence(keysym::XK_Escape, 0, DISAMBIGUATE, KeyEvent::Press).as_deref(), Some("\x1b[27u"));
+        assert_eq!(sequence(keysym::XK_Return, 0, DISAMBIGUATE, KeyEvent::Press), None);
+        assert_eq!(sequence(keysym::XK_Return, shift, DISAMBIGUATE, KeyEvent::Press).as_deref(), Some("\x1b[13;2u"));
+        assert_eq!(sequence(keysym::XK_Up, 0, DISAMBIGUATE, KeyEvent::Press).as_deref(), Some("\x1b[A"));
+        assert_eq!(sequence(keysym::XK_F5, ctrl, DISAMBIGUATE, KeyEvent::Press).as_deref(), Some("\x1b[15;5~"));
+        assert_eq!(sequence(keysym::XK_Shift_L, shift, DISAMBIGUATE, KeyEvent::Press), None);
+
+        assert_eq!(sequence(keysym::XK_a, 0, ALL_KEYS, KeyEvent::Press).as_deref(), Some("\x1b[97u"));
+        assert_eq!(sequence(keysym::XK_a, 0, ALL_KEYS | EVENT_TYPES, KeyEvent::Release).as_deref(), Some("\x1b[97;1:3u"));
+        assert_eq!(sequence(keysym::XK_Left, ctrl, DISAMBIGUATE | EVENT_TYPES, KeyEvent::Repeat).as_deref(), Some("\x1b[1;5:2D"));
+        assert_eq!(sequence(keysym::XK_Left, ctrl, DISAMBIGUATE, KeyEvent::Repeat).as_deref(), Some("\x1b[1;5D"));
+        assert_eq!(sequence(keysym::XK_Left, 0, DISAMBIGUATE, KeyEvent::Release), None);
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::{HashMap, HashSet};
+
+// the first collection runs once this many links are interned, the next ones once the live links doubled
+
+const COLLECT_AT: usize = 256;
+
+
+// https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feaa, OSC 8 uris are interned and cells hold their id.
+// ids are never handed out twice, a cell the collection missed loses its link instead of pointing at another one
+
+pub struct Links {
+    ids: HashMap<String, u32>,
+    uris: HashMap<u32, String>,
+    next: u32,
+    collect_at: usize,
+}
+
+impl Default for Links {
+    fn default() -> Links {
+        Links {
+            ids: HashMap::new(),
+            uris: HashMap::new(),
+            next: 1,
+            collect_at: COLLECT_AT,
+        }
+    }
+}
+
+impl Links {
+    pub fn intern(&mut self, uri: String) -> u32 {
+        if let Some(id) = self.ids.get(&uri) {
+            return *id;
+        }
+
+        let id = self.next;
+
+        self.next = self.next.wrapping_add(1).max(1);
+
+        self.uris.insert(id, uri.clone());
+        self.ids.insert(uri, id);
+
+        id
+    }
+
+    pub fn get(&self, id: u32) -> Option<&str> {
+        self.uris.get(&id).map(|uri| uri.as_str())
+    }
+
+    // true once enough links piled up that the ones no cell uses anymore should be collected
+
+    pub fn full(&self) -> bool {
+        self.ids.len() >= self.collect_at
+    }
+
+    // used is every id still held by a cell or an attribute, the rest is dropped
+
+    pub fn collect(&mut self, used: impl IntoIterator<Item = u32>) {
+        let used = used.into_iter().collect::<HashSet<u32>>();
+
+        self.ids.retain(|_, id| used.contains(id));
+        self.uris.retain(|id, _| used.contains(id));
+
+        self.collect_at = COLLECT_AT.max(self.ids.len() * 2);
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.uris.clear();
+
+        self.collect_at = COLLECT_AT;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collection() {
+        let mut links = Links::default();
+
+        let first = links.intern(String::from("https://example.com"));
+        let second = links.intern(String::from("https://example.org"));
+
+        assert_eq!(links.intern(String::from("https://example.com")), first);
+        assert_eq!(links.get(second), Some("https://example.org"));
+
+        for n in 0..COLLECT_AT {
+            links.intern(format!("https://example.com/{}", n));
+        }
+
+        assert!(links.full());
+
+        links.collect([second]);
+
+        assert!(!links.full());
+        assert_eq!(links.get(first), None);
+        assert_eq!(links.get(second), Some("https://example.org"));
+
+        // a collected uri comes back with a new id
+
+        assert!(links.intern(String::from("https://example.com")) > second);
+
+        links.clear();
+
+        assert_eq!(links.get(second), None);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::keybind::Command;
+
+
+pub const ENTRIES: [(Command, &str); 5] = [
+    (Command::Copy, "Copy"),
+    (Command::Paste, "Paste"),
+    (Command::OpenUrl, "Open URL"),
+    (Command::SearchSelection, "Search selection"),
+    (Command::Fullscreen, "Toggle fullscreen"),
+];
+
+// the menu is positioned in pixels, it opens at the pointer but is kept inside the window
+
+pub struct Menu {
+    pub open: bool,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub entry_height: i32,
+    pub selected: Option<usize>,
+}
+
+impl Menu {
+    pub fn new() -> Menu {
+        Menu {
+            open: false,
+            x: 0,
+            y: 0,
+            width: 0,
+            entry_height: 0,
+            selected: None,
+        }
+    }
+
+    pub fn open_at(&mut self, x: i32, y: i32, window: (i32, i32), cell: (i32, i32)) {
+        let columns = ENTRIES.iter().map(|(_, label)| label.len()).max().unwrap_or_default() as i32 + 2;
+
+        self.width = columns * cell.0;
+        self.entry_height = cell.1;
+        self.x = x.min(window.0 - self.width).max(0);
+        self.y = y.min(window.1 - self.height()).max(0);
+        self.selected = None;
+        self.open = true;
+    }
+
+    pub fn height(&self) -> i32 {
+        ENTRIES.len() as i32 * self.entry_height
+    }
+
+    pub fn entry_at(&self, x: i32, y: i32) -> Option<usize> {
+        let inside = (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height()).contains(&y);
+
+        inside.then(|| ((y - self.y) / self.entry_height) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placement() {
+        let mut menu = Menu::new();
+
+        menu.open_at(790, 10, (800, 600), (10, 20));
+
+        assert_eq!((menu.x, menu.y, menu.width), (800 - 190, 10, 190));
+        assert_eq!(menu.entry_at(menu.x, 10), Some(0));
+        assert_eq!(menu.entry_at(menu.x + 5, 10 + 20 * 4 + 19), Some(4));
+        assert_eq!(menu.entry_at(menu.x - 1, 10), None);
+        assert_eq!(menu.entry_at(menu.x, 10 + 20 * 5), None);
+    }
+}
@@ -1,29 +1,109 @@
+mod palette;
+mod history;
+mod search;
+mod dirty;
+mod paste;
+mod charset;
+mod title;
+mod menu;
+mod url;
+mod termcap;
+mod kitty;
+mod width;
+mod audio;
+mod graphics;
+mod printer;
+mod notification;
+mod links;
+mod frontend;
+pub mod headless;
+
 use crate::escape::{Parser, Action};
-use crate::config::{self, Config};
-use crate::pty::Pty;
+use crate::config::{self, Config, Urgency};
+use crate::keybind::{self, Command};
+use crate::pty::{Pty, PtyLike, Reader};
 use crate::xlib;
+use crate::a11y::Accessibility;
+use crate::ipc::{self, Ipc};
 
-use rodio::{Decoder, OutputStream, OutputStreamHandle, source::Source};
 use nix::libc;
-use arboard::Clipboard;
+use base64::Engine;
+use image::RgbaImage;
 
 use std::io::{self, Read, ErrorKind, Write};
 use std::time::{Duration, Instant};
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut, Range};
 use std::os::fd::AsRawFd;
-use std::sync::Arc;
-use std::fs::File;
+use std::process;
 use std::thread;
-
+use std::env;
+
+use palette::Palette;
+use history::CommandHistory;
+use search::Search;
+use dirty::Dirty;
+use charset::Charset;
+use menu::Menu;
+use audio::Audio;
+use graphics::{Placements, Picture, GraphicsAttributes};
+use printer::Printer;
+use notification::Notifier;
+use links::Links;
+
+pub use frontend::Frontend;
+
+
+const SCROLL_LINES: i32 = 3;
+const SCROLL_REPEATS_PER_STEP: u32 = 4;
+const MAX_TITLES: usize = 10;
+const MAX_PASSTHROUGH: usize = 4;
+const MAX_USER_VARS: usize = 64;
+const MAX_USER_VAR_BYTES: usize = 4096;
+
+const BLINK_INTERVAL: Duration = Duration::from_millis(500);
+const SCROLL_INDICATOR_TIMEOUT: Duration = Duration::from_millis(1500);
+const SYNC_TIMEOUT: Duration = Duration::from_millis(150);
+const IDLE_GRACE: Duration = Duration::from_secs(30);
+
+// SGR 7 is kept as a flag and only applied when drawing, the colors it swaps can still change underneath it
+const INVERSE: u16 = 1 << 0;
+const STRIKETHROUGH: u16 = 1 << 1;
+const BOLD: u16 = 1 << 2;
+const ITALIC: u16 = 1 << 3;
+const FAINT: u16 = 1 << 4;
+const BLINK: u16 = 1 << 5;
+
+// a wide character takes up two cells, the second one is a spacer that is drawn together with the first
+const WIDE: u16 = 1 << 6;
+const WIDE_SPACER: u16 = 1 << 7;
+const CONCEAL: u16 = 1 << 8;
 
 struct Cell {
     width: i32,
     height: i32,
+    baseline: i32,
+}
+
+impl Cell {
+    fn from_font(display: &dyn Frontend, font: *mut x11::xft::XftFont) -> Cell {
+        let (width, height, baseline) = display.font_metrics(font);
+
+        Cell {
+            width: width.max(1),
+            height: height.max(1),
+            baseline,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
 struct Cursor {
     position: Position,
     save: Position,
+    save_charsets: ([Charset; 2], usize),
 }
 
 #[derive(Debug)]
@@ -34,6 +114,62 @@ pub struct Window {
 
 struct Xft {
     font: *mut x11::xft::XftFont,
+    bold: *mut x11::xft::XftFont,
+    italic: *mut x11::xft::XftFont,
+    bold_italic: *mut x11::xft::XftFont,
+    double: *mut x11::xft::XftFont,
+}
+
+impl Xft {
+    fn load(display: &mut dyn Frontend, font: &str) -> Result<Xft, Box<dyn std::error::Error>> {
+        // fontconfig falls back to the closest match, so fonts without a bold or italic face still load
+
+        Ok(Xft {
+            font: display.load_font(font)?,
+            bold: display.load_font(&config::font_with_style(font, "bold"))?,
+            italic: display.load_font(&config::font_with_style(font, "italic"))?,
+            bold_italic: display.load_font(&config::font_with_style(font, "bold:italic"))?,
+            double: display.load_font(&config::font_with_size(font, config::font_size(font) * 2.0))?,
+        })
+    }
+
+    fn close(&self, display: &mut dyn Frontend) {
+        display.close_font(self.font);
+        display.close_font(self.bold);
+        display.close_font(self.italic);
+        display.close_font(self.bold_italic);
+        display.close_font(self.double);
+    }
+
+    fn face(&self, flags: u16) -> *mut x11::xft::XftFont {
+        match (flags & BOLD != 0, flags & ITALIC != 0) {
+            (false, false) => self.font,
+            (true, false) => self.bold,
+            (false, true) => self.italic,
+            (true, true) => self.bold_italic,
+        }
+    }
+}
+
+// neighbouring cells drawn in the same face and color, start and end span their cells in pixels and clip the glyphs.
+// the glyphs are a range into one buffer shared by the whole row
+
+struct GlyphRun {
+    face: *mut x11::xft::XftFont,
+    fg: xlib::Color,
+    color: x11::xft::XftColor,
+    start: i32,
+    end: i32,
+    glyphs: Range<usize>,
+}
+
+// the buffers draw fills for every row, kept between frames so a redraw doesn't allocate
+
+#[derive(Default)]
+struct Runs {
+    runs: Vec<Option<GlyphRun>>,
+    glyphs: Vec<(char, i32)>,
+    drawn: Vec<(usize, usize, Attribute)>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -49,41 +185,57 @@ struct Selection {
     selecting: bool,
 }
 
-struct Sound {
-    data: Arc<Vec<u8>>
-}
-
-impl AsRef<[u8]> for Sound {
-    fn as_ref(&self) -> &[u8] {
-        &self.data
-    }
+#[derive(Clone, Copy, PartialEq, Hash)]
+enum Underline {
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
 }
 
-impl Sound {
-    pub fn load(file: &str) -> Result<Sound, Box<dyn std::error::Error>> {
-        let mut bell: Vec<u8> = Vec::new();
-        File::open(file)?.read_to_end(&mut bell)?;
-
-        Ok(Sound {
-            data: Arc::new(bell),
-        })
-    }
-
-    pub fn decoder(&self) -> Result<Decoder<io::Cursor<Sound>>, Box<dyn std::error::Error>> {
-        Ok(Decoder::new(io::Cursor::new(Sound { data: self.data.clone(), }))?)
+impl Underline {
+    fn from_style(style: u16) -> Underline {
+        match style {
+            0 => Underline::None,
+            2 => Underline::Double,
+            3 => Underline::Curly,
+            4 => Underline::Dotted,
+            5 => Underline::Dashed,
+            _ => Underline::Single,
+        }
     }
 }
 
-struct Audio {
-    _stream: OutputStream,
-    stream_handle: OutputStreamHandle,
-    bell: Sound,
-}
-
 #[derive(Clone, Copy, PartialEq)]
 struct Attribute {
     fg: config::UniColor,
     bg: config::UniColor,
+    flags: u16,
+    underline: Underline,
+    underline_color: Option<config::UniColor>,
+    link: u32,
+}
+
+impl Attribute {
+    fn new(config: &Config) -> Attribute {
+        Attribute {
+            fg: config.fg,
+            bg: config.bg,
+            flags: 0,
+            underline: Underline::None,
+            underline_color: None,
+            link: 0,
+        }
+    }
+
+    // underline and strikethrough default to the color the text is drawn in once resolved. an SGR 58 color only
+    // replaces the underline and is left alone by SGR 7 and the selection, like in xterm
+
+    fn decorations(&self) -> (xlib::Color, xlib::Color) {
+        (self.underline_color.map_or(self.fg.raw, |color| color.raw), self.fg.raw)
+    }
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -92,6 +244,24 @@ struct Character {
     byte: char,
 }
 
+impl Character {
+    fn describe(&self) -> Vec<String> {
+        let mut utf8 = [0; 4];
+
+        let bytes = self.byte.encode_utf8(&mut utf8).bytes()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        vec![
+            format!("U+{:04X} {:?}", self.byte as u32, self.byte),
+            format!("utf-8: {}", bytes),
+            format!("fg: {}", self.attr.fg.raw.hex()),
+            format!("bg: {}", self.attr.bg.raw.hex()),
+        ]
+    }
+}
+
 impl std::fmt::Debug for Character {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         fmt.write_str(&self.byte.to_string())?;
@@ -100,10 +270,111 @@ impl std::fmt::Debug for Character {
     }
 }
 
+// what happens to OSC 8 hyperlinks when the selection is copied
+
+#[derive(Clone, Copy, PartialEq)]
+enum LinkCopy {
+    Text,
+    Uri,
+    Both,
+}
+
+// https://gitlab.freedesktop.org/Per_Bothner/specifications/blob/master/proposals/semantic-prompts.md
+
+#[derive(Clone, Copy, Default)]
+struct Marks {
+    prompt: bool,
+    output: bool,
+    end: bool,
+}
+
+// https://vt100.net/docs/vt510-rm/DECDHL.html, a double height line is drawn as two rows that each show one half of the glyphs
+
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Default)]
+enum LineSize {
+    #[default]
+    Single,
+    DoubleWidth,
+    DoubleTop,
+    DoubleBottom,
+}
+
+#[derive(Clone)]
+struct Line {
+    cells: Vec<Character>,
+    marks: Marks,
+    size: LineSize,
+    hash: std::cell::Cell<Option<u64>>,
+}
+
+impl Line {
+    fn new(character: Character, width: usize) -> Line {
+        Line {
+            cells: vec![character; width],
+            marks: Marks::default(),
+            size: LineSize::Single,
+            hash: std::cell::Cell::new(None),
+        }
+    }
+
+    // covers everything that ends up on screen, two lines with the same hash draw the same pixels.
+    // the hash is cached until the cells are borrowed mutably
+
+    fn content_hash(&self) -> u64 {
+        if let Some(hash) = self.hash.get() {
+            return hash;
+        }
+
+        let mut hasher = DefaultHasher::new();
+
+        self.size.hash(&mut hasher);
+
+        for character in self.cells.iter() {
+            character.byte.hash(&mut hasher);
+            character.attr.fg.raw.encode().hash(&mut hasher);
+            character.attr.bg.raw.encode().hash(&mut hasher);
+            character.attr.flags.hash(&mut hasher);
+            character.attr.underline.hash(&mut hasher);
+            character.attr.underline_color.map(|color| color.raw.encode()).hash(&mut hasher);
+        }
+
+        self.hash.set(Some(hasher.finish()));
+
+        hasher.finish()
+    }
+
+    fn set_size(&mut self, size: LineSize) {
+        self.size = size;
+        self.hash.set(None);
+    }
+
+    fn scale(&self) -> usize {
+        if self.size == LineSize::Single { 1 } else { 2 }
+    }
+}
+
+impl Deref for Line {
+    type Target = Vec<Character>;
+
+    fn deref(&self) -> &Vec<Character> {
+        &self.cells
+    }
+}
+
+impl DerefMut for Line {
+    fn deref_mut(&mut self) -> &mut Vec<Character> {
+        self.hash.set(None);
+
+        &mut self.cells
+    }
+}
+
 #[derive(Debug)]
 struct ScrollingRegion {
     top: usize,
     bottom: usize,
+    left: usize,
+    right: usize,
 }
 
 #[derive(Clone, Copy)]
@@ -116,8 +387,41 @@ struct Mode {
     decalt: bool,
     decpaste: bool,
     decfocus: bool,
+    decnm: bool,
     decmm: bool,
     decdm: bool,
+    decum: bool,
+    decrm: bool,
+    decas: bool,
+    att610: bool,
+    decawm: bool,
+    deckpam: bool,
+    declrmm: bool,
+}
+
+impl Mode {
+    fn new() -> Mode {
+        Mode {
+            decim: false,
+            decom: false,
+            decscnm: false,
+            decckm: false,
+            dectecm: true,
+            decalt: false,
+            decpaste: false,
+            decfocus: false,
+            decnm: false,
+            decmm: false,
+            decdm: false,
+            decum: false,
+            decrm: false,
+            decas: true,
+            att610: false,
+            decawm: true,
+            deckpam: false,
+            declrmm: false,
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -129,18 +433,15 @@ enum CursorStyle {
 
 #[derive(Clone)]
 struct AltScreen {
-    buf: Vec<Vec<Character>>,
+    buf: Vec<Line>,
     attr: Attribute,
     mode: Mode,
     cursor: Cursor,
 }
 
 impl AltScreen {
-    pub fn new(config: &Config, width: usize, height: usize) -> AltScreen {
-        let attr = Attribute {
-            fg: config.fg,
-            bg: config.bg,
-        };
+    pub fn new(config: &Config, cols: usize, rows: usize) -> AltScreen {
+        let attr = Attribute::new(config);
 
         AltScreen {
             cursor: Cursor {
@@ -152,21 +453,11 @@ impl AltScreen {
                     x: 0,
                     y: 0,
                 },
+                save_charsets: ([Charset::Ascii; 2], 0),
             },
             attr,
-            mode: Mode {
-                decim: false,
-                decom: false,
-                decscnm: false,
-                decckm: false,
-                dectecm: true,
-                decalt: false,
-                decpaste: false,
-                decfocus: false,
-                decmm: false,
-                decdm: false,
-            },
-            buf: vec![vec![Character { attr, byte: ' ' }; (width / 10) + 1]; (height / 20) + 1],
+            mode: Mode::new(),
+            buf: vec![Line::new(Character { attr, byte: ' ' }, cols + 1); rows + 1],
         }
     }
 }
@@ -175,6 +466,7 @@ impl AltScreen {
 enum Buttons {
     Button1,
     Button2,
+    Button3,
     ScrollUp,
     ScrollDown,
     None,
@@ -186,6 +478,7 @@ impl Buttons {
         match self {
             Buttons::Button1 => 0,
             Buttons::Button2 => 1,
+            Buttons::Button3 => 2,
             Buttons::ScrollUp => 64,
             Buttons::ScrollDown => 65,
             Buttons::None => 0,
@@ -194,10 +487,12 @@ impl Buttons {
 }
 
 pub struct Screen {
-    display: xlib::Display,
+    display: Box<dyn Frontend>,
     selection: Selection,
     cursor: Cursor,
     window: Window,
+    cols: usize,
+    rows: usize,
     config: Config,
     buttons: Buttons,
     audio: Audio,
@@ -205,14 +500,62 @@ pub struct Screen {
     cell: Cell,
     mode: Mode,
     xft: Xft,
-    pty: Pty,
+    pty: Box<dyn PtyLike>,
+    palette: Palette,
+    command_history: CommandHistory,
+    command_start: Option<Position>,
+    search: Search,
+    menu: Menu,
+    a11y: Option<Accessibility>,
+    ipc: Vec<Ipc>,
+    pointer: Position,
     cursor_style: CursorStyle,
     scrolling_region: ScrollingRegion,
-    clipboard: Clipboard,
-    buf: Vec<Vec<Character>>,
+    buf: Vec<Line>,
+    history: VecDeque<Line>,
     alt: AltScreen,
-    dirty: Vec<Vec<bool>>,
+    dirty: Dirty,
+    hashes: Vec<u64>,
     tabs: Vec<bool>,
+    font_size: f64,
+    scale: u32,
+    scroll: usize,
+    last_notification: Option<Instant>,
+    notifier: Option<Notifier>,
+    user_vars: HashMap<String, String>,
+    images: Placements,
+    graphics: GraphicsAttributes,
+    image_rows: Vec<usize>,
+    app_title: Option<String>,
+    titles: Vec<Option<String>>,
+    semantic_prompts: bool,
+    links: Links,
+    faint: std::cell::RefCell<HashMap<u64, x11::xft::XftColor>>,
+    runs: Runs,
+    blit: Option<(usize, usize, i32)>,
+    last_blink: Instant,
+    last_activity: Instant,
+    last_frame: Instant,
+    idle_since: Instant,
+    idle_warning: Option<Instant>,
+    blink_visible: bool,
+    blinking: bool,
+    xkb_event: Option<i32>,
+    scroll_indicator: Option<Instant>,
+    synchronized: Option<Instant>,
+    other_keys: u16,
+    keyboard: Vec<u16>,
+    keys_down: Vec<u32>,
+    binding_repeat: Option<(u32, u32)>,
+    urgent: bool,
+    wrap_pending: Option<Position>,
+    last_printed: Option<char>,
+    auto_print: bool,
+    printer: Option<Printer>,
+    charsets: [Charset; 2],
+    shift: usize,
+    layout: Option<String>,
+    inspect: bool,
     refresh: bool,
     focused: bool,
     scroll_set: bool,
@@ -222,6 +565,7 @@ pub struct Screen {
 pub struct Terminal {
     parser: Parser,
     screen: Screen,
+    reader: Option<Reader>,
 }
 
 impl Screen {
@@ -229,46 +573,237 @@ impl Screen {
         // https://www.vt100.net/docs/vt510-rm/IRM.html
         // println!("[print] y={}, x={}, character={:?}", self.cursor.position.y, self.cursor.position.x, c);
 
+        if self.attr.flags & BLINK != 0 {
+            self.blinking = true;
+        }
+
+        // https://vt100.net/docs/vt510-rm/DECAWM.html, the wrap only happens once the next character arrives
+        // and is forgotten as soon as the cursor is moved
+
+        if self.mode.decawm && self.wrap_pending == Some(self.cursor.position) {
+            self.cursor.position.x = if self.within_margins(self.cursor.position.x) { self.scrolling_region.left as i32 } else { 0 };
+
+            self.line_feed();
+        }
+
+        self.wrap_pending = None;
+        self.last_printed = Some(c);
+
+        let c = self.charsets[self.shift].translate(c);
+
+        let width = width::char_width(c, self.config.width_table, self.config.ambiguous_wide);
+        let scale = self.buf.get(self.cursor.position.y as usize).map_or(1, |line| line.scale()) as i32;
+        let (first, last) = match self.within_margins(self.cursor.position.x) && scale == 1 {
+            true => (self.scrolling_region.left as i32, self.scrolling_region.right as i32),
+            false => (0, self.cols as i32 / scale - 1),
+        };
+
+        // a wide character never gets split over two lines, it wraps early instead
+
+        if width == 2 && self.cursor.position.x >= last {
+            if self.mode.decawm {
+                self.cursor.position.x = first;
+
+                self.line_feed();
+            } else {
+                self.cursor.position.x = last - 1;
+            }
+        }
+
+        let (y, x) = (self.cursor.position.y as usize, self.cursor.position.x as usize);
+
+        let attr = match width {
+            2 => Attribute { flags: self.attr.flags | WIDE, ..self.attr },
+            _ => self.attr,
+        };
+
+        self.split_wide(y, x);
+
         if !self.mode.decim {
-            self.set_char(self.cursor.position.y as usize, self.cursor.position.x as usize, Character { attr: self.attr, byte: c });
+            if width == 2 {
+                self.split_wide(y, x + 1);
+                self.set_char(y, x + 1, Character { attr: Attribute { flags: self.attr.flags | WIDE_SPACER, ..self.attr }, byte: ' ' });
+            }
+
+            self.set_char(y, x, Character { attr, byte: c });
         } else {
-            self.insert_char(self.cursor.position.y as usize, self.cursor.position.x as usize, Character { attr: self.attr, byte: c });
+            if width == 2 {
+                self.insert_char(y, x, Character { attr: Attribute { flags: self.attr.flags | WIDE_SPACER, ..self.attr }, byte: ' ' });
+            }
+
+            self.insert_char(y, x, Character { attr, byte: c });
         }
 
-        if self.cursor.position.x < self.window.width as i32 / self.cell.width {
-            self.cursor.position.x += 1;
+        if self.cursor.position.x + width - 1 < last {
+            self.cursor.position.x += width;
+        } else {
+            self.cursor.position.x += width - 1;
+
+            self.wrap_pending = Some(self.cursor.position);
         }
     }
 
-    fn execute(&mut self, byte: u8) {
-        // println!("[execute] byte={:#x?}", byte);
+    // overwriting either half of a wide character leaves the other half behind as a blank
 
-        match byte {
-            0x09 => {
+    fn split_wide(&mut self, y: usize, x: usize) {
+        let Some(flags) = self.buf.get(y).and_then(|line| line.get(x)).map(|character| character.attr.flags) else { return };
+
+        let other = match flags {
+            flags if flags & WIDE != 0 => x + 1,
+            flags if flags & WIDE_SPACER != 0 && x > 0 => x - 1,
+            _ => return,
+        };
+
+        if let Some(character) = self.buf[y].get(other).copied() {
+            self.set_char(y, other, Character { attr: Attribute { flags: character.attr.flags & !(WIDE | WIDE_SPACER), ..character.attr }, byte: ' ' });
+        }
+    }
+
+    fn is_wide(&self, y: usize, x: usize, flag: u16) -> bool {
+        self.buf.get(y).and_then(|line| line.get(x)).is_some_and(|character| character.attr.flags & flag != 0)
+    }
+
+    // erases the columns start..end, widened so that no wide character is cut in half
+
+    fn erase(&mut self, y: usize, start: usize, end: usize) {
+        let Some(len) = self.buf.get(y).map(|line| line.len()) else { return };
+
+        let start = if self.is_wide(y, start, WIDE_SPACER) { start.saturating_sub(1) } else { start };
+        let end = if end > 0 && self.is_wide(y, end - 1, WIDE) { end + 1 } else { end };
+
+        for x in start..end.min(len) {
+            self.set_char(y, x, Character { byte: ' ', attr: self.attr });
+        }
+    }
+
+    // the cursor never rests on the spacer of a wide character
+
+    fn snap_cursor(&mut self, forward: bool) {
+        let (y, x) = (self.cursor.position.y as usize, self.cursor.position.x as usize);
+
+        if self.is_wide(y, x, WIDE_SPACER) {
+            if forward && x + 1 < self.buf[y].len() {
                 self.cursor.position.x += 1;
+            } else {
+                self.cursor.position.x -= 1;
+            }
+        }
+    }
+
+    fn line_feed(&mut self) {
+        // shells without OSC 133 get their prompts guessed from the line the cursor is leaving
+
+        if let Some(pattern) = self.config.prompt_pattern.as_ref().filter(|_| !self.semantic_prompts) {
+            if let Some(line) = self.buf.get_mut(self.cursor.position.y as usize) {
+                if pattern.is_match(&line.iter().map(|character| character.byte).collect::<String>()) {
+                    line.marks.prompt = true;
+                }
+            }
+        }
+
+        // auto print hands over each line as the cursor leaves it
+
+        if self.auto_print {
+            let y = self.history.len() as i32 + self.cursor.position.y;
+
+            self.auto_print_line(self.row_text(y, 0, usize::MAX, false) + "\n");
+        }
+
+        self.index();
+    }
+
+    // https://vt100.net/docs/vt510-rm/IND.html, only the margins scroll, outside the region the cursor stops at the screen edge
+
+    fn index(&mut self) {
+        let last = self.rows as i32 - 1;
+
+        if self.cursor.position.y as usize == self.scrolling_region.bottom {
+            if self.full_width() {
+                self.scroll_down(self.scrolling_region.bottom);
+            } else if self.within_margins(self.cursor.position.x) {
+                self.shift_lines(self.scrolling_region.top, 1, true, false);
+            }
+        } else if self.cursor.position.y < last {
+            self.cursor.position.y += 1;
+        }
+    }
 
-                while !self.tabs[self.cursor.position.x as usize] {
+    fn reverse_index(&mut self) {
+        if self.cursor.position.y as usize == self.scrolling_region.top {
+            if self.full_width() {
+                self.scroll_up(self.scrolling_region.top);
+            } else if self.within_margins(self.cursor.position.x) {
+                self.shift_lines(self.scrolling_region.top, 1, false, false);
+            }
+        } else if self.cursor.position.y > 0 {
+            self.cursor.position.y -= 1;
+        }
+    }
+
+    // top, left, bottom and right of a rectangular area as inclusive cell indices, DECOM makes the rows relative to the margins.
+    // missing or zero params extend to the edge of the screen and an area that ends before it starts is empty
+
+    fn rectangle(&self, params: &[u16]) -> Option<(usize, usize, usize, usize)> {
+        let columns = self.cols.min(self.buf.first().map_or(0, |line| line.len()));
+
+        let param = |index: usize| params.get(index).copied().filter(|param| *param != 0).map(|param| param as usize - 1);
+        let origin = if self.mode.decom { self.scrolling_region.top } else { 0 };
+
+        let top = (param(0).unwrap_or(0) + origin).min(self.rows.saturating_sub(1));
+        let left = param(1).unwrap_or(0).min(columns.saturating_sub(1));
+        let bottom = param(2).map_or(self.rows, |bottom| bottom + origin + 1).min(self.rows).min(self.buf.len());
+        let right = param(3).map_or(columns, |right| right + 1).min(columns);
+
+        (top < bottom && left < right).then(|| (top, left, bottom - 1, right - 1))
+    }
+
+    // HT, CHT and CBT, the cursor stops at the first and last column when there are no more tab stops
+
+    fn tab(&mut self, count: u16, forward: bool) {
+        let last = self.tabs.len() as i32 - 1;
+        let is_stop = |tabs: &[bool], x: i32| tabs.get(x as usize).copied().unwrap_or(false);
+
+        self.cursor.position.x = self.cursor.position.x.clamp(0, last.max(0));
+
+        for _ in 0..count.max(1) {
+            if forward {
+                self.cursor.position.x = (self.cursor.position.x + 1).min(last);
+
+                while self.cursor.position.x < last && !is_stop(&self.tabs, self.cursor.position.x) {
                     self.cursor.position.x += 1;
                 }
-            },
-            0x0a | 0x0b | 0x0c => {
-                if self.cursor.position.y as usize >= self.scrolling_region.bottom {
-                    self.scroll_down(self.scrolling_region.bottom);
-                } else {
-                    self.cursor.position.y += 1;
+            } else {
+                self.cursor.position.x = (self.cursor.position.x - 1).max(0);
+
+                while self.cursor.position.x > 0 && !is_stop(&self.tabs, self.cursor.position.x) {
+                    self.cursor.position.x -= 1;
                 }
-            },
-            0x0d => self.cursor.position.x = 0,
+            }
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        // println!("[execute] byte={:#x?}", byte);
+
+        match byte {
+            0x09 => self.tab(1, true),
+            0x0a | 0x0b | 0x0c => self.line_feed(),
+            0x0d => self.cursor.position.x = if self.within_margins(self.cursor.position.x) { self.scrolling_region.left as i32 } else { 0 },
+            // SO and SI, invoke G1 or G0 into GL
+            0x0e => self.shift = 1,
+            0x0f => self.shift = 0,
             0x08 => {
                 if self.cursor.position.x > 0 {
                     self.cursor.position.x -= 1;
+
+                    self.snap_cursor(false);
                 }
             },
             0x07 => {
-                if let Ok(bell) = self.audio.bell.decoder() {
-                    if let Err(err) = self.audio.stream_handle.play_raw(bell.convert_samples()) {
-                        println!("[+] failed to play bell: {}", err);
-                    }
+                self.audio.play_bell();
+
+                if !self.focused {
+                    self.set_urgency(true);
                 }
             },
             _ => println!("[+] unknown C0 control code: {:#x?}", byte),
@@ -278,20 +813,63 @@ impl Screen {
     fn set_char(&mut self, y: usize, x: usize, character: Character) {
         if self.buf[y][x] != character {
             self.buf[y][x] = character;
-            self.dirty[y][x] = true;
+            self.dirty.set(y, x);
         }
     }
 
     fn insert_char(&mut self, y: usize, x: usize, character: Character) {
-        self.buf[y].insert(x, character);
-        self.buf[y].pop();
+        self.shift_chars(y, x, 1, false);
+        self.set_char(y, x, character);
+    }
+
+    // ICH and DCH, the cells from x up to the right margin move and whatever is pushed past it is lost
+
+    fn shift_chars(&mut self, y: usize, x: usize, count: usize, delete: bool) {
+        let end = if !self.full_width() && self.within_margins(x as i32) { self.scrolling_region.right + 1 } else { self.buf[y].len() };
+
+        if x >= end {
+            return;
+        }
+
+        let count = count.min(end - x);
+        let blank = Character { byte: ' ', attr: self.attr };
+
+        // the last deleted cell may be the left half of a wide character, its spacer would be left behind without it
+
+        if delete {
+            self.split_wide(y, x + count - 1);
+        }
+
+        let cells = &mut self.buf[y][x..end];
 
-        for column in x..self.buf[y].len() {
-            self.dirty[y][column] = true;
+        if delete {
+            cells.rotate_left(count);
+            cells[end - x - count..].fill(blank);
+        } else {
+            cells.rotate_right(count);
+            cells[..count].fill(blank);
         }
+
+        self.dirty.set_row(y, x);
+    }
+
+    // the left and right margins only differ from the screen edges while DECLRMM is set
+
+    fn full_width(&self) -> bool {
+        self.scrolling_region.left == 0 && self.scrolling_region.right + 1 >= self.cols
+    }
+
+    fn within_margins(&self, x: i32) -> bool {
+        (self.scrolling_region.left as i32..=self.scrolling_region.right as i32).contains(&x)
+    }
+
+    // DECIC and DECDC do nothing while the cursor is outside the scrolling region or its margins
+
+    fn within_region(&self) -> bool {
+        (self.scrolling_region.top as i32..=self.scrolling_region.bottom as i32).contains(&self.cursor.position.y) && self.within_margins(self.cursor.position.x)
     }
 
-    fn csi_dispatch(&mut self, params: &[u16], intermediates: &[u8], c: char) -> Result<(), Box<dyn std::error::Error>> {
+    fn csi_dispatch(&mut self, prefix: Option<char>, params: &[u16], subparams: u128, intermediates: &[u8], c: char) -> Result<(), Box<dyn std::error::Error>> {
         /*
         println!(
             "[csi_dispatch] params={:?}, intermediates={:?}, char={:?}, buf_len: {}",
@@ -308,30 +886,32 @@ impl Screen {
                 match params.get(0).unwrap_or(&0) {
                     // default: cursor to end
                     0 => {
+                        self.images.erase(self.screen_row(self.cursor.position.y as usize + 1)..i32::MAX, self.mode.decalt);
+
                         for line in self.cursor.position.y as usize + 1..self.buf.len() {
                             for column in 0..self.buf[line].len() {
                                 self.set_char(line, column, Character { byte: ' ', attr: self.attr });
                             }
                         }
 
-                        for column in self.cursor.position.x as usize..self.buf[self.cursor.position.y as usize].len() {
-                            self.set_char(self.cursor.position.y as usize, column, Character { byte: ' ', attr: self.attr });
-                        }
+                        self.erase(self.cursor.position.y as usize, self.cursor.position.x as usize, usize::MAX);
                     },
                     // start to cursor
                     1 => {
+                        self.images.erase(self.screen_row(0)..self.screen_row(self.cursor.position.y as usize), self.mode.decalt);
+
                         for line in 0..self.cursor.position.y as usize {
                             for column in 0..self.buf[line].len() {
                                 self.set_char(line, column, Character { byte: ' ', attr: self.attr });
                             }
                         }
 
-                        for column in 0..self.cursor.position.x as usize + 1 {
-                            self.set_char(self.cursor.position.y as usize, column, Character { byte: ' ', attr: self.attr });
-                        }
+                        self.erase(self.cursor.position.y as usize, 0, self.cursor.position.x as usize + 1);
                     },
                     // whole buffer
                     3 | 2 => {
+                        self.images.erase(self.screen_row(0)..i32::MAX, self.mode.decalt);
+
                         for line in 0..self.buf.len() {
                             for column in 0..self.buf[line].len() {
                                 self.set_char(line, column, Character { byte: ' ', attr: self.attr });
@@ -344,34 +924,26 @@ impl Screen {
             'K' => {
                 match params.get(0).unwrap_or(&0) {
                     // default: from cursor to end
-                    0 => {
-                        for column in self.cursor.position.x as usize..self.buf[self.cursor.position.y as usize].len() {
-                            self.set_char(self.cursor.position.y as usize, column, Character { byte: ' ', attr: self.attr });
-                        }
-                    },
+                    0 => self.erase(self.cursor.position.y as usize, self.cursor.position.x as usize, usize::MAX),
                     // start to cursor
-                    1 => {
-                        for column in 0..self.cursor.position.x as usize + 1 {
-                            self.set_char(self.cursor.position.y as usize, column, Character { byte: ' ', attr: self.attr });
-                        }
-                    },
+                    1 => self.erase(self.cursor.position.y as usize, 0, self.cursor.position.x as usize + 1),
                     // whole line
-                    2 => {
-                        for column in 0..self.buf[self.cursor.position.y as usize].len() {
-                            self.set_char(self.cursor.position.y as usize, column, Character { byte: ' ', attr: self.attr });
-                        }
-                    },
+                    2 => self.erase(self.cursor.position.y as usize, 0, usize::MAX),
                     param => println!("[+] expected EL[0..2] found EL{}", param),
                 }
             },
             'H' | 'f' => {
-                self.cursor.position.x = ((*params.get(1).unwrap_or(&1) as i32).max(1) - 1).min(self.window.width as i32 / self.cell.width);
+                self.cursor.position.x = ((*params.get(1).unwrap_or(&1) as i32).max(1) - 1).min(self.cols as i32 - 1);
 
                 if self.mode.decom {
+                    self.cursor.position.x = (self.cursor.position.x + self.scrolling_region.left as i32).min(self.scrolling_region.right as i32);
+
                     self.cursor.position.y = (*params.get(0).unwrap_or(&1) as i32).max(1) - 1 + self.scrolling_region.top as i32;
                 } else {
                     self.cursor.position.y = (*params.get(0).unwrap_or(&1) as i32).max(1) - 1;
                 }
+
+                self.snap_cursor(false);
             },
             'A' => {
                 self.cursor.position.y -= self.cursor.position.y.min((*params.get(0).unwrap_or(&1) as i32).max(1));
@@ -379,9 +951,23 @@ impl Screen {
             'B' | 'e' => {
                 self.cursor.position.y += (*params.get(0).unwrap_or(&1) as i32).max(1);
             },
-            'C' | 'a' => self.cursor.position.x += (*params.get(0).unwrap_or(&1) as i32).max(1),
+            'C' | 'a' => {
+                self.cursor.position.x += (*params.get(0).unwrap_or(&1) as i32).max(1);
+
+                self.snap_cursor(true);
+            },
+            // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Functions-using-CSI-_-ordered-by-the-final-character_s_, REP
+            'b' => {
+                if let Some(c) = self.last_printed {
+                    for _ in 0..(*params.get(0).unwrap_or(&1)).max(1) {
+                        self.print(c);
+                    }
+                }
+            },
             'D' => {
                 self.cursor.position.x -= self.cursor.position.x.min((*params.get(0).unwrap_or(&1) as i32).max(1));
+
+                self.snap_cursor(false);
             },
             'E' => {
                 self.cursor.position.y += (*params.get(0).unwrap_or(&1) as i32).max(1);
@@ -393,7 +979,11 @@ impl Screen {
             },
             'g' => {
                 match params.get(0).unwrap_or(&0) {
-                    0 => self.tabs[self.cursor.position.x as usize] = false,
+                    0 => {
+                        if let Some(stop) = self.tabs.get_mut(self.cursor.position.x as usize) {
+                            *stop = false;
+                        }
+                    },
                     3 => self.tabs = self.tabs.iter().map(|_| false).collect::<Vec<bool>>(),
                     param => println!("[+] expected TBC[0 | 3] found TBC{}", param),
                 }
@@ -401,164 +991,210 @@ impl Screen {
             '@' => {
                 // self.alloc_area(self.cursor.position.x, self.cursor.position.y, 1, *params.get(0).unwrap_or(&1) as i32, false);
 
-                for _ in 0..*params.get(0).unwrap_or(&1) as usize {
-                    self.insert_char(self.cursor.position.y as usize, self.cursor.position.x as usize, Character { attr: self.attr, byte: ' ' });
-                }
+                self.split_wide(self.cursor.position.y as usize, self.cursor.position.x as usize);
+
+                self.shift_chars(self.cursor.position.y as usize, self.cursor.position.x as usize, (*params.get(0).unwrap_or(&1)).max(1) as usize, false);
             },
             'i' => {
-                // TODO: MC -- copy media
+                // https://vt100.net/docs/vt510-rm/MC.html, printer controller mode (CSI 5 i) is not supported
+
+                match (prefix, params.first().copied().unwrap_or_default()) {
+                    (None, 0) => {
+                        if let Some(text) = self.extent_text(ipc::Extent::Screen, false) {
+                            self.print_text(text);
+                        }
+                    },
+                    (Some('?'), 1) => {
+                        let y = self.history.len() as i32 + self.cursor.position.y;
+
+                        self.print_text(self.row_text(y, 0, usize::MAX, false) + "\n");
+                    },
+                    (Some('?'), 4) => {
+                        self.auto_print = false;
+                        self.printer = None;
+                    },
+                    (Some('?'), 5) => self.auto_print = true,
+                    _ => {},
+                }
             },
             'G' | '`' => {
                 self.cursor.position.x = (*params.get(0).unwrap_or(&1) as i32).max(1) - 1;
+
+                self.snap_cursor(false);
             },
-            'S' => {
-                self.scroll_up(self.scrolling_region.top);
-            },
-            'T' => {
-                self.scroll_down(self.scrolling_region.bottom);
-            },
-            'L' => {
-                /*
-                 * this has the same behaviour as kitty, but st seems to keep the x position after
-                 * the lines are inserted.
-                 *
-                 * https://www.vt100.net/docs/vt510-rm/IL.html
-                 * "lines scrolled of the page are lost"
-                */
+            'S' if prefix == Some('?') => {
+                let window = (self.cols as u32 * self.cell.width as u32, self.rows as u32 * self.cell.height as u32);
 
-                /*
-                for index in 0..*params.get(0).unwrap_or(&1) {
-                    self.buf.insert((self.cursor.position.y as usize).max(self.scrolling_region.top) + index as usize, vec![Character { attr: self.attr, byte: ' ' }]);
-                }
+                let reply = self.graphics.query(*params.get(0).unwrap_or(&0), *params.get(1).unwrap_or(&0), params.get(2..).unwrap_or_default(), window);
 
-                for index in self.scrolling_region.bottom..self.buf.len() - 1 {
-                    self.buf[index] = vec![Character { attr: self.attr, byte: ' ' }];
-                }
-                */
+                self.write_tty_raw(&reply)?;
+            },
+            // SU moves the region's content up and SD moves it down, with a prefix these are other sequences like XTRMTITLE
+            'S' if prefix.is_none() => self.shift_lines(self.scrolling_region.top, *params.get(0).unwrap_or(&1) as usize, true, true),
+            'T' if prefix.is_none() => self.shift_lines(self.scrolling_region.top, *params.get(0).unwrap_or(&1) as usize, false, true),
+            'L' | 'M' => {
+                // https://www.vt100.net/docs/vt510-rm/IL.html, lines pushed past the bottom margin are lost and the cursor moves to the left margin
+
+                let y = self.cursor.position.y as usize;
 
-                for _ in 0..*params.get(0).unwrap_or(&1) as usize {
-                    self.scroll_down(self.cursor.position.y as usize);
+                if (self.scrolling_region.top..=self.scrolling_region.bottom).contains(&y) && self.within_margins(self.cursor.position.x) {
+                    self.shift_lines(y, (*params.get(0).unwrap_or(&1)).max(1) as usize, c == 'M', false);
+
+                    self.cursor.position.x = self.scrolling_region.left as i32;
                 }
+            },
+            'X' => {
+                let x = self.cursor.position.x as usize;
 
-                self.cursor.position.x = 0;
+                self.erase(self.cursor.position.y as usize, x, x + (*params.get(0).unwrap_or(&1)).max(1) as usize);
             },
-            'M' => {
-                /*
-                self.alloc_area(self.cursor.position.x, self.cursor.position.y, *params.get(0).unwrap_or(&1) as i32 + 1, 1, false);
+            'P' => {
+                self.split_wide(self.cursor.position.y as usize, self.cursor.position.x as usize);
+
+                self.shift_chars(self.cursor.position.y as usize, self.cursor.position.x as usize, (*params.get(0).unwrap_or(&1)).max(1) as usize, true);
+            },
+            '}' if intermediates == b"'" => {
+                // https://vt100.net/docs/vt510-rm/DECIC.html, an ICH on every row of the scrolling region
 
-                for _ in y..y + *params.get(0).unwrap_or(&1) as usize {
-                    if self.buf.len() > y {
-                        self.buf.remove(y);
+                if self.within_region() {
+                    let x = self.cursor.position.x as usize;
+                    let count = (*params.get(0).unwrap_or(&1)).max(1) as usize;
+
+                    for y in self.scrolling_region.top..=self.scrolling_region.bottom.min(self.buf.len() - 1) {
+                        self.split_wide(y, x);
+                        self.shift_chars(y, x, count, false);
                     }
                 }
-                */
+            },
+            '~' if intermediates == b"'" => {
+                // https://vt100.net/docs/vt510-rm/DECDC.html, a DCH on every row of the scrolling region
 
-                for _ in 0..*params.get(0).unwrap_or(&1) as usize {
-                    self.scroll_up(self.cursor.position.y as usize);
-                }
+                if self.within_region() {
+                    let x = self.cursor.position.x as usize;
+                    let count = (*params.get(0).unwrap_or(&1)).max(1) as usize;
 
-                self.cursor.position.x = 0;
-            },
-            'X' => {
-                for index in 0..*params.get(0).unwrap_or(&1) as usize {
-                    self.set_char(self.cursor.position.y as usize, self.cursor.position.x as usize + index, Character { byte: ' ', attr: self.attr });
+                    for y in self.scrolling_region.top..=self.scrolling_region.bottom.min(self.buf.len() - 1) {
+                        self.split_wide(y, x);
+                        self.shift_chars(y, x, count, true);
+                    }
                 }
             },
-            'P' => {
-                for _ in 0..*params.get(0).unwrap_or(&1) as usize {
-                    self.buf[self.cursor.position.y as usize].remove(self.cursor.position.x as usize);
-                    self.buf[self.cursor.position.y as usize].push(Character { byte: ' ', attr: self.attr });
-                }
+            'x' if intermediates == b"$" => {
+                // https://vt100.net/docs/vt510-rm/DECFRA.html, only printable characters can fill
+
+                let fill = params.get(0).and_then(|c| char::from_u32(*c as u32)).filter(|c| !c.is_control());
+
+                if let (Some(fill), Some((top, left, bottom, right))) = (fill, self.rectangle(params.get(1..).unwrap_or(&[]))) {
+                    for y in top..=bottom {
+                        self.erase(y, left, right + 1);
 
-                for column in self.cursor.position.x as usize..self.buf[self.cursor.position.y as usize].len() {
-                    self.dirty[self.cursor.position.y as usize][column] = true;
+                        for x in left..=right {
+                            self.set_char(y, x, Character { byte: fill, attr: self.attr });
+                        }
+                    }
                 }
             },
-            'Z' => {
-                for _ in 0..*params.get(0).unwrap_or(&1) {
-                    self.cursor.position.x -= 1;
+            'z' if intermediates == b"$" => {
+                // https://vt100.net/docs/vt510-rm/DECERA.html
 
-                    while !self.tabs[self.cursor.position.x as usize] {
-                        self.cursor.position.x -= 1;
+                if let Some((top, left, bottom, right)) = self.rectangle(params) {
+                    for y in top..=bottom {
+                        self.erase(y, left, right + 1);
                     }
                 }
+            },
+            'v' if intermediates == b"$" => {
+                // https://vt100.net/docs/vt510-rm/DECCRA.html, there is a single page so both page params are ignored
 
-                self.cursor.position.x = self.cursor.position.x.max(0);
+                if let Some((top, left, bottom, right)) = self.rectangle(params) {
+                    let rows = self.buf[top..=bottom].iter().map(|line| line[left..=right].to_vec()).collect::<Vec<Vec<Character>>>();
+
+                    let target = [*params.get(5).unwrap_or(&0), *params.get(6).unwrap_or(&0), 0, 0];
+
+                    if let Some((target_top, target_left, target_bottom, target_right)) = self.rectangle(&target) {
+                        for (y, row) in (target_top..=target_bottom).zip(rows.iter()) {
+                            for (x, character) in (target_left..=target_right).zip(row.iter()) {
+                                self.set_char(y, x, *character);
+                            }
+                        }
+                    }
+                }
             },
+            'I' => self.tab(*params.get(0).unwrap_or(&1), true),
+            'Z' => self.tab(*params.get(0).unwrap_or(&1), false),
             'd' => {
                 self.cursor.position.y = (*params.get(0).unwrap_or(&1) as i32).max(1) - 1;
             },
-            'm' => {
+            'm' | 'n' if prefix == Some('>') => {
+                // XTMODKEYS, "CSI > 4 n" and a missing value both disable modifyOtherKeys
+
+                if params[0] == 4 {
+                    self.other_keys = if c == 'm' { *params.get(1).unwrap_or(&0) } else { 0 };
+                }
+            },
+            // a prefix makes it another sequence, eg. XTQMODKEYS is CSI ? m
+            'm' if prefix.is_none() => {
                 let mut index = 0;
 
+                // colon separated sub-parameters belong to the param before them, eg. 4:3 or 58:2::r:g:b
+
+                let is_subparam = |index: usize| index < 128 && subparams >> index & 1 != 0;
+
                 while index < params.len() {
                     let param = params.get(index).unwrap_or(&0);
 
                     match param {
-                        0 => {
-                            self.attr = Attribute {
-                                fg: self.config.fg,
-                                bg: self.config.bg,
-                            };
-                        },
-                        22 => {
-                            // set normal intensity
-                        },
-                        1 => {
-                            // set bold, we ignore this for perfomance reasons
-                        },
-                        3 => {
-                            // set italic
-                        },
-                        7 => {
-                            self.attr.fg = self.config.bg;
-                            self.attr.bg = self.config.fg;
-                        },
-                        27 => {
-                            self.attr.fg = self.config.fg;
-                            self.attr.bg = self.config.bg;
+                        0 => self.attr = Attribute::new(&self.config),
+                        22 => self.attr.flags &= !(BOLD | FAINT),
+                        1 => self.attr.flags |= BOLD,
+                        2 => self.attr.flags |= FAINT,
+                        5 | 6 => self.attr.flags |= BLINK,
+                        25 => self.attr.flags &= !BLINK,
+                        3 => self.attr.flags |= ITALIC,
+                        23 => self.attr.flags &= !ITALIC,
+                        4 if is_subparam(index + 1) => {
+                            self.attr.underline = Underline::from_style(params[index + 1]);
+
+                            index += 1;
                         },
+                        4 => self.attr.underline = Underline::Single,
+                        21 => self.attr.underline = Underline::Double,
+                        24 => self.attr.underline = Underline::None,
+                        9 => self.attr.flags |= STRIKETHROUGH,
+                        29 => self.attr.flags &= !STRIKETHROUGH,
+                        7 => self.attr.flags |= INVERSE,
+                        27 => self.attr.flags &= !INVERSE,
+                        8 => self.attr.flags |= CONCEAL,
+                        28 => self.attr.flags &= !CONCEAL,
                         39 => self.attr.fg = self.config.fg,
                         49 => self.attr.bg = self.config.bg,
-                        38 | 48 => {
-                            match params.get(index + 1).unwrap_or(&2) {
-                                2 => {
-                                    let raw = xlib::Color::new(
-                                        *params.get(index + 2).unwrap_or(&0) as u64,
-                                        *params.get(index + 3).unwrap_or(&0) as u64,
-                                        *params.get(index + 4).unwrap_or(&0) as u64,
-                                    );
-
-                                    if let Ok(xft) = self.display.xft_color_alloc_value(raw) {
-                                        if *param == 38 {
-                                            self.attr.fg = config::UniColor {
-                                                raw,
-                                                xft,
-                                            };
-                                        } else if *param == 48 {
-                                            self.attr.bg = config::UniColor {
-                                                raw,
-                                                xft,
-                                            };
-                                        }
-                                    } else {
-                                        println!("[+] failed to create color: {:?}", raw);
-                                    }
-
-                                    index += 4;
-                                },
-                                5 => {},
-                                mode => println!("[+] unimplemented SGR mode: {}", mode),
+                        59 => self.attr.underline_color = None,
+                        38 | 48 | 58 => {
+                            let (color, consumed) = self.sgr_color(&params[index + 1..], subparams.checked_shr((index + 1) as u32).unwrap_or(0));
+
+                            match (param, color) {
+                                (_, None) => {},
+                                (38, Some(color)) => self.attr.fg = color,
+                                (48, Some(color)) => self.attr.bg = color,
+                                (_, Some(color)) => self.attr.underline_color = Some(color),
                             }
+
+                            index += consumed;
                         },
                         30..=37 => self.attr.fg = self.config.colors[*param as usize - 30],
-                        90..=97 => self.attr.fg = self.config.colors[*param as usize - 90],
+                        90..=97 => self.attr.fg = self.config.colors[*param as usize - 90 + 8],
                         40..=47 => self.attr.bg = self.config.colors[*param as usize - 40],
-                        100..=107 => self.attr.bg = self.config.colors[*param as usize - 100],
+                        100..=107 => self.attr.bg = self.config.colors[*param as usize - 100 + 8],
                         _ => println!("[+] unknown SGR code: {}", param),
                     }
 
                     index += 1;
+
+                    // sub-parameters we dont understand are skipped together with their param
+
+                    while is_subparam(index) {
+                        index += 1;
+                    }
                 }
             },
             'n' => {
@@ -568,7 +1204,9 @@ impl Screen {
                     },
                     6 => {
                         if self.mode.decom {
-                            self.write_tty_raw(&format!("\x1b[{};{}R", self.cursor.position.y - self.scrolling_region.top as i32 + 1, self.cursor.position.x + 1))?;
+                            let (y, x) = (self.cursor.position.y - self.scrolling_region.top as i32, self.cursor.position.x - self.scrolling_region.left as i32);
+
+                            self.write_tty_raw(&format!("\x1b[{};{}R", y + 1, x + 1))?;
                         } else {
                             self.write_tty_raw(&format!("\x1b[{};{}R", self.cursor.position.y + 1, self.cursor.position.x + 1))?;
                         }
@@ -577,89 +1215,170 @@ impl Screen {
                 }
             },
             'c' => {
-                match *params.get(0).unwrap_or(&0) {
-                    14 => self.write_tty_raw("\x1b[>1;4000;33c")?,
-                    0 => self.write_tty_raw("\x1b[?6c")?,
+                match (prefix, *params.get(0).unwrap_or(&0)) {
+                    (None, 0) => self.primary_attributes()?,
+                    (Some('>'), 0) => self.secondary_attributes()?,
+                    // https://vt100.net/docs/vt510-rm/DA3.html, the unit id is all zeros like xterm
+
+                    (Some('='), 0) => self.write_tty_raw("\x1bP!|00000000\x1b\\")?,
                     _ => {},
                 }
             },
-            's' => self.cursor.save = self.cursor.position,
-            'u' => self.cursor.position = self.cursor.save,
-            'h' => {
-                match *params.get(0).unwrap_or(&0) {
-                    1 => self.mode.decckm = true,
-                    3 => { /* DECCOLM 80/132 col mode */ },
-                    4 => self.mode.decim = true,
-                    5 => self.mode.decscnm = true,
-                    6 => {
-                        // https://git.suckless.org/st/file/st.c.html#l1482
-                        self.cursor.position = Position { x: 0, y: 0 };
-                        self.mode.decom = true;
+            's' if prefix.is_none() && self.mode.declrmm => {
+                // https://vt100.net/docs/vt510-rm/DECSLRM.html, margins less than two columns apart are ignored
+
+                let left = (*params.get(0).unwrap_or(&0)).max(1) as usize - 1;
+                let right = match *params.get(1).unwrap_or(&0) {
+                    0 => self.cols,
+                    right => (right as usize).min(self.cols),
+                } - 1;
+
+                if left < right {
+                    self.scrolling_region.left = left;
+                    self.scrolling_region.right = right;
+
+                    self.cursor.position = match self.mode.decom {
+                        true => Position { x: left as i32, y: self.scrolling_region.top as i32 },
+                        false => Position { x: 0, y: 0 },
+                    };
+                }
+            },
+            's' if prefix.is_none() => self.cursor.save = self.cursor.position,
+            'u' if prefix == Some('?') => {
+                // kitty keyboard flags query
+
+                self.write_tty_raw(&format!("\x1b[?{}u", self.keyboard.last().copied().unwrap_or(0)))?;
+            },
+            'u' if prefix.is_some() => {
+                // kitty keyboard flags, pushed with '>', popped with '<' and set in place with '='
+
+                let value = params[0];
+
+                match prefix {
+                    Some('>') => {
+                        if self.keyboard.len() == kitty::MAX_STACK {
+                            self.keyboard.remove(0);
+                        }
+
+                        self.keyboard.push(value & kitty::SUPPORTED);
                     },
-                    7 => { /* auto wrapping */ },
-                    12 => { /* start blinking cursor */ },
-                    25 => self.mode.dectecm = true,
-                    1004 => self.mode.decfocus = true,
-                    1000 => { /* normal mouse tracking */ },
-                    1002 => self.mode.decmm = true,
-                    1006 => self.mode.decdm = true,
-                    1049 => {
-                        if !self.mode.decalt {
-                            self.switch_screen();
+                    Some('<') => {
+                        let len = self.keyboard.len().saturating_sub(value.max(1) as usize);
+
+                        self.keyboard.truncate(len);
+                    },
+                    _ => {
+                        let flags = value & kitty::SUPPORTED;
+                        let current = self.keyboard.last().copied().unwrap_or(0);
+
+                        let flags = match *params.get(1).unwrap_or(&1) {
+                            2 => current | flags,
+                            3 => current & !flags,
+                            _ => flags,
+                        };
 
-                            self.mode.decalt = true;
+                        match self.keyboard.last_mut() {
+                            Some(current) => *current = flags,
+                            None => self.keyboard.push(flags),
                         }
                     },
-                    2004 => self.mode.decpaste = true,
-                    param => println!("[+] unknown mode: {}", param),
                 }
             },
-            'l' => {
+            'u' => self.cursor.position = self.cursor.save,
+            'h' | 'l' => {
+                for mode in params {
+                    if prefix == Some('?') {
+                        self.set_private_mode(*mode, c == 'h');
+                    } else {
+                        self.set_ansi_mode(*mode, c == 'h');
+                    }
+                }
+            },
+            'p' if intermediates == b"$" => {
+                // https://vt100.net/docs/vt510-rm/DECRQM.html
+
+                let mode = *params.get(0).unwrap_or(&0);
+                let marker = if prefix == Some('?') { "?" } else { "" };
+
+                self.write_tty_raw(&format!("\x1b[{}{};{}$y", marker, mode, self.mode_status(mode, prefix == Some('?'))))?;
+            },
+            'q' if intermediates.is_empty() && prefix == Some('>') => {
+                // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h4-Functions-using-CSI-_-ordered-by-the-final-character-lparen-s-rparen:CSI-gt-Ps-q.1F7D
+
+                self.write_tty_raw(&format!("\x1bP>|termal {}\x1b\\", env!("CARGO_PKG_VERSION")))?;
+            },
+            'q' if intermediates == b" " => {
+                // https://vt100.net/docs/vt510-rm/DECSCUSR.html, odd styles and the default blink, even styles are steady
+
+                let style = *params.get(0).unwrap_or(&0);
+
+                self.cursor_style = match style {
+                    0..=2 => CursorStyle::Block,
+                    3 | 4 => CursorStyle::Underline,
+                    5 | 6 => CursorStyle::Line,
+                    style => {
+                        println!("[+] unknown cursor style: {}", style);
+
+                        return Ok(());
+                    },
+                };
+
+                self.mode.att610 = style % 2 == 1 || style == 0;
+
+                // the new shape starts out visible, the blink timer and its timeout pick it up from there
+
+                self.blink_visible = true;
+                self.last_blink = Instant::now();
+                self.last_activity = Instant::now();
+            },
+            'q' if intermediates.is_empty() => { /* DECLL load leds */ },
+            't' => {
+                // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html, XTWINOPS. icon and window titles share one stack as only the window title is shown
+
                 match *params.get(0).unwrap_or(&0) {
-                    1 => self.mode.decckm = false,
-                    4 => self.mode.decim = false,
-                    5 => self.mode.decscnm = false,
-                    6 => {
-                        // https://git.suckless.org/st/file/st.c.html#l1482
-                        self.cursor.position = Position { x: 0, y: 0 };
-                        self.mode.decom = false;
+                    1 => self.display.map_window(),
+                    2 => self.display.iconify(),
+                    14 => self.write_tty_raw(&format!("\x1b[4;{};{}t", self.window.height, self.window.width))?,
+                    18 => {
+                        self.write_tty_raw(&format!("\x1b[8;{};{}t", self.rows, self.cols))?;
                     },
-                    7 => { /* auto wrapping */ },
-                    25 => self.mode.dectecm = false,
-                    1004 => self.mode.decfocus = false,
-                    1002 => self.mode.decmm = false,
-                    1006 => self.mode.decdm = false,
-                    1049 => {
-                        if self.mode.decalt {
-                            self.switch_screen();
+                    22 => {
+                        if self.titles.len() == MAX_TITLES {
+                            self.titles.remove(0);
+                        }
+
+                        self.titles.push(self.app_title.clone());
+                    },
+                    23 => {
+                        if let Some(title) = self.titles.pop() {
+                            self.app_title = title;
 
-                            self.mode.decalt = false;
+                            self.update_title();
                         }
                     },
-                    2004 => self.mode.decpaste = false,
-                    param => println!("[+] unknown reset mode: {}", param),
-                }
-            },
-            'q' => {
-                match *params.get(0).unwrap_or(&0) {
-                    2 => self.cursor_style = CursorStyle::Block,
-                    4 => self.cursor_style = CursorStyle::Underline,
-                    6 => self.cursor_style = CursorStyle::Line,
-                    param => println!("[+] unknown LED: {}", param),
+                    param => println!("[+] unknown window operation: {}", param),
                 }
             },
             'r' => {
-                self.scrolling_region = ScrollingRegion {
-                    top: *params.get(0).unwrap_or(&0).max(&1) as usize - 1,
-                    bottom: *params.get(1).unwrap_or(&(self.window.height as u16 / self.cell.height as u16)).max(&1) as usize - 1,
-                };
+                // https://vt100.net/docs/vt510-rm/DECSTBM.html, like xterm a region of less than two lines is ignored
 
-                self.cursor.position = Position {
-                    x: 0,
-                    y: 0,
-                };
+                let top = (*params.get(0).unwrap_or(&0)).max(1) as usize - 1;
+                let bottom = match *params.get(1).unwrap_or(&0) {
+                    0 => self.rows,
+                    bottom => (bottom as usize).min(self.rows),
+                } - 1;
+
+                if top < bottom {
+                    self.scrolling_region.top = top;
+                    self.scrolling_region.bottom = bottom;
 
-                self.scroll_set = !params.is_empty();
+                    self.cursor.position = Position {
+                        x: 0,
+                        y: 0,
+                    };
+
+                    self.scroll_set = !params.is_empty();
+                }
             },
             _ => {
                 println!(
@@ -678,6 +1397,114 @@ impl Screen {
         Ok(())
     }
 
+    // 0 not recognized, 1 set, 2 reset, 3 permanently set and 4 permanently reset
+
+    // https://vt100.net/docs/vt510-rm/SM.html, ansi modes only cover insert, the rest are answered as permanently reset
+
+    fn set_ansi_mode(&mut self, mode: u16, set: bool) {
+        match mode {
+            4 => self.mode.decim = set,
+            2 | 12 | 20 => {},
+            mode => println!("[+] unknown mode: {}", mode),
+        }
+    }
+
+    fn set_private_mode(&mut self, mode: u16, set: bool) {
+        match mode {
+            1 => self.mode.decckm = set,
+            3 => { /* DECCOLM 80/132 col mode */ },
+            4 => { /* DECSCLM smooth scroll */ },
+            5 => {
+                self.mode.decscnm = set;
+
+                self.full_dirt();
+            },
+            6 => {
+                // https://git.suckless.org/st/file/st.c.html#l1482
+                self.cursor.position = Position { x: 0, y: 0 };
+                self.mode.decom = set;
+            },
+            7 => self.mode.decawm = set,
+            12 => self.mode.att610 = set,
+            25 => self.mode.dectecm = set,
+            66 => self.mode.deckpam = set,
+            69 => {
+                self.mode.declrmm = set;
+
+                if !set {
+                    self.scrolling_region.left = 0;
+                    self.scrolling_region.right = self.cols - 1;
+                }
+            },
+            1004 => self.mode.decfocus = set,
+            1000 => self.mode.decnm = set,
+            1002 => self.mode.decmm = set,
+            1005 => self.mode.decum = set,
+            1006 => self.mode.decdm = set,
+            1015 => self.mode.decrm = set,
+            1007 => self.mode.decas = set,
+            1049 => {
+                if self.mode.decalt && !set {
+                    self.images.clear(true);
+                }
+
+                if self.mode.decalt != set {
+                    self.switch_screen();
+
+                    self.mode.decalt = set;
+                }
+            },
+            2004 => self.mode.decpaste = set,
+            2026 => {
+                // https://gist.github.com/christianparpart/d8a62cc1ab659194337d73e399004036, drawing waits for the reset or the timeout
+
+                if set {
+                    self.synchronized.get_or_insert_with(Instant::now);
+                } else {
+                    self.synchronized = None;
+                    self.refresh = true;
+                }
+            },
+            mode => println!("[+] unknown private mode: {}", mode),
+        }
+    }
+
+    fn mode_status(&self, mode: u16, private: bool) -> u8 {
+        if !private {
+            return match mode {
+                4 if self.mode.decim => 1,
+                4 => 2,
+                2 | 12 | 20 => 4,
+                _ => 0,
+            };
+        }
+
+        let set = match mode {
+            1 => self.mode.decckm,
+            5 => self.mode.decscnm,
+            6 => self.mode.decom,
+            7 => self.mode.decawm,
+            12 => self.mode.att610,
+            25 => self.mode.dectecm,
+            66 => self.mode.deckpam,
+            69 => self.mode.declrmm,
+            1000 => self.mode.decnm,
+            1002 => self.mode.decmm,
+            1005 => self.mode.decum,
+            1004 => self.mode.decfocus,
+            1006 => self.mode.decdm,
+            1015 => self.mode.decrm,
+            1007 => self.mode.decas,
+            1049 => self.mode.decalt,
+            2004 => self.mode.decpaste,
+            2026 => self.synchronized.is_some(),
+            3 | 4 => return 4,
+            _ => return 0,
+        };
+
+        if set { 1 } else { 2 }
+    }
+
     fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) -> Result<(), Box<dyn std::error::Error>> {
         let prefix = intermediates.get(0).unwrap_or(&('q' as u8));
         let unknown: bool;
@@ -690,66 +1517,150 @@ impl Screen {
         */
 
         match *prefix as char {
-            '(' => {
-                match byte as char {
-                    'B' => unknown = false, /* ISO 8859-1 */
-                    _ => unknown = true,
+            '(' | ')' => {
+                match Charset::from_designator(byte) {
+                    Some(charset) => {
+                        self.charsets[(*prefix == b')') as usize] = charset;
+
+                        unknown = false;
+                    },
+                    None => unknown = true,
                 }
             },
             'q' | '#' => {
                 match byte as char {
                     'M' => {
-                        if self.cursor.position.y as usize <= self.scrolling_region.top {
-                            self.scroll_up(self.scrolling_region.top);
-                        } else {
-                            self.cursor.position.y -= 1;
-                        }
+                        self.reverse_index();
 
                         unknown = false;
                     },
                     'D' => {
-                        self.cursor.position.y += 1;
+                        self.index();
 
                         unknown = false;
                     },
                     'E' => {
-                        self.cursor.position.y += 1;
-                        self.cursor.position.x = 0;
+                        // like CR it returns to the left margin, unless the cursor started out left of it
+
+                        let x = if self.within_margins(self.cursor.position.x) { self.scrolling_region.left as i32 } else { 0 };
+
+                        self.index();
+
+                        self.cursor.position.x = x;
 
                         unknown = false;
                     },
                     'Z' => {
-                        self.write_tty_raw("\x1b[?6c")?;
+                        self.primary_attributes()?;
 
                         unknown = false;
                     },
                     'H' => {
-                        self.tabs[self.cursor.position.x as usize] = true;
+                        if let Some(stop) = self.tabs.get_mut(self.cursor.position.x as usize) {
+                            *stop = true;
+                        }
 
                         unknown = false;
                     },
                     'c' => {
-                        let default_ch = Character { attr: Attribute { fg: self.config.fg, bg: self.config.bg }, byte: ' ' };
+                        // https://vt100.net/docs/vt510-rm/RIS.html, everything goes back to how the terminal started except the window
+
+                        if self.mode.decalt {
+                            self.switch_screen();
+                        }
 
-                        self.buf = vec![vec![default_ch; (self.window.width as usize / self.cell.width as usize) + 1];
-                            (self.window.height as usize / self.cell.height as usize) + 1];
+                        let default_ch = Character { attr: Attribute::new(&self.config), byte: ' ' };
+
+                        self.buf = vec![Line::new(default_ch, self.cols + 1); self.rows + 1];
+                        self.alt = AltScreen::new(&self.config, self.cols, self.rows);
+                        self.history.clear();
+                        self.images = Placements::default();
+                        self.links.clear();
+                        self.graphics = GraphicsAttributes::default();
+                        self.scroll = 0;
+                        self.selection.end = self.selection.start;
 
                         self.full_dirt();
 
-                        self.cursor.position.x = 0;
-                        self.cursor.position.y = 0;
+                        self.cursor.position = Position { x: 0, y: 0 };
+                        self.cursor.save = Position { x: 0, y: 0 };
+                        self.cursor.save_charsets = ([Charset::Ascii; 2], 0);
+                        self.cursor_style = CursorStyle::Block;
+                        self.wrap_pending = None;
+
+                        self.attr = Attribute::new(&self.config);
+                        self.mode = Mode::new();
+
+                        self.scrolling_region = ScrollingRegion { top: 0, bottom: self.rows - 1, left: 0, right: self.cols - 1 };
+                        self.scroll_set = false;
+
+                        let interval = self.config.tab_interval;
+
+                        self.tabs.iter_mut().enumerate().for_each(|(x, stop)| *stop = x % interval == 0);
 
-                        self.attr = Attribute {
-                            fg: self.config.fg,
-                            bg: self.config.bg,
+                        self.charsets = [Charset::Ascii; 2];
+                        self.shift = 0;
+                        self.synchronized = None;
+                        self.last_printed = None;
+                        self.auto_print = false;
+                        self.printer = None;
+                        self.other_keys = 0;
+                        self.keyboard.clear();
+
+                        self.display.define_cursor(xlib::DEFAULT_CURSOR);
+
+                        unknown = false;
+                    },
+                    'B' | '6' if *prefix != b'#' => unknown = false,
+                    // DECKPAM and DECKPNM
+                    '=' => {
+                        self.mode.deckpam = true;
+
+                        unknown = false;
+                    },
+                    '>' => {
+                        self.mode.deckpam = false;
+
+                        unknown = false;
+                    },
+                    // DECSC and DECRC, the designated charsets and the shift are part of the saved state
+                    '7' if *prefix != b'#' => {
+                        self.cursor.save = self.cursor.position;
+                        self.cursor.save_charsets = (self.charsets, self.shift);
+
+                        unknown = false;
+                    },
+                    '8' if *prefix != b'#' => {
+                        self.cursor.position = self.cursor.save;
+                        (self.charsets, self.shift) = self.cursor.save_charsets;
+
+                        unknown = false;
+                    },
+                    '3' | '4' | '5' | '6' if *prefix == b'#' => {
+                        let size = match byte {
+                            b'3' => LineSize::DoubleTop,
+                            b'4' => LineSize::DoubleBottom,
+                            b'5' => LineSize::Single,
+                            _ => LineSize::DoubleWidth,
                         };
 
+                        let y = self.cursor.position.y as usize;
+
+                        if let Some(line) = self.buf.get_mut(y) {
+                            line.set_size(size);
+
+                            // the right half no longer fits on a double width line
+
+                            let last = (self.cols as i32 / line.scale() as i32 - 1).max(0);
+
+                            self.cursor.position.x = self.cursor.position.x.min(last);
+                            self.dirty.set_row(y, 0);
+                        }
+
                         unknown = false;
                     },
-                    'B' | '6' => unknown = false,
                     '8' => {
-                        self.buf = vec![vec![Character { byte: 'E', attr: self.attr }; (self.window.width as usize / self.cell.width as usize) + 1];
-                            (self.window.height as usize / self.cell.height as usize) + 1];
+                        self.buf = vec![Line::new(Character { byte: 'E', attr: self.attr }, self.cols + 1); self.rows + 1];
 
                         self.full_dirt();
 
@@ -786,679 +1697,3837 @@ impl Screen {
         self.attr = alt.attr;
         self.mode = alt.mode;
 
-        self.full_dirt();
+        self.scroll = 0;
+
+        self.rehash_dirt();
+    }
+
+    // like full_dirt, but rows that still show the same content as last frame are left alone
+
+    fn rehash_dirt(&mut self) {
+        for y in 0..self.buf.len() {
+            let line = if y < self.scroll {
+                self.history.get(self.history.len() + y - self.scroll)
+            } else {
+                self.buf.get(y - self.scroll)
+            };
+
+            if line.map(|line| line.content_hash()) != self.hashes.get(y).copied() {
+                self.dirty.set_row(y, 0);
+            }
+        }
     }
 
     #[inline]
     fn full_dirt(&mut self) {
-        self.dirty = vec![vec![true; (self.window.width as usize / self.cell.width as usize) + 1]; (self.window.height as usize / self.cell.height as usize) + 1];
+        self.dirty.fill(self.rows + 1, self.cols + 1);
     }
 
     fn scroll_down(&mut self, y: usize) {
-        self.buf.remove(self.scrolling_region.top);
+        let line = self.buf.remove(self.scrolling_region.top);
 
-        self.buf.insert(y, vec![Character { byte: ' ', attr: self.attr };  (self.window.width as usize / self.cell.width as usize) + 1]);
-        self.full_dirt();
+        // only lines leaving the top of the primary screen are kept in the scrollback
+
+        if self.scrolling_region.top == 0 && y == self.scrolling_region.bottom && !self.mode.decalt {
+            self.push_history(line);
+        } else {
+            self.images.shift((self.screen_row(self.scrolling_region.top), self.screen_row(y)), 1, true, self.mode.decalt);
+        }
+
+        self.buf.insert(y, Line::new(Character { byte: ' ', attr: self.attr }, self.cols + 1));
+        self.scrolled(self.scrolling_region.top, y, 1, true);
     }
 
     fn scroll_up(&mut self, y: usize) {
         self.buf.remove(self.scrolling_region.bottom);
+        self.images.shift((self.screen_row(y), self.screen_row(self.scrolling_region.bottom)), 1, false, self.mode.decalt);
 
-        self.buf.insert(y, vec![Character { byte: ' ', attr: self.attr }; (self.window.width as usize / self.cell.width as usize) + 1]);
-        self.full_dirt();
+        self.buf.insert(y, Line::new(Character { byte: ' ', attr: self.attr }, self.cols + 1));
+        self.scrolled(y, self.scrolling_region.bottom, 1, false);
     }
 
-    fn decom_clamp(&mut self) {
-        if self.cursor.position.y < self.scrolling_region.top as i32 {
-            self.cursor.position.y = self.scrolling_region.top as i32;
-        } else if self.cursor.position.y > self.scrolling_region.bottom as i32 {
-            self.cursor.position.y = self.scrolling_region.bottom as i32;
+    // moves the lines from top to the bottom margin by count lines in one pass. with history set, lines leaving the top of the
+    // primary screen are kept like in scroll_down, left and right margins narrow it down to moving the cells between them
+
+    fn shift_lines(&mut self, top: usize, count: usize, up: bool, history: bool) {
+        let bottom = self.scrolling_region.bottom.min(self.buf.len() - 1);
+
+        if top > bottom {
+            return;
         }
-    }
 
-    fn handle_key(&mut self, event: x11::xlib::XKeyEvent) -> Result<(), Box<dyn std::error::Error>> {
-        let keysym = self.display.keycode_to_keysym(event.keycode as u8) as u32;
+        let count = count.clamp(1, bottom + 1 - top);
 
-        if is_cursor_key(keysym) {
-            let prefix = match self.mode.decckm {
-                true => "\x1bO",
-                false => "\x1b[",
-            };
+        if !self.full_width() {
+            self.shift_columns(top, bottom, count, up);
 
-            let key = match keysym {
-                x11::keysym::XK_Up => "A",
-                x11::keysym::XK_Down => "B",
-                x11::keysym::XK_Left => "D",
-                x11::keysym::XK_Right => "C",
-                _ => unreachable!(),
-            };
+            return;
+        }
+
+        let blank = Line::new(Character { byte: ' ', attr: self.attr }, self.cols + 1);
 
-            if event.state != 0 {
-                // https://git.suckless.org/st/file/config.def.h.html#l327
-                self.pty.file.write(format!("\x1b[1;{}{}", event.state + 1, key).as_bytes())?;
+        if up {
+            let lines = self.buf.drain(top..top + count).collect::<Vec<Line>>();
+
+            self.buf.splice(bottom + 1 - count..bottom + 1 - count, vec![blank; count]);
+
+            if history && top == 0 && !self.mode.decalt {
+                lines.into_iter().for_each(|line| self.push_history(line));
             } else {
-                self.pty.file.write(format!("{prefix}{key}").as_bytes())?;
+                self.images.shift((self.screen_row(top), self.screen_row(bottom)), count, true, self.mode.decalt);
             }
-        } else if is_special_key(keysym) {
-            match keysym {
-                x11::keysym::XK_BackSpace => { self.pty.file.write("\x7f".as_bytes())?; },
-                x11::keysym::XK_F10 => { self.pty.file.write("\x1b[21~".as_bytes())?; },
-                x11::keysym::XK_Escape => { self.pty.file.write("\x1b".as_bytes())?; },
-                _ => {},
+        } else {
+            self.buf.drain(bottom + 1 - count..=bottom);
+            self.buf.splice(top..top, vec![blank; count]);
+
+            self.images.shift((self.screen_row(top), self.screen_row(bottom)), count, false, self.mode.decalt);
+        }
+
+        self.scrolled(top, bottom, count, up);
+    }
+
+    // the lines from top to bottom moved by count. what the back buffer shows of them is copied along on the next draw
+    // instead of drawn again, so only the rows left behind are dirty. overlays and images are drawn over the cells and
+    // would be copied with them, and with the view in the scrollback the screen lines aren't where they are drawn
+
+    fn scrolled(&mut self, top: usize, bottom: usize, count: usize, up: bool) {
+        let overlay = self.scroll_indicator.is_some()
+            || self.inspect
+            || self.palette.open
+            || self.command_history.open
+            || self.search.open
+            || self.idle_warning.is_some()
+            || self.menu.open
+            || self.images.iter(self.mode.decalt).next().is_some();
+
+        if self.scroll == 0 && !overlay {
+            // copies of different regions can't be merged, the one waiting goes first
+
+            if self.blit.is_some_and(|(pending_top, pending_bottom, _)| (pending_top, pending_bottom) != (top, bottom)) {
+                self.flush_blit();
             }
-        } else if keysym == x11::keysym::XK_c && event.state == 5 {
-            if let Some(selection) = self.get_selection() {
-                self.clipboard.set_text(selection)?;
+
+            let distance = if up { -(count as i32) } else { count as i32 };
+
+            self.blit = Some((top, bottom, self.blit.map_or(0, |(_, _, pending)| pending) + distance));
+            self.dirty.shift(top, bottom, count, up);
+
+            if let Some(a11y) = self.a11y.as_mut() {
+                a11y.scroll(top, bottom, count, up);
             }
-        } else if keysym == x11::keysym::XK_v && event.state == 5 {
-            if let Ok(selection) = self.clipboard.get_text() {
-                if self.mode.decpaste {
-                    self.write_tty_raw(&format!("\x1b[200~{}\x1b[201~", selection))?;
+
+            if bottom < self.hashes.len() {
+                let count = count.min(bottom + 1 - top);
+                let hashes = &mut self.hashes[top..=bottom];
+
+                if up {
+                    hashes.rotate_left(count);
+                    hashes[bottom + 1 - top - count..].fill(0);
                 } else {
-                    self.write_tty_raw(&selection)?;
+                    hashes.rotate_right(count);
+                    hashes[..count].fill(0);
                 }
             }
         } else {
-            let mut content = self.display.lookup_string(event)?;
+            self.rehash_dirt();
+        }
+    }
 
-            content = content.chars().filter(|x| *x != '\0').collect();
+    fn flush_blit(&mut self) {
+        let Some((top, bottom, distance)) = self.blit.take() else { return };
 
-            if !content.is_empty() {
-                self.pty.file.write_all(content.as_bytes())?;
-            }
+        let count = distance.unsigned_abs() as usize;
+
+        // a region moved by its whole height or more has no row left to copy
+
+        if count == 0 || count > bottom - top {
+            return;
         }
 
-        Ok(())
+        let height = self.cell.height;
+        let rows = (bottom + 1 - top - count) as i32;
+        let (source, dest) = if distance < 0 { (top + count, top) } else { (top, top + count) };
+
+        self.display.copy_area((0, source as i32 * height), (self.window.width, (rows * height) as u32), (0, dest as i32 * height));
     }
 
-    // TODO: clean up these functions, they are ugly af
+    fn shift_columns(&mut self, top: usize, bottom: usize, count: usize, up: bool) {
+        let (left, right) = (self.scrolling_region.left, self.scrolling_region.right);
+        let blank = Character { byte: ' ', attr: self.attr };
 
-    fn get_line(&mut self, buf: &Vec<Vec<Character>>, start: Position, end: Position) -> String {
-        if buf.len() > start.y as usize {
-            let length = buf[start.y as usize].len();
+        let rows = if up { (top..=bottom).collect::<Vec<usize>>() } else { (top..=bottom).rev().collect() };
 
-            buf[start.y as usize][(start.x as usize).min(length)..(end.x as usize).min(length)].iter().map(|c| c.byte).collect::<String>()
-        } else {
-            String::new()
+        for y in rows {
+            let source = if up { Some(y + count).filter(|source| *source <= bottom) } else { y.checked_sub(count).filter(|source| *source >= top) };
+
+            let cells = match source {
+                Some(source) => self.buf[source][left..=right].to_vec(),
+                None => vec![blank; right + 1 - left],
+            };
+
+            self.buf[y][left..=right].copy_from_slice(&cells);
+            self.dirty.set_row(y, left);
         }
     }
 
-    fn get_selection(&mut self) -> Option<String> {
-        let buf = self.buf.clone();
+    fn push_history(&mut self, line: Line) {
+        if self.config.scrollback == 0 {
+            self.images.trim(1);
 
-        let mut start = self.selection.start;
-        let mut end = self.selection.end;
+            return;
+        }
 
-        if start.y == end.y {
-            return if start.x > end.x {
-                Some(self.get_line(&buf, end, start))
-            } else if start.x < end.x {
-                Some(self.get_line(&buf, start, end))
-            } else {
-                None
-            }
-        } else {
-            if end.y < start.y {
-                let old_start = start;
+        self.history.push_back(line);
 
-                start = end;
-                end = old_start;
-            }
+        if self.history.len() > self.config.scrollback {
+            // lines are addressed from the top of the scrollback, everything moves up by one
 
-            let mut content = String::new();
+            self.history.pop_front();
 
-            for y in start.y..=end.y {
-                if y == start.y && self.buf.len() as i32 > y {
-                    'start: for x in start.x as usize..self.window.width as usize / self.cell.width as usize {
-                        if x < self.buf[start.y as usize].len() {
-                            content.push(self.buf[start.y as usize][x].byte);
-                        } else {
-                            break 'start;
-                        }
-                    }
-                } else if y == end.y && self.buf.len() as i32 > y {
-                    'end: for x in 0..end.x as usize {
-                        if x < self.buf[end.y as usize].len() {
-                            content.push(self.buf[end.y as usize][x].byte);
-                        } else {
-                            break 'end;
-                        }
-                    }
-                } else if self.buf.len() as i32 > y {
-                    content.extend(self.buf[y as usize].iter().map(|c| c.byte).collect::<Vec<char>>());
-                }
+            self.selection.start.y -= 1;
+            self.selection.end.y -= 1;
 
-                content.push('\n');
-            }
+            self.images.trim(1);
+        }
 
-            Some(content)
+        // keep the viewport still while output arrives, once the oldest line is dropped the view can't go further back
+
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.history.len());
         }
     }
 
-    fn write_tty_raw(&mut self, content: &str) -> Result<(), Box<dyn std::error::Error>> {
-        if !content.is_empty() {
-            self.pty.file.write_all(content.as_bytes())?;
-        }
+    // absolute rows start at the oldest scrollback line and continue into the screen buffer
 
-        Ok(())
+    fn line(&self, y: i32) -> Option<&Line> {
+        if y < 0 {
+            None
+        } else if (y as usize) < self.history.len() {
+            self.history.get(y as usize)
+        } else {
+            self.buf.get(y as usize - self.history.len())
+        }
     }
 
-    #[inline]
-    fn mouse_tracking(&self) -> bool {
-        self.mode.decmm || self.mode.decdm
+    // the pixels past the last full cell and outside the window belong to the nearest cell, applications are never told
+    // about a column or row they don't have
+
+    fn cell_at(&self, x: i32, y: i32) -> Position {
+        Position {
+            x: (x / self.cell.width).min(self.cols as i32 - 1).max(0),
+            y: (y / self.cell.height).min(self.rows as i32 - 1).max(0),
+        }
     }
 
-    fn handle_mouse_motion(&mut self, x: i32, y: i32, type_: i32) -> Result<(), Box<dyn std::error::Error>> {
-        if (self.mode.decmm && self.buttons != Buttons::None && self.mode.decdm) || (!self.mode.decmm && self.mode.decdm) {
-            let suffix = match type_ {
-                x11::xlib::ButtonRelease => "m",
-                _ => "M",
-            };
+    // a selection may end one past the last column so the last cell can be taken whole, rows above the window keep
+    // going into the scrollback
 
-            self.write_tty_raw(
-                format!(
-                    "\x1b[<{};{};{}{}",
-                    if type_ == x11::xlib::MotionNotify {
-                        32
-                    } else {
-                        self.buttons.as_code()
-                    },
-                    (x / self.cell.width) + 1,
-                    (y / self.cell.height) + 1,
-                    suffix,
-                ).as_str()
-            )?;
+    fn selection_at(&self, x: i32, y: i32) -> Position {
+        Position {
+            x: x.div_euclid(self.cell.width).clamp(0, self.cols as i32),
+            y: self.view_to_abs(y.div_euclid(self.cell.height).min(self.rows as i32 - 1)),
         }
+    }
 
-        Ok(())
+    #[inline]
+    fn view_to_abs(&self, y: i32) -> i32 {
+        y + self.history.len() as i32 - self.scroll as i32
     }
 
-    fn handle_event(&mut self, event: x11::xlib::XEvent) -> Result<(), Box<dyn std::error::Error>> {
-        match unsafe { event.type_ } {
-            x11::xlib::KeyPress => {
-                self.handle_key(unsafe { event.key })?;
-            },
-            x11::xlib::ButtonPress => {
-                match unsafe { event.button.button } {
-                    x11::xlib::Button4 => {
-                        self.buttons = Buttons::ScrollUp;
+    fn scroll_view(&mut self, lines: i32) {
+        let scroll = (self.scroll as i32 + lines).clamp(0, self.history.len() as i32) as usize;
 
-                        if !self.mouse_tracking() {
-                            self.write_tty_raw("\x19")?;
-                        } else {
-                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonPress)?;
-                        }
+        if scroll != self.scroll {
+            self.scroll = scroll;
+            self.scroll_indicator = (scroll > 0).then(Instant::now);
 
-                        self.refresh = true;
-                    },
-                    x11::xlib::Button5 => {
-                        self.buttons = Buttons::ScrollDown;
+            self.rehash_dirt();
+        }
+    }
 
-                        if !self.mouse_tracking() {
-                            self.write_tty_raw("\x05")?;
-                        } else {
-                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonPress)?;
-                        }
+    // a held scroll key starts with single lines and doubles the step every few repeats until it moves a whole page
 
-                        self.refresh = true;
-                    },
-                    x11::xlib::Button1 => {
-                        self.buttons = Buttons::Button1;
+    fn scroll_step(&self) -> i32 {
+        let repeats = self.binding_repeat.map_or(0, |(_, repeats)| repeats);
 
-                        if !self.mouse_tracking() {
-                            let raw = unsafe { event.button.y };
-                            let y = raw.is_negative().then(|| raw - self.cell.height).unwrap_or(raw) / self.cell.height;
+        (1 << (repeats / SCROLL_REPEATS_PER_STEP).min(16)).min(self.rows as i32).max(1)
+    }
 
-                            self.selection.start = Position {
-                                x: unsafe { event.button.x } / self.cell.width,
-                                y,
-                            };
+    fn jump_to_prompt(&mut self, forward: bool) {
+        let top = self.view_to_abs(0);
+        let last = (self.history.len() + self.buf.len()) as i32;
 
-                            self.selection.end = Position {
-                                x: unsafe { event.button.x } / self.cell.width,
-                                y,
-                            };
+        let target = if forward {
+            (top + 1..last).find(|y| self.line(*y).is_some_and(|line| line.marks.prompt))
+        } else {
+            (0..top).rev().find(|y| self.line(*y).is_some_and(|line| line.marks.prompt))
+        };
 
-                            self.selection.selecting = true;
-                            self.refresh = true;
-                        } else {
-                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonPress)?;
-                        }
-                    },
-                    x11::xlib::Button2 => {
-                        self.buttons = Buttons::Button2;
+        match target {
+            Some(y) => self.scroll_view(self.history.len() as i32 - y - self.scroll as i32),
+            None if forward => self.scroll_view(-(self.scroll as i32)),
+            None => {},
+        }
+    }
 
-                        if self.mouse_tracking() {
-                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonPress)?;
-                        }
-                    },
-                    _ => {},
-                }
-            },
-            x11::xlib::ButtonRelease => {
-                match unsafe { event.button.button } {
-                    x11::xlib::Button1 | x11::xlib::Button2 | x11::xlib::Button4 | x11::xlib::Button5 => {
-                        if unsafe { event.button.button } == x11::xlib::Button1 {
-                            self.selection.selecting = false;
-                        }
+    // the newest output mark that has been followed by a finished command, as the first and last row of the output
 
-                        if self.mouse_tracking() {
-                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonRelease)?;
-                        }
+    fn last_output(&self) -> Option<(i32, i32)> {
+        let last = (self.history.len() + self.buf.len()) as i32;
 
-                        self.buttons = Buttons::None;
-                    },
-                    _ => {},
-                }
-            },
-            x11::xlib::MotionNotify => {
-                if self.mouse_tracking() {
-                    self.handle_mouse_motion(unsafe { event.motion.x }, unsafe { event.motion.y }, x11::xlib::MotionNotify)?;
-                } else if self.selection.selecting {
-                    let raw = unsafe { event.motion.y };
-                    let y = raw.is_negative().then(|| raw - self.cell.height).unwrap_or(raw) / self.cell.height;
+        let end = (0..last).rev().find(|y| self.line(*y).is_some_and(|line| line.marks.end))?;
+        let start = (0..=end).rev().find(|y| self.line(*y).is_some_and(|line| line.marks.output))?;
 
-                    self.selection.end = Position {
-                        x: unsafe { event.motion.x } / self.cell.width,
-                        y,
-                    };
+        (end > start).then_some((start, end - 1))
+    }
 
-                    self.refresh = true;
-                }
-            },
-            x11::xlib::Expose => {
-                /*
-                 * TODO: screenshots seem to cause a Expose event with a fucked up width and height
-                 *
-                 * we may need to handle other forms of resizing
-                 *
-                */
+    fn select_last_output(&mut self) {
+        if let Some((start, end)) = self.last_output() {
+            self.selection = Selection {
+                start: Position { x: 0, y: start },
+                end: Position { x: self.cols as i32, y: end },
+                selecting: false,
+            };
 
-                let width = unsafe { event.expose.width } as u32;
-                let height = unsafe { event.expose.height } as u32;
+            if start < self.view_to_abs(0) {
+                self.scroll_view(self.view_to_abs(0) - start);
+            }
 
-                if width != self.window.width || height != self.window.height {
-                    self.window = Window {
-                        width,
-                        height,
-                    };
+            self.full_dirt();
+        }
+    }
 
-                    self.display.resize_back_buffer(&self.window);
-                    self.pty.resize(width as u16 / self.cell.width as u16, height as u16 / self.cell.height as u16)?;
-                    self.full_dirt();
+    fn handle_ipc(&mut self) {
+        // the instance socket only activates the window unless ipc is enabled as well
 
-                    let default_ch = Character { attr: Attribute { fg: self.config.fg, bg: self.config.bg }, byte: ' ' };
+        while let Some((pending, instance)) = self.ipc.iter().find_map(|ipc| ipc.accept().map(|pending| (pending, ipc.instance))) {
+            let reply = pending.request.clone().and_then(|request| match request {
+                ipc::Request::GetText { .. } if instance && !self.config.ipc => Err(String::from("ipc is disabled")),
+                ipc::Request::GetText { extent, ansi } => self.extent_text(extent, ansi).ok_or_else(|| String::from("nothing in that extent")),
+                ipc::Request::GetUserVar { .. } if instance && !self.config.ipc => Err(String::from("ipc is disabled")),
+                ipc::Request::GetUserVar { name: Some(name) } => self.user_vars.get(&name).map(|value| format!("{}\n", value)).ok_or_else(|| format!("no user var {}", name)),
+                ipc::Request::GetUserVar { name: None } => {
+                    let mut vars = self.user_vars.iter().map(|(name, value)| format!("{}={}\n", name, value)).collect::<Vec<String>>();
 
-                    self.buf.resize((height as usize / self.cell.height as usize) + 1, vec![default_ch; (width as usize / self.cell.width as usize) + 1]);
-                    self.alt.buf.resize((height as usize / self.cell.height as usize) + 1, vec![default_ch; (width as usize / self.cell.width as usize) + 1]);
+                    vars.sort();
 
-                    self.buf.iter_mut().for_each(|line| line.resize((width as usize / self.cell.width as usize) + 1, default_ch));
-                    self.alt.buf.iter_mut().for_each(|line| line.resize((width as usize / self.cell.width as usize) + 1, default_ch));
+                    Ok(vars.concat())
+                },
+                ipc::Request::Activate => {
+                    self.display.activate();
 
-                    if !self.scroll_set {
-                        self.scrolling_region.bottom = (self.window.height as usize / self.cell.height as usize) - 1;
-                    }
+                    Ok(String::new())
+                },
+            });
 
-                    if self.cursor.position.y > self.window.height as i32 / self.cell.height {
-                        self.cursor.position.y = self.window.height as i32 / self.cell.height - 1;
-                    }
+            pending.reply(reply);
+        }
+    }
 
-                    self.refresh = true;
-                }
-            },
-            x11::xlib::VisibilityNotify => {
-                self.dirty = vec![vec![true; (self.window.width as usize / self.cell.width as usize) + 1]; (self.window.height as usize / self.cell.height as usize) + 1];
+    fn extent_text(&self, extent: ipc::Extent, ansi: bool) -> Option<String> {
+        let bottom = self.history.len() as i32 + self.rows as i32 - 1;
 
-                self.refresh = true
-            },
-            x11::xlib::FocusIn => {
-                if self.mode.decfocus {
-                    self.write_tty_raw("\x1b[I")?;
-                }
+        let (start, end) = match extent {
+            ipc::Extent::Screen => (Position { x: 0, y: self.history.len() as i32 }, Position { x: i32::MAX, y: bottom }),
+            ipc::Extent::Scrollback => (Position { x: 0, y: 0 }, Position { x: i32::MAX, y: bottom }),
+            ipc::Extent::LastCommand => {
+                let (start, end) = self.last_output()?;
 
-                self.focused = true;
-                self.refresh = true;
+                (Position { x: 0, y: start }, Position { x: i32::MAX, y: end })
             },
-            x11::xlib::FocusOut => {
-                if self.mode.decfocus {
-                    self.write_tty_raw("\x1b[O")?;
+            ipc::Extent::Selection => {
+                let (start, end) = (self.selection.start, self.selection.end);
+
+                if start == end {
+                    return None;
                 }
 
-                self.focused = false;
-                self.refresh = true;
+                match start.y.cmp(&end.y) {
+                    std::cmp::Ordering::Equal => (Position { x: start.x.min(end.x), ..start }, Position { x: start.x.max(end.x), ..end }),
+                    std::cmp::Ordering::Less => (start, end),
+                    std::cmp::Ordering::Greater => (end, start),
+                }
             },
-            _ => {},
-        }
+        };
 
-        Ok(())
-    }
+        Some((start.y..=end.y)
+            .map(|y| {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = if y == end.y { end.x } else { i32::MAX };
 
-    #[inline]
-    fn is_within_selection(&self, y: usize, x: usize, selection: &Selection) -> bool {
-        if selection.start == selection.end {
-            false
-        } else if selection.start.y == selection.end.y && y as i32 == selection.start.y {
-            x as i32 >= selection.start.x && (x as i32) < selection.end.x
-        } else if y as i32 == selection.start.y {
-            x as i32 >= selection.start.x
-        } else if y as i32 == selection.end.y {
-            x as i32 <= selection.end.x
-        } else if y as i32 > selection.start.y && (y as i32) < selection.end.y {
-            true
-        } else {
-            false
-        }
+                self.row_text(y, from as usize, to as usize, ansi) + "\n"
+            })
+            .collect())
     }
 
-    fn draw(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        /* making sure end.y is always bigger then start.y and end.x is always bigger start.x */
+    // trailing blanks are dropped, with ansi every change of attributes is written as the full SGR state
 
-        let mut selection = if self.selection.end.y > self.selection.start.y {
-            self.selection
-        } else {
-            Selection {
-                start: self.selection.end,
-                end: self.selection.start,
-                selecting: false,
+    fn row_text(&self, y: i32, start: usize, end: usize, ansi: bool) -> String {
+        let Some(line) = self.line(y) else { return String::new() };
+
+        let cells = &line[start.min(line.len())..end.min(line.len())];
+        let len = cells.iter().rposition(|character| character.byte != ' ').map_or(0, |x| x + 1);
+
+        let mut text = String::new();
+        let mut current = None;
+
+        for character in cells[..len].iter().filter(|character| character.attr.flags & WIDE_SPACER == 0) {
+            if ansi && current != Some(character.attr) {
+                text.push_str(&format!("\x1b[{}m", self.sgr_string(character.attr)));
+
+                current = Some(character.attr);
             }
-        };
 
-        if selection.start.x > selection.end.x && selection.start.y == selection.end.y {
-            let end = selection.end.x;
+            text.push(character.byte);
+        }
 
-            selection.end.x = selection.start.x;
-            selection.start.x = end;
+        if current.is_some() {
+            text.push_str("\x1b[0m");
         }
 
-        let width = self.window.width / self.cell.width as u32;
-        let height = self.window.height / self.cell.height as u32;
+        text
+    }
 
-        for (y, line) in self.buf.iter().enumerate().rev() {
-            let y_pos = y as i32 * self.cell.height;
+    fn osc_dispatch(&mut self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let data = String::from_utf8_lossy(data);
+        let mut params = data.split(';');
 
-            if (0..self.window.height as i32).contains(&y_pos) {
-                for (x, character) in line.iter().enumerate() {
-                    let is_within_selection = self.is_within_selection(y, x, &selection);
+        match params.next() {
+            Some("0") | Some("2") => {
+                self.app_title = Some(params.collect::<Vec<&str>>().join(";"));
 
-                    if self.dirty[y][x] || is_within_selection {
-                        if is_within_selection {
-                            self.dirty[y][x] = true;
-                        } else {
-                            self.dirty[y][x] = false;
-                        }
+                self.update_title();
+            },
+            Some("133") => {
+                self.semantic_prompts = true;
 
-                        self.display.draw_rec(
-                            x as i32 * self.cell.width,
-                            y_pos,
-                            self.cell.width as u32,
-                            self.cell.height as u32,
-                            if is_within_selection {
-                                character.attr.fg.raw
-                            } else {
-                                character.attr.bg.raw
-                            }
-                        );
+                let mark = params.next();
+
+                // the command line sits between B and C, it is read back once the shell starts running it
 
-                        self.display.xft_draw_string(
-                            character.byte.to_string().as_str(),
-                            x as i32 * self.cell.width,
-                            y_pos + 15,
-                            height,
-                            width,
-                            self.xft.font,
-                            if is_within_selection {
-                                &character.attr.bg.xft
-                            } else {
-                                &character.attr.fg.xft
+                match mark {
+                    Some("B") => self.command_start = Some(Position { x: self.cursor.position.x, y: self.cursor.position.y + self.history.len() as i32 }),
+                    Some("C") => {
+                        if let Some(start) = self.command_start.take() {
+                            let command = self.command_line(start);
+
+                            if let Err(err) = self.command_history.record(&command) {
+                                println!("[+] failed to record command: {}", err);
                             }
-                        );
+                        }
+                    },
+                    _ => {},
+                }
+
+                if let Some(line) = self.buf.get_mut(self.cursor.position.y as usize) {
+                    match mark {
+                        Some("A") => line.marks.prompt = true,
+                        Some("B") => {},
+                        Some("C") => line.marks.output = true,
+                        Some("D") => line.marks.end = true,
+                        param => println!("[+] unknown OSC 133 mark: {:?}", param),
                     }
                 }
-            }
-        }
+            },
+            Some("9") => {
+                // https://iterm2.com/documentation-escape-codes.html, conemu uses numeric subcommands
 
-        if self.mode.dectecm {
-            let width = match self.cursor_style {
-                CursorStyle::Block | CursorStyle::Underline => self.cell.width as u32,
-                CursorStyle::Line => 2,
-            };
+                let body = params.collect::<Vec<&str>>().join(";");
 
-            let height = match self.cursor_style {
-                CursorStyle::Block | CursorStyle::Line => self.cell.height as u32,
-                CursorStyle::Underline => 5,
-            };
+                if body.parse::<u32>().is_err() {
+                    self.notify("termal", &body);
+                }
+            },
+            Some("22") => {
+                // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Operating-System-Commands, an empty shape restores the default
 
-            let y = match self.cursor_style {
-                CursorStyle::Block | CursorStyle::Line => self.cursor.position.y * self.cell.height,
-                CursorStyle::Underline => (self.cursor.position.y * self.cell.height) + 15,
-            };
+                match params.next().filter(|shape| !shape.is_empty()).map_or(Some(xlib::DEFAULT_CURSOR), xlib::cursor_shape) {
+                    Some(shape) => self.display.define_cursor(shape),
+                    None => println!("[+] unknown pointer shape: {}", data),
+                }
+            },
+            Some("8") => {
+                // https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feaa, the uri may contain semicolons
 
-            if !self.focused && self.cursor_style == CursorStyle::Block {
-                self.display.outline_rec(
-                    self.cursor.position.x * self.cell.width,
-                    self.cursor.position.y * self.cell.height,
-                    self.cell.width as u32 - 1,
-                    self.cell.height as u32 - 1,
-                    self.config.fg.raw,
-                );
-            } else {
-                self.display.draw_rec(
-                    self.cursor.position.x * self.cell.width,
-                    y,
-                    width,
-                    height,
-                    self.config.fg.raw,
-                );
-            }
-        }
+                let uri = params.skip(1).collect::<Vec<&str>>().join(";");
+
+                self.attr.link = match uri.is_empty() {
+                    true => 0,
+                    false => self.intern_link(uri),
+                };
+            },
+            Some("1337") if data.starts_with("1337;File=") => {
+                // https://iterm2.com/documentation-images.html, the arguments are separated by semicolons as well
 
-        self.dirty[self.cursor.position.y as usize][self.cursor.position.x as usize] = true;
+                match graphics::parse_inline(&data["1337;File=".len()..]) {
+                    Ok(Some(inline)) => self.place_image(&inline),
+                    Ok(None) => println!("[+] ignoring file transfer"),
+                    Err(err) => println!("[+] invalid inline image: {}", err),
+                }
+            },
+            Some("1337") => {
+                // https://iterm2.com/documentation-escape-codes.html, SetUserVar=name=base64 value
 
-        self.display.swap_buffers(&self.window);
+                match params.next().and_then(|param| param.strip_prefix("SetUserVar=")).and_then(|var| var.split_once('=')) {
+                    // a program can't grow the vars without bound, new names are refused once MAX_USER_VARS are set
 
-        self.refresh = false;
+                    Some((name, value)) if name.len() + value.len() > MAX_USER_VAR_BYTES => println!("[+] user var {} is too long", name),
+                    Some((name, _)) if self.user_vars.len() >= MAX_USER_VARS && !self.user_vars.contains_key(name) => {
+                        println!("[+] too many user vars, ignoring {}", name);
+                    },
+                    Some((name, value)) => {
+                        match base64::engine::general_purpose::STANDARD.decode(value) {
+                            Ok(value) => {
+                                self.user_vars.insert(name.to_string(), String::from_utf8_lossy(&value).to_string());
+
+                                self.update_title();
+                            },
+                            Err(err) => println!("[+] invalid user var {}: {}", name, err),
+                        }
+                    },
+                    None => println!("[+] unknown OSC 1337: {}", data),
+                }
+            },
+            Some("777") => {
+                if let Some("notify") = params.next() {
+                    let title = params.next().unwrap_or("termal");
+                    let body = params.collect::<Vec<&str>>().join(";");
+
+                    self.notify(title, &body);
+                }
+            },
+            _ => {},
+        }
 
         Ok(())
     }
-}
 
-impl Terminal {
-    pub fn new() -> Result<Terminal, Box<dyn std::error::Error>> {
-        let mut display = xlib::Display::open()?;
+    fn open(&self, target: &str) {
+        let child = process::Command::new("xdg-open")
+            .arg(target)
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::null())
+            .spawn();
 
-        let window_attr = display.get_window_attributes();
+        match child {
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            },
+            Err(err) => println!("[+] failed to open {}: {}", target, err),
+        }
+    }
 
-        let (_stream, stream_handle) = OutputStream::try_default()?;
+    fn dcs_dispatch(&mut self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let data = String::from_utf8_lossy(data);
 
-        let config = Config::load(&display)?;
+        if let Some(names) = data.strip_prefix("+q") {
+            // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Device-Control-functions, names and values are hex encoded
 
-        let font = display.load_font(&config.font)?;
+            for name in names.split(';') {
+                match termcap::decode_hex(name).as_deref().and_then(termcap::capability) {
+                    Some("") => self.write_tty_raw(&format!("\x1bP1+r{}\x1b\\", name))?,
+                    Some(value) => self.write_tty_raw(&format!("\x1bP1+r{}={}\x1b\\", name, termcap::encode_hex(value)))?,
+                    None => self.write_tty_raw(&format!("\x1bP0+r{}\x1b\\", name))?,
+                }
+            }
 
-        let attr = Attribute {
-            fg: config.fg,
-            bg: config.bg,
-        };
+            return Ok(());
+        }
 
-        let alt = AltScreen::new(&config, window_attr.width as usize, window_attr.height as usize);
+        match data.strip_prefix("$q") {
+            // https://vt100.net/docs/vt510-rm/DECRQSS.html, xterm answers 1 for a valid request and 0 otherwise
 
-        let tabs = (0..config.tab_max).map(|x| x % 8 == 0).collect::<Vec<bool>>();
+            Some(setting) => {
+                let reply = match setting {
+                    "m" => Some(format!("{}m", self.sgr_string(self.attr))),
+                    " q" => Some(format!("{} q", self.cursor_style_param())),
+                    "r" => Some(format!("{};{}r", self.scrolling_region.top + 1, self.scrolling_region.bottom + 1)),
+                    "s" => Some(format!("{};{}s", self.scrolling_region.left + 1, self.scrolling_region.right + 1)),
+                    _ => None,
+                };
 
-        let bell = Sound::load(&config.bell)?;
+                match reply {
+                    Some(reply) => self.write_tty_raw(&format!("\x1bP1$r{}\x1b\\", reply))?,
+                    None => self.write_tty_raw("\x1bP0$r\x1b\\")?,
+                }
+            },
+            None => println!("[+] unknown DCS: {:?}", data),
+        }
 
-        Ok(Terminal {
-            parser: Parser::new(),
-            screen: Screen {
-                display,
-                selection: Selection {
-                    start: Position { x: 0, y: 0 },
-                    end: Position { x: 0, y: 0 },
-                    selecting: false,
-                },
-                cursor: Cursor {
-                    position: Position {
-                        x: 0,
-                        y: 0,
-                    },
-                    save: Position {
-                        x: 0,
-                        y: 0,
-                    },
-                },
-                window: Window {
-                    width: window_attr.width as u32,
-                    height: window_attr.height as u32,
-                },
-                buttons: Buttons::None,
-                attr,
-                config,
-                audio: Audio {
-                    _stream,
-                    stream_handle,
-                    bell,
-                },
-                cell: Cell {
-                    width: 10,
-                    height: 20,
-                },
-                mode: Mode {
-                    decim: false,
-                    decom: false,
-                    decscnm: false,
-                    decckm: false,
-                    dectecm: true,
-                    decalt: false,
-                    decpaste: false,
-                    decfocus: false,
-                    decmm: false,
-                    decdm: false,
-                },
-                xft: Xft {
-                    font,
-                },
-                cursor_style: CursorStyle::Block,
-                scrolling_region: ScrollingRegion {
-                    top: 0,
-                    bottom: (window_attr.height as usize / 20 as usize) - 1,
-                },
-                clipboard: Clipboard::new()?,
-                pty: Pty::new()?,
-                buf: vec![vec![Character { attr, byte: ' ' }; (window_attr.width as usize / 10) + 1]; (window_attr.height as usize / 20) + 1],
-                alt,
-                tabs,
-                dirty: vec![vec![true; (window_attr.width as usize / 10) + 1]; (window_attr.height as usize / 20) + 1],
-                refresh: true,
-                focused: true,
-                scroll_set: false,
-                should_close: false,
+        Ok(())
+    }
+
+    // a program emitting links in a loop would otherwise grow them forever, the ones no line or attribute still holds are
+    // dropped before a new one is added
+
+    fn intern_link(&mut self, uri: String) -> u32 {
+        if self.links.full() {
+            let cells = self.history.iter().chain(self.buf.iter()).chain(self.alt.buf.iter()).flat_map(|line| line.iter());
+
+            let used = cells.map(|character| character.attr.link).chain([self.attr.link, self.alt.attr.link]);
+
+            self.links.collect(used);
+        }
+
+        self.links.intern(uri)
+    }
+
+    // like in iterm2 the cursor ends up on the line below the image, in the column it started from
+
+    fn place_image(&mut self, inline: &graphics::InlineImage) {
+        match graphics::decode(&inline.data) {
+            Ok(source) => {
+                let picture = self.picture(&source, inline.size);
+                let (x, rows) = (self.cursor.position.x, picture.rows);
+
+                self.images.push(source, inline.size, picture, (x as usize, self.history.len() as i32 + self.cursor.position.y), self.mode.decalt);
+
+                for _ in 0..rows {
+                    self.line_feed();
+                }
+
+                self.cursor.position.x = x;
             },
-        })
+            Err(err) => println!("[+] failed to decode inline image: {}", err),
+        }
     }
 
-    fn read_tty(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut more_to_read = true;
+    fn picture(&self, source: &RgbaImage, size: graphics::Size) -> Picture {
+        let (r, g, b) = self.reverse_video(Attribute::new(&self.config)).bg.raw.rgb();
+
+        graphics::scale(
+            source,
+            size,
+            (self.cell.width as u32, self.cell.height as u32),
+            (self.cols, self.rows),
+            (r as u8, g as u8, b as u8),
+            |r, g, b| self.display.pixel(xlib::Color::new(r as u64, g as u64, b as u64)) as u32,
+        )
+    }
 
-        while more_to_read {
-            let mut buffer: Vec<u8> = vec![0; 2048];
+    // the absolute row of a screen line, images are anchored to those
 
-            match self.screen.pty.file.read(&mut buffer) {
-                Ok(0) => {},
-                Ok(bytes) => {
-                    self.handle_bytes(&buffer[..bytes])?;
-                },
-                Err(err) => {
-                    match err.kind() {
-                        ErrorKind::WouldBlock => more_to_read = false,
-                        ErrorKind::Interrupted => {},
-                        _ => return Err(Box::new(err)),
-                    }
-                },
+    fn screen_row(&self, y: usize) -> i32 {
+        self.history.len() as i32 + y as i32
+    }
+
+    fn sgr_string(&self, attr: Attribute) -> String {
+        let mut params = vec![String::from("0")];
+
+        for (flag, param) in [(BOLD, "1"), (FAINT, "2"), (ITALIC, "3"), (BLINK, "5"), (INVERSE, "7"), (CONCEAL, "8"), (STRIKETHROUGH, "9")] {
+            if attr.flags & flag != 0 {
+                params.push(param.to_string());
             }
         }
 
-        Ok(())
+        match attr.underline {
+            Underline::None => {},
+            Underline::Single => params.push(String::from("4")),
+            Underline::Double => params.push(String::from("4:2")),
+            Underline::Curly => params.push(String::from("4:3")),
+            Underline::Dotted => params.push(String::from("4:4")),
+            Underline::Dashed => params.push(String::from("4:5")),
+        }
+
+        if attr.fg != self.config.fg {
+            params.push(self.color_param(attr.fg, 30));
+        }
+
+        if attr.bg != self.config.bg {
+            params.push(self.color_param(attr.bg, 40));
+        }
+
+        if let Some(color) = attr.underline_color {
+            let (r, g, b) = color.raw.rgb();
+
+            params.push(format!("58:2::{}:{}:{}", r, g, b));
+        }
+
+        params.join(";")
     }
 
-    fn handle_bytes(&mut self, bytes: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        for byte in bytes {
-            if let Ok(Some(action)) = self.parser.advance(*byte) {
-                match action {
-                    Action::Print(c) => {
-                        self.screen.print(c);
-                    },
-                    Action::Execute(byte) => {
-                        self.screen.execute(byte);
-                    },
-                    Action::CsiDispatch(params, intermediates, c) => {
-                        self.screen.csi_dispatch(&params, intermediates, c)?;
-                    },
-                    Action::EscDispatch(intermediates, c) => {
-                        self.screen.esc_dispatch(intermediates, c)?;
+    // palette colors are reported by index, anything else as truecolor
+
+    fn color_param(&self, color: config::UniColor, base: u16) -> String {
+        match self.config.colors.iter().position(|palette| *palette == color) {
+            Some(index @ 0..=7) => (base + index as u16).to_string(),
+            Some(index) => (base + 60 + index as u16 - 8).to_string(),
+            None => {
+                let (r, g, b) = color.raw.rgb();
+
+                format!("{};2;{};{};{}", base + 8, r, g, b)
+            },
+        }
+    }
+
+    fn cursor_style_param(&self) -> u8 {
+        let steady = !self.mode.att610 as u8;
+
+        match self.cursor_style {
+            CursorStyle::Block => 1 + steady,
+            CursorStyle::Underline => 3 + steady,
+            CursorStyle::Line => 5 + steady,
+        }
+    }
+
+    fn notify(&mut self, title: &str, body: &str) {
+        let throttled = self.last_notification.is_some_and(|last| last.elapsed() < Duration::from_millis(self.config.notification_interval));
+
+        if self.config.notifications && !throttled {
+            self.notifier.get_or_insert_with(Notifier::new).send(title, body);
+
+            self.last_notification = Some(Instant::now());
+        }
+    }
+
+    fn print_text(&self, text: String) {
+        if self.config.print_command.is_empty() {
+            return;
+        }
+
+        match Printer::open(&self.config.print_command) {
+            Ok(printer) => printer.send(text),
+            Err(err) => println!("[+] failed to run print command: {}", err),
+        }
+    }
+
+    // auto print keeps a single print command running and streams every line into it until auto print ends
+
+    fn auto_print_line(&mut self, text: String) {
+        if self.printer.is_none() && !self.config.print_command.is_empty() {
+            self.printer = Printer::open(&self.config.print_command)
+                .map_err(|err| println!("[+] failed to run print command: {}", err))
+                .ok();
+        }
+
+        if let Some(printer) = self.printer.as_ref() {
+            printer.send(text);
+        }
+    }
+
+    fn watch_layout(&mut self) {
+        if self.config.layout_indicator || !self.config.layout_hook.is_empty() {
+            self.xkb_event = self.display.select_layout_events();
+
+            self.update_layout();
+        }
+    }
+
+    fn update_layout(&mut self) {
+        let layout = self.display.layout();
+
+        if layout.is_some() && layout != self.layout {
+            let name = layout.clone().unwrap_or_default();
+
+            // the hook only fires on changes, not for the layout we started with
+
+            if self.layout.is_some() && !self.config.layout_hook.is_empty() {
+                let child = process::Command::new("sh")
+                    .arg("-c")
+                    .arg(&self.config.layout_hook)
+                    .env("TERMAL_LAYOUT", &name)
+                    .stdin(process::Stdio::null())
+                    .stdout(process::Stdio::null())
+                    .spawn();
+
+                match child {
+                    Ok(mut child) => {
+                        thread::spawn(move || child.wait());
                     },
-                    Action::OscDispatch(_) => {},
+                    Err(err) => println!("[+] failed to run layout hook: {}", err),
                 }
             }
+
+            self.layout = layout;
+
+            self.update_title();
+        }
+    }
+
+    fn update_title(&mut self) {
+        // a title set by the application replaces the configured template
+
+        let mut name = match &self.app_title {
+            Some(title) => title.clone(),
+            None => title::expand(&self.config.title, &self.user_vars),
+        };
+
+        if let Some(layout) = self.layout.as_ref().filter(|_| self.config.layout_indicator) {
+            name.push_str(&format!(" [{}]", layout));
         }
 
-        self.screen.refresh = true;
+        self.display.set_window_name(&name);
+    }
 
-        Ok(())
+    fn sgr_color(&mut self, params: &[u16], subparams: u128) -> (Option<config::UniColor>, usize) {
+        // params start after the 38/48/58, the colon form may carry a color space id before the rgb values
+
+        let colon = subparams & 1 != 0;
+        let count = if colon { (subparams.trailing_ones() as usize).min(params.len()) } else { params.len() };
+
+        let (raw, consumed) = match params.first() {
+            Some(2) => {
+                let rgb = if colon && count >= 5 { &params[2..5] } else { &params[1..count.min(4)] };
+
+                let raw = xlib::Color::new(
+                    *rgb.first().unwrap_or(&0) as u64,
+                    *rgb.get(1).unwrap_or(&0) as u64,
+                    *rgb.get(2).unwrap_or(&0) as u64,
+                );
+
+                (Some(raw), if colon { count } else { count.min(4) })
+            },
+            Some(5) => {
+                let consumed = if colon { count } else { count.min(2) };
+
+                match params.get(1) {
+                    Some(index) if *index < 16 => return (Some(self.config.colors[*index as usize]), consumed),
+                    Some(index) => (Some(indexed_color(*index)), consumed),
+                    None => (None, consumed),
+                }
+            },
+            Some(mode) => {
+                println!("[+] unimplemented SGR mode: {}", mode);
+
+                (None, if colon { count } else { 1 })
+            },
+            None => (None, 0),
+        };
+
+        match raw.filter(|_| !self.config.high_contrast) {
+            Some(raw) => match self.display.xft_color_alloc_value(raw) {
+                Ok(xft) => (Some(config::UniColor { raw, xft }), consumed),
+                Err(_) => {
+                    println!("[+] failed to create color: {:?}", raw);
+
+                    (None, consumed)
+                },
+            },
+            None => (None, consumed),
+        }
     }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.screen.display.set_window_name("termal");
-        self.screen.display.define_cursor();
-        self.screen.display.select_input();
-        self.screen.display.map_window();
-        self.screen.display.flush();
+    fn set_urgency(&mut self, urgent: bool) {
+        if urgent == self.urgent {
+            return;
+        }
 
-        // TODO: clean up mode and button handling
+        self.urgent = urgent;
 
-        unsafe {
-            let flags = libc::fcntl(self.screen.pty.file.as_raw_fd(), libc::F_GETFL, 0) | libc::O_NONBLOCK;
+        match self.config.urgency {
+            Urgency::None => {},
+            Urgency::Hints => self.display.set_urgency_hint(urgent),
+            Urgency::DemandsAttention => self.display.set_demands_attention(urgent),
+        }
+    }
 
-            libc::fcntl(self.screen.pty.file.as_raw_fd(), libc::F_SETFL, flags);
+    fn decom_clamp(&mut self) {
+        if self.cursor.position.y < self.scrolling_region.top as i32 {
+            self.cursor.position.y = self.scrolling_region.top as i32;
+        } else if self.cursor.position.y > self.scrolling_region.bottom as i32 {
+            self.cursor.position.y = self.scrolling_region.bottom as i32;
         }
+    }
 
-        while !self.screen.should_close {
-            let render_time = Instant::now();
+    fn handle_key(&mut self, event: x11::xlib::XKeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let keysym = self.display.keycode_to_keysym(event.keycode as u8) as u32;
 
-            self.read_tty()?;
+        if self.menu.open {
+            self.close_menu();
+        }
 
-            if let Some(events) = self.screen.display.poll_event() {
-                for event in events {
-                    self.screen.handle_event(event)?;
-                }
+        if self.palette.open {
+            return self.handle_palette_key(event, keysym);
+        }
+
+        if self.command_history.open {
+            return self.handle_history_key(event, keysym);
+        }
+
+        if self.search.open {
+            return self.handle_search_key(event, keysym);
+        }
+
+        if let Some(command) = keybind::lookup(&self.config.keybinds, keysym, event.state) {
+            // counts the auto repeated presses of a held binding, the release of the key resets it
+
+            self.binding_repeat = match self.binding_repeat {
+                Some((keycode, repeats)) if keycode == event.keycode => Some((keycode, repeats + 1)),
+                _ => Some((event.keycode, 0)),
+            };
+
+            return self.run_command(command);
+        }
+
+        self.scroll_view(-(self.scroll as i32));
+
+        // x only repeats presses once detectable auto repeat is on, a press for a key that is still down is a repeat
+
+        let key_event = if self.keys_down.contains(&event.keycode) {
+            kitty::KeyEvent::Repeat
+        } else {
+            if event.keycode != 0 {
+                self.keys_down.push(event.keycode);
             }
 
-            if self.screen.refresh {
-                self.screen.draw()?;
+            kitty::KeyEvent::Press
+        };
+
+        let flags = self.keyboard.last().copied().unwrap_or(0);
+
+        if let Some(sequence) = kitty::sequence(keysym, event.state, flags, key_event).filter(|_| flags != 0) {
+            self.pty.write_all(sequence.as_bytes())?;
+        } else if let Some(sequence) = other_key_sequence(keysym, event.state, self.other_keys, self.config.key_format) {
+            self.pty.write_all(sequence.as_bytes())?;
+        } else if let Some(sequence) = key_sequence(keysym, event.state, self.mode.decckm, self.mode.deckpam) {
+            self.pty.write_all(sequence.as_bytes())?;
+        } else {
+            let mut content = self.display.lookup_string(event)?;
+
+            content = content.chars().filter(|x| *x != '\0').collect();
+
+            if !content.is_empty() && event.state & x11::xlib::Mod1Mask != 0 && self.config.alt_sends_escape {
+                content.insert(0, '\x1b');
             }
 
-            thread::sleep(Duration::from_millis(8 - render_time.elapsed().subsec_millis().min(8) as u64));
+            if !content.is_empty() {
+                self.pty.write_all(content.as_bytes())?;
+            }
         }
 
         Ok(())
     }
-}
 
-fn is_cursor_key(keysym: u32) -> bool {
-    matches!(
-        keysym,
-        x11::keysym::XK_Up
-        | x11::keysym::XK_Down
-        | x11::keysym::XK_Left
-        | x11::keysym::XK_Right
-    )
-}
+    fn handle_search_key(&mut self, event: x11::xlib::XKeyEvent, keysym: u32) -> Result<(), Box<dyn std::error::Error>> {
+        match keysym {
+            x11::keysym::XK_Escape => self.run_command(Command::Search)?,
+            x11::keysym::XK_Return | x11::keysym::XK_KP_Enter | x11::keysym::XK_Up => {
+                self.search.select_older();
+                self.show_match();
+            },
+            x11::keysym::XK_Down => {
+                self.search.select_newer();
+                self.show_match();
+            },
+            x11::keysym::XK_BackSpace => {
+                self.search.query.pop();
+                self.update_search();
+            },
+            x11::keysym::XK_r if event.state & x11::xlib::ControlMask != 0 => {
+                self.search.regex = !self.search.regex;
+                self.update_search();
+            },
+            _ => {
+                let content = self.display.lookup_string(event)?;
 
-fn is_special_key(keysym: u32) -> bool {
-    matches!(
-        keysym,
-        x11::keysym::XK_Up
-        | x11::keysym::XK_BackSpace
-        | x11::keysym::XK_F10
-        | x11::keysym::XK_Escape
-    )
-}
+                self.search.query.extend(content.chars().filter(|x| !x.is_control()));
+                self.update_search();
+            },
+        }
+
+        self.refresh = true;
+
+        Ok(())
+    }
+
+    fn update_search(&mut self) {
+        let last = (self.history.len() + self.buf.len()) as i32;
+
+        let lines = (0..last)
+            .filter_map(|y| self.line(y).map(|line| (y, line.iter().map(|character| character.byte).collect())))
+            .collect::<Vec<(i32, Vec<char>)>>();
+
+        self.search.update(lines.into_iter());
+
+        self.show_match();
+    }
+
+    // the selected match becomes the selection so it is highlighted and can be copied right away
+
+    fn show_match(&mut self) {
+        match self.search.selected() {
+            Some(found) => {
+                self.selection = Selection {
+                    start: Position { x: found.start as i32, y: found.y },
+                    end: Position { x: found.end as i32, y: found.y },
+                    selecting: false,
+                };
+
+                let row = found.y - self.view_to_abs(0);
+
+                if !(0..self.rows as i32).contains(&row) {
+                    self.scroll_view(self.history.len() as i32 - found.y - self.scroll as i32);
+                }
+            },
+            None => self.selection.end = self.selection.start,
+        }
+
+        self.full_dirt();
+    }
+
+    fn handle_key_release(&mut self, event: x11::xlib::XKeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        if self.binding_repeat.is_some_and(|(keycode, _)| keycode == event.keycode) {
+            self.binding_repeat = None;
+        }
+
+        let Some(index) = self.keys_down.iter().position(|keycode| *keycode == event.keycode) else { return Ok(()) };
+
+        self.keys_down.remove(index);
+
+        let keysym = self.display.keycode_to_keysym(event.keycode as u8) as u32;
+        let flags = self.keyboard.last().copied().unwrap_or(0);
+
+        if let Some(sequence) = kitty::sequence(keysym, event.state, flags, kitty::KeyEvent::Release) {
+            self.pty.write_all(sequence.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_history_key(&mut self, event: x11::xlib::XKeyEvent, keysym: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let count = self.command_history.entries().len();
+
+        match keysym {
+            x11::keysym::XK_Escape => self.run_command(Command::HistorySearch)?,
+            x11::keysym::XK_Return | x11::keysym::XK_KP_Enter => {
+                let command = self.command_history.entries().get(self.command_history.selected).map(|command| command.to_string());
+
+                self.run_command(Command::HistorySearch)?;
+
+                // the command is left at the prompt for editing, it is never run directly
+
+                if let Some(command) = command {
+                    self.paste(&command)?;
+                }
+            },
+            x11::keysym::XK_Up => self.command_history.select_prev(count),
+            x11::keysym::XK_Down | x11::keysym::XK_Tab => self.command_history.select_next(count),
+            x11::keysym::XK_BackSpace => {
+                self.command_history.query.pop();
+                self.command_history.selected = 0;
+            },
+            _ => {
+                let content = self.display.lookup_string(event)?;
+
+                self.command_history.query.extend(content.chars().filter(|x| !x.is_control()));
+                self.command_history.selected = 0;
+            },
+        }
+
+        self.refresh = true;
 
+        Ok(())
+    }
+
+    fn handle_palette_key(&mut self, event: x11::xlib::XKeyEvent, keysym: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let entries = self.palette.entries(&self.config.keybinds);
+
+        match keysym {
+            x11::keysym::XK_Escape => self.run_command(Command::Palette)?,
+            x11::keysym::XK_Return | x11::keysym::XK_KP_Enter => {
+                let command = entries.get(self.palette.selected).map(|(command, _)| *command);
+
+                self.run_command(Command::Palette)?;
+
+                if let Some(command) = command {
+                    self.run_command(command)?;
+                }
+            },
+            x11::keysym::XK_Up => self.palette.select_prev(entries.len()),
+            x11::keysym::XK_Down | x11::keysym::XK_Tab => self.palette.select_next(entries.len()),
+            x11::keysym::XK_BackSpace => {
+                self.palette.query.pop();
+                self.palette.selected = 0;
+            },
+            _ => {
+                let content = self.display.lookup_string(event)?;
+
+                self.palette.query.extend(content.chars().filter(|x| !x.is_control()));
+                self.palette.selected = 0;
+            },
+        }
+
+        self.refresh = true;
+
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: Command) -> Result<(), Box<dyn std::error::Error>> {
+        match command {
+            Command::Copy => {
+                if let Some(selection) = self.get_selection(LinkCopy::Text) {
+                    self.copy(selection)?;
+                }
+            },
+            Command::CopyLink => {
+                if let Some(selection) = self.get_selection(LinkCopy::Uri) {
+                    self.copy(selection)?;
+                }
+            },
+            Command::CopyWithLink => {
+                if let Some(selection) = self.get_selection(LinkCopy::Both) {
+                    self.copy(selection)?;
+                }
+            },
+            Command::Paste => {
+                if let Some(selection) = self.display.clipboard() {
+                    self.paste(&paste::transform(&selection, &self.config.paste))?;
+                }
+            },
+            Command::PasteRaw => {
+                if let Some(selection) = self.display.clipboard() {
+                    self.paste(&selection)?;
+                }
+            },
+            Command::Palette => {
+                self.palette.toggle();
+
+                if !self.palette.open {
+                    self.full_dirt();
+                }
+            },
+            Command::HistorySearch => {
+                self.command_history.toggle();
+
+                if !self.command_history.open {
+                    self.full_dirt();
+                }
+            },
+            Command::Search => {
+                self.search.toggle();
+
+                if !self.search.open {
+                    self.full_dirt();
+                }
+            },
+            Command::ZoomIn => self.set_font_size(self.font_size + 1.0)?,
+            Command::ZoomOut => self.set_font_size(self.font_size - 1.0)?,
+            Command::ZoomReset => self.set_font_size(config::font_size(&self.config.font))?,
+            Command::NewWindow => {
+                process::Command::new(env::current_exe()?).spawn()?;
+            },
+            Command::ScrollPageUp => self.scroll_view(self.rows as i32),
+            Command::ScrollPageDown => self.scroll_view(-(self.rows as i32)),
+            Command::ScrollLineUp => self.scroll_view(self.scroll_step()),
+            Command::ScrollLineDown => self.scroll_view(-self.scroll_step()),
+            Command::PromptPrev => self.jump_to_prompt(false),
+            Command::PromptNext => self.jump_to_prompt(true),
+            Command::SelectLastOutput => self.select_last_output(),
+            Command::CopyLastOutput => {
+                // only the output between the OSC 133 C and D marks, the prompt and command line are left out
+
+                if let Some(output) = self.extent_text(ipc::Extent::LastCommand, false) {
+                    self.copy(output)?;
+                }
+            },
+            Command::Inspect => {
+                self.inspect = !self.inspect;
+
+                self.full_dirt();
+            },
+            Command::OpenUrl => {
+                let y = self.view_to_abs(self.pointer.y);
+
+                if let Some(line) = self.line(y) {
+                    let link = line.get(self.pointer.x as usize).map_or(0, |character| character.attr.link);
+
+                    let target = match self.links.get(link) {
+                        Some(uri) => Some(uri.to_string()),
+                        None => url::find(&line.iter().map(|character| character.byte).collect::<Vec<char>>(), self.pointer.x as usize),
+                    };
+
+                    match target {
+                        Some(target) => self.open(&target),
+                        None => println!("[+] no url under the pointer"),
+                    }
+                }
+            },
+            Command::SearchSelection => {
+                if let Some(selection) = self.get_selection(LinkCopy::Text) {
+                    self.open(&self.config.search_url.replace("{}", &url::encode(selection.trim())));
+                }
+            },
+            Command::Fullscreen => self.display.toggle_fullscreen(),
+            Command::RenderScale => self.set_scale(if self.scale == 1 { self.config.render_scale } else { 1 })?,
+        }
+
+        self.refresh = true;
+
+        Ok(())
+    }
+
+    fn set_font_size(&mut self, size: f64) -> Result<(), Box<dyn std::error::Error>> {
+        self.load_font(size)?;
+
+        self.resize(self.window.width, self.window.height)
+    }
+
+    // the font is loaded at scale times its size, font_size stays the unscaled size so zooming keeps working
+
+    fn load_font(&mut self, size: f64) -> Result<(), Box<dyn std::error::Error>> {
+        let size = size.max(self.config.min_font_size).max(1.0);
+
+        let xft = Xft::load(self.display.as_mut(), &config::font_with_size(&self.config.font, size * self.scale as f64))?;
+
+        self.xft.close(self.display.as_mut());
+
+        self.cell = Cell::from_font(self.display.as_ref(), xft.font);
+        self.xft = xft;
+        self.font_size = size;
+
+        Ok(())
+    }
+
+    // scaling grows the window along with the cells so the pty keeps its rows and columns, the window manager may still refuse the new size
+
+    fn set_scale(&mut self, scale: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.scale = scale;
+
+        self.load_font(self.font_size)?;
+
+        let (width, height) = (self.cols as u32 * self.cell.width as u32, self.rows as u32 * self.cell.height as u32);
+
+        self.display.resize_window(width, height);
+
+        self.resize(width, height)
+    }
+
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), Box<dyn std::error::Error>> {
+        // cols and rows only change here, loading a font is always followed by a resize
+
+        self.window = Window {
+            width,
+            height,
+        };
+
+        self.cols = width as usize / self.cell.width as usize;
+        self.rows = height as usize / self.cell.height as usize;
+
+        self.display.resize_back_buffer(&self.window);
+        self.pty.resize(self.cols as u16, self.rows as u16)?;
+        self.full_dirt();
+
+        // the back buffer is new, there is nothing to copy
+
+        self.blit = None;
+
+        let default_ch = Character { attr: Attribute::new(&self.config), byte: ' ' };
+
+        self.buf.resize(self.rows + 1, Line::new(default_ch, self.cols + 1));
+        self.alt.buf.resize(self.rows + 1, Line::new(default_ch, self.cols + 1));
+
+        self.buf.iter_mut().for_each(|line| line.resize(self.cols + 1, default_ch));
+        self.alt.buf.iter_mut().for_each(|line| line.resize(self.cols + 1, default_ch));
+
+        // keep the stops that were set explicitly, new columns get the default interval
+
+        let start = self.tabs.len();
+
+        self.tabs.truncate(self.cols + 1);
+        self.tabs.extend((start..self.cols + 1).map(|x| x % self.config.tab_interval == 0));
+
+        if !self.scroll_set {
+            self.scrolling_region.bottom = self.rows - 1;
+        }
+
+        if !self.mode.declrmm || self.scrolling_region.right >= self.cols {
+            self.scrolling_region.left = 0;
+            self.scrolling_region.right = self.cols - 1;
+        }
+
+        self.cursor.position.y = self.cursor.position.y.min(self.rows as i32 - 1);
+        self.cursor.position.x = self.cursor.position.x.min(self.cols as i32 - 1);
+
+        // images on the rows cut off the bottom go with them, the rest is scaled to the new cells once it is drawn
+
+        let bottom = self.screen_row(self.buf.len());
+
+        self.images.erase(bottom..i32::MAX, false);
+        self.images.erase(bottom..i32::MAX, true);
+        self.images.rescale();
+        self.refresh = true;
+
+        Ok(())
+    }
+
+    fn get_line(&self, y: i32, start: usize, end: usize, copy: LinkCopy) -> String {
+        if let Some(line) = self.line(y) {
+            let end = end.min(line.len());
+
+            let cells = line[start.min(end)..end].iter()
+                .filter(|c| c.attr.flags & WIDE_SPACER == 0)
+                .map(|c| (c.byte, c.attr.link));
+
+            copy_links(cells, &self.links, copy)
+        } else {
+            String::new()
+        }
+    }
+
+    // the clipboard is served from its own thread so a slow requestor never stalls drawing, the limit keeps huge scrollback copies bounded
+
+    fn copy(&mut self, mut text: String) -> Result<(), Box<dyn std::error::Error>> {
+        if paste::truncate(&mut text, self.config.selection_limit) {
+            println!("[+] selection cut to {} bytes", text.len());
+        }
+
+        self.display.set_clipboard(text)?;
+
+        Ok(())
+    }
+
+    fn get_selection(&mut self, copy: LinkCopy) -> Option<String> {
+        let mut start = self.selection.start;
+        let mut end = self.selection.end;
+
+        if start.y == end.y {
+            if start.x > end.x {
+                Some(self.get_line(start.y, end.x as usize, start.x as usize, copy))
+            } else if start.x < end.x {
+                Some(self.get_line(start.y, start.x as usize, end.x as usize, copy))
+            } else {
+                None
+            }
+        } else {
+            if end.y < start.y {
+                std::mem::swap(&mut start, &mut end);
+            }
+
+            let mut content = String::new();
+
+            for y in start.y..=end.y {
+                if y == start.y {
+                    content.push_str(&self.get_line(y, start.x as usize, usize::MAX, copy));
+                } else if y == end.y {
+                    content.push_str(&self.get_line(y, 0, end.x as usize, copy));
+                } else {
+                    content.push_str(&self.get_line(y, 0, usize::MAX, copy));
+                }
+
+                content.push('\n');
+            }
+
+            Some(content)
+        }
+    }
+
+    // rows the shell wrapped are joined as they are, a row that ends early ended a line of a multi-line command
+
+    fn command_line(&self, start: Position) -> String {
+        let cursor = self.cursor.position.y + self.history.len() as i32;
+        let end = if self.cursor.position.x == 0 && cursor > start.y { cursor - 1 } else { cursor };
+
+        let mut command = String::new();
+
+        for y in start.y..=end {
+            let row = self.get_line(y, if y == start.y { start.x as usize } else { 0 }, usize::MAX, LinkCopy::Text);
+
+            if row.ends_with(' ') && y != end {
+                command.push_str(row.trim_end());
+                command.push('\n');
+            } else {
+                command.push_str(&row);
+            }
+        }
+
+        command.trim().to_string()
+    }
+
+    fn paste(&mut self, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.mode.decpaste {
+            self.write_tty_raw(&format!("\x1b[200~{}\x1b[201~", content))
+        } else {
+            self.write_tty_raw(content)
+        }
+    }
+
+    // a vt220 with ansi color, https://vt100.net/docs/vt510-rm/DA1.html
+
+    fn primary_attributes(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_tty_raw("\x1b[?62;22c")
+    }
+
+    // the firmware version is the crate version as major * 10000 + minor * 100 + patch
+
+    fn secondary_attributes(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let version = env!("CARGO_PKG_VERSION")
+            .split('.')
+            .take(3)
+            .fold(0, |version, part| version * 100 + part.parse::<u32>().unwrap_or(0));
+
+        self.write_tty_raw(&format!("\x1b[>1;{};0c", version))
+    }
+
+    fn write_tty_raw(&mut self, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if !content.is_empty() {
+            self.pty.write_all(content.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html, alternate scroll mode. the alternate screen has no scrollback so
+    // the wheel becomes arrow keys for pagers and editors
+
+    fn alternate_scroll(&mut self, keysym: u32) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(sequence) = key_sequence(keysym, 0, self.mode.decckm, false).filter(|_| self.mode.decas) {
+            self.write_tty_raw(&sequence.repeat(self.config.alternate_scroll_lines))?;
+        }
+
+        Ok(())
+    }
+
+    // 1006 on its own keeps enabling tracking, applications asking for sgr reports never set 1000 before
+
+    #[inline]
+    fn mouse_tracking(&self) -> bool {
+        self.mode.decnm || self.mode.decmm || self.mode.decdm
+    }
+
+    fn handle_mouse_motion(&mut self, x: i32, y: i32, type_: i32) -> Result<(), Box<dyn std::error::Error>> {
+        let report = match type_ {
+            x11::xlib::MotionNotify => (self.mode.decmm && self.buttons != Buttons::None) || (!self.mode.decmm && self.mode.decdm),
+            _ => self.mouse_tracking(),
+        };
+
+        if report {
+            let encoding = if self.mode.decdm {
+                MouseEncoding::Sgr
+            } else if self.mode.decrm {
+                MouseEncoding::Urxvt
+            } else if self.mode.decum {
+                MouseEncoding::Utf8
+            } else {
+                MouseEncoding::X10
+            };
+
+            let code = if type_ == x11::xlib::MotionNotify { 32 } else { self.buttons.as_code() };
+
+            let cell = self.cell_at(x, y);
+
+            if let Some(report) = mouse_report(encoding, code, cell.x + 1, cell.y + 1, type_ == x11::xlib::ButtonRelease) {
+                self.pty.write_all(&report)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: x11::xlib::XEvent) -> Result<(), Box<dyn std::error::Error>> {
+        match unsafe { event.type_ } {
+            x11::xlib::KeyPress => {
+                self.handle_key(unsafe { event.key })?;
+            },
+            x11::xlib::KeyRelease => {
+                self.handle_key_release(unsafe { event.key })?;
+            },
+            x11::xlib::ButtonPress if self.menu.open => {
+                let entry = self.menu.entry_at(unsafe { event.button.x }, unsafe { event.button.y });
+
+                self.close_menu();
+
+                if let Some((command, _)) = entry.filter(|_| unsafe { event.button.button } == x11::xlib::Button1).and_then(|entry| menu::ENTRIES.get(entry)) {
+                    self.run_command(*command)?;
+                }
+            },
+            x11::xlib::ButtonPress => {
+                match unsafe { event.button.button } {
+                    x11::xlib::Button4 => {
+                        self.buttons = Buttons::ScrollUp;
+
+                        if !self.mouse_tracking() && !self.mode.decalt {
+                            self.scroll_view(SCROLL_LINES);
+                        } else if !self.mouse_tracking() {
+                            self.alternate_scroll(x11::keysym::XK_Up)?;
+                        } else {
+                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonPress)?;
+                        }
+
+                        self.refresh = true;
+                    },
+                    x11::xlib::Button5 => {
+                        self.buttons = Buttons::ScrollDown;
+
+                        if !self.mouse_tracking() && !self.mode.decalt {
+                            self.scroll_view(-SCROLL_LINES);
+                        } else if !self.mouse_tracking() {
+                            self.alternate_scroll(x11::keysym::XK_Down)?;
+                        } else {
+                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonPress)?;
+                        }
+
+                        self.refresh = true;
+                    },
+                    x11::xlib::Button1 => {
+                        self.buttons = Buttons::Button1;
+
+                        if !self.mouse_tracking() {
+                            self.selection.start = self.selection_at(unsafe { event.button.x }, unsafe { event.button.y });
+                            self.selection.end = self.selection.start;
+
+                            self.selection.selecting = true;
+                            self.refresh = true;
+                        } else {
+                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonPress)?;
+                        }
+                    },
+                    x11::xlib::Button2 => {
+                        self.buttons = Buttons::Button2;
+
+                        if self.mouse_tracking() {
+                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonPress)?;
+                        }
+                    },
+                    x11::xlib::Button3 => {
+                        // shift gets the menu even when the application is tracking the mouse
+
+                        if self.mouse_tracking() && unsafe { event.button.state } & x11::xlib::ShiftMask == 0 {
+                            self.buttons = Buttons::Button3;
+
+                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonPress)?;
+                        } else {
+                            self.menu.open_at(
+                                unsafe { event.button.x },
+                                unsafe { event.button.y },
+                                (self.window.width as i32, self.window.height as i32),
+                                (self.cell.width, self.cell.height),
+                            );
+
+                            self.refresh = true;
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            x11::xlib::ButtonRelease => {
+                match unsafe { event.button.button } {
+                    x11::xlib::Button1 | x11::xlib::Button2 | x11::xlib::Button3 | x11::xlib::Button4 | x11::xlib::Button5 => {
+                        if unsafe { event.button.button } == x11::xlib::Button1 {
+                            self.selection.selecting = false;
+                        }
+
+                        if self.mouse_tracking() {
+                            self.handle_mouse_motion(unsafe { event.button.x }, unsafe { event.button.y }, x11::xlib::ButtonRelease)?;
+                        }
+
+                        self.buttons = Buttons::None;
+                    },
+                    _ => {},
+                }
+            },
+            x11::xlib::MotionNotify => {
+                let pointer = self.cell_at(unsafe { event.motion.x }, unsafe { event.motion.y });
+
+                if self.inspect && pointer != self.pointer {
+                    // the overlay follows the pointer, repaint what it covered
+
+                    self.full_dirt();
+
+                    self.refresh = true;
+                }
+
+                self.pointer = pointer;
+
+                if self.menu.open {
+                    let selected = self.menu.entry_at(unsafe { event.motion.x }, unsafe { event.motion.y });
+
+                    if selected != self.menu.selected {
+                        self.menu.selected = selected;
+
+                        self.refresh = true;
+                    }
+                }
+
+                if self.mouse_tracking() {
+                    self.handle_mouse_motion(unsafe { event.motion.x }, unsafe { event.motion.y }, x11::xlib::MotionNotify)?;
+                } else if self.selection.selecting {
+                    self.selection.end = self.selection_at(unsafe { event.motion.x }, unsafe { event.motion.y });
+
+                    self.refresh = true;
+                }
+            },
+            x11::xlib::Expose => {
+                /*
+                 * TODO: screenshots seem to cause a Expose event with a fucked up width and height
+                 *
+                 * we may need to handle other forms of resizing
+                 *
+                */
+
+                let width = unsafe { event.expose.width } as u32;
+                let height = unsafe { event.expose.height } as u32;
+
+                if width != self.window.width || height != self.window.height {
+                    self.resize(width, height)?;
+                }
+            },
+            x11::xlib::VisibilityNotify => {
+                self.full_dirt();
+
+                self.refresh = true
+            },
+            x11::xlib::FocusIn => {
+                if self.mode.decfocus {
+                    self.write_tty_raw("\x1b[I")?;
+                }
+
+                self.focused = true;
+                self.refresh = true;
+
+                self.set_urgency(false);
+            },
+            x11::xlib::FocusOut => {
+                if self.mode.decfocus {
+                    self.write_tty_raw("\x1b[O")?;
+                }
+
+                // releases go to the window that has focus, keys still held would look like repeats when focus returns
+
+                self.keys_down.clear();
+                self.binding_repeat = None;
+                self.focused = false;
+                self.refresh = true;
+            },
+            event_type if self.xkb_event == Some(event_type) => self.update_layout(),
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn is_within_selection(&self, y: usize, x: usize, selection: &Selection) -> bool {
+        if selection.start == selection.end {
+            false
+        } else if selection.start.y == selection.end.y && y as i32 == selection.start.y {
+            x as i32 >= selection.start.x && (x as i32) < selection.end.x
+        } else if y as i32 == selection.start.y {
+            x as i32 >= selection.start.x
+        } else if y as i32 == selection.end.y {
+            x as i32 <= selection.end.x
+        } else if y as i32 > selection.start.y && (y as i32) < selection.end.y {
+            true
+        } else {
+            false
+        }
+    }
+
+    fn draw(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        /* making sure end.y is always bigger then start.y and end.x is always bigger start.x */
+
+        let mut selection = if self.selection.end.y > self.selection.start.y {
+            self.selection
+        } else {
+            Selection {
+                start: self.selection.end,
+                end: self.selection.start,
+                selecting: false,
+            }
+        };
+
+        if selection.start.x > selection.end.x && selection.start.y == selection.end.y {
+            let end = selection.end.x;
+
+            selection.end.x = selection.start.x;
+            selection.start.x = end;
+        }
+
+        self.hashes.resize(self.buf.len(), 0);
+
+        self.flush_blit();
+
+        // images are drawn over the cells every frame, the rows they covered last frame are repainted in case they moved
+
+        for y in std::mem::take(&mut self.image_rows) {
+            self.dirty.set_row(y, 0);
+        }
+
+        let top = self.view_to_abs(0);
+
+        let mut images = std::mem::take(&mut self.images);
+
+        images.refresh(top - self.rows as i32..top + self.rows as i32, self.mode.decalt, |source, size| self.picture(source, size));
+
+        self.images = images;
+
+        for image in self.images.iter(self.mode.decalt) {
+            for y in (image.rows().start - top).max(0)..(image.rows().end - top).min(self.rows as i32) {
+                self.image_rows.push(y as usize);
+                self.dirty.set_row(y as usize, 0);
+            }
+        }
+
+        let columns = self.cols as i32;
+        let padding = self.reverse_video(Attribute::new(&self.config)).bg.raw;
+
+        let mut glyph = [0; 4];
+        let Runs { mut runs, mut glyphs, mut drawn } = std::mem::take(&mut self.runs);
+
+        let mut repainted = Vec::new();
+
+        for y in (0..self.buf.len()).rev() {
+            let y_pos = y as i32 * self.cell.height;
+            let abs_y = y + self.history.len() - self.scroll;
+
+            let line = if y < self.scroll {
+                &self.history[abs_y]
+            } else {
+                &self.buf[y - self.scroll]
+            };
+
+            if self.a11y.is_some() && self.dirty.row(y) {
+                let mut text = line.iter().map(|character| character.byte).collect::<Vec<char>>();
+
+                text.truncate(text.iter().rposition(|c| !c.is_whitespace()).map_or(0, |x| x + 1));
+
+                repainted.push((y, text));
+            }
+
+            let is_selected_row = selection.start != selection.end && (selection.start.y..=selection.end.y).contains(&(abs_y as i32));
+
+            if (0..self.window.height as i32).contains(&y_pos) && (self.dirty.row(y) || is_selected_row) {
+                let scale = line.scale();
+
+                // backgrounds go first, then the glyphs in runs of one face and color and the decorations on top of both
+
+                runs.clear();
+                glyphs.clear();
+                drawn.clear();
+
+                for (x, character) in line.iter().enumerate() {
+                    if x * scale > columns as usize {
+                        break;
+                    }
+
+                    // spacers are drawn together with the wide character in front of them
+
+                    if character.attr.flags & WIDE_SPACER != 0 {
+                        continue;
+                    }
+
+                    let cells = if character.attr.flags & WIDE != 0 { 2 } else { 1 };
+
+                    let is_within_selection = self.is_within_selection(abs_y, x, &selection);
+
+                    if !(x..x + cells).any(|x| self.dirty.get(y, x)) && !is_within_selection {
+                        runs.push(None);
+
+                        continue;
+                    }
+
+                    for x in x..x + cells {
+                        if is_within_selection {
+                            self.dirty.set(y, x);
+                        } else {
+                            self.dirty.clear(y, x);
+                        }
+                    }
+
+                    let attr = self.resolve(character.attr, is_within_selection, false);
+
+                    let x_pos = (x * scale) as i32 * self.cell.width;
+
+                    self.display.draw_rec(x_pos, y_pos, self.cell.width as u32 * (cells * scale) as u32, self.cell.height as u32, attr.bg.raw);
+
+                    // the scrollback is never blinked, only the live screen is redrawn when the phase changes
+
+                    if attr.flags & BLINK != 0 && !self.blink_visible && y >= self.scroll {
+                        runs.push(None);
+
+                        continue;
+                    }
+
+                    drawn.push((x, cells, attr));
+
+                    // double height rows draw the double size font shifted up for the bottom half and clipped to the row,
+                    // double width rows keep the regular font centered in the wider cell
+
+                    match line.size {
+                        LineSize::Single | LineSize::DoubleWidth => {
+                            let face = self.xft.face(attr.flags);
+                            let glyph = (character.byte, x_pos + (scale as i32 - 1) * self.cell.width / 2);
+
+                            if character.byte != ' ' {
+                                glyphs.push(glyph);
+                            }
+
+                            match runs.last_mut() {
+                                Some(Some(run)) if run.face == face && run.fg == attr.fg.raw => {
+                                    run.end = x_pos + self.cell.width * (cells * scale) as i32;
+                                    run.glyphs.end = glyphs.len();
+                                },
+                                _ => runs.push(Some(GlyphRun {
+                                    face,
+                                    fg: attr.fg.raw,
+                                    color: attr.fg.xft,
+                                    start: x_pos,
+                                    end: x_pos + self.cell.width * (cells * scale) as i32,
+                                    glyphs: glyphs.len() - (character.byte != ' ') as usize..glyphs.len(),
+                                })),
+                            }
+                        },
+                        LineSize::DoubleTop | LineSize::DoubleBottom => {
+                            let top = if line.size == LineSize::DoubleTop { y_pos } else { y_pos - self.cell.height };
+
+                            self.display.xft_draw_string_clipped(
+                                character.byte.encode_utf8(&mut glyph),
+                                x_pos,
+                                top + self.cell.baseline * 2,
+                                (x_pos, y_pos, self.cell.width as u32 * (cells * scale) as u32, self.cell.height as u32),
+                                self.xft.double,
+                                &attr.fg.xft,
+                            );
+                        },
+                    }
+                }
+
+                for run in runs.iter().flatten().filter(|run| !run.glyphs.is_empty()) {
+                    self.display.xft_draw_glyphs(
+                        &glyphs[run.glyphs.clone()],
+                        y_pos + self.cell.baseline,
+                        (run.start, y_pos, (run.end - run.start) as u32, self.cell.height as u32),
+                        run.face,
+                        &run.color,
+                    );
+                }
+
+                let thickness = (self.cell.height / 16).clamp(1, 2);
+
+                for (x, cells, attr) in drawn.iter() {
+                    let (underline, decoration) = attr.decorations();
+
+                    if attr.underline != Underline::None && line.size != LineSize::DoubleTop {
+                        for x in x * scale..(x + cells) * scale {
+                            draw_underline(self.display.as_mut(), &self.cell, x as i32 * self.cell.width, y_pos, attr.underline, underline);
+                        }
+                    }
+
+                    // the midline of lowercase glyphs sits roughly a third of the ascent above the baseline
+
+                    let strike = match line.size {
+                        LineSize::Single | LineSize::DoubleWidth => y_pos + self.cell.baseline - self.cell.baseline / 3,
+                        LineSize::DoubleTop => y_pos + (self.cell.baseline - self.cell.baseline / 3) * 2,
+                        LineSize::DoubleBottom => y_pos - self.cell.height + (self.cell.baseline - self.cell.baseline / 3) * 2,
+                    };
+
+                    if attr.flags & STRIKETHROUGH != 0 && (y_pos..y_pos + self.cell.height).contains(&strike) {
+                        self.display.draw_rec(
+                            (x * scale) as i32 * self.cell.width,
+                            strike,
+                            self.cell.width as u32 * (cells * scale) as u32,
+                            thickness as u32,
+                            decoration,
+                        );
+                    }
+                }
+
+                // the pixels past the last full cell are padding and always get the theme background, this also
+                // covers scrollback lines that are shorter than the window after a resize
+
+                let start = ((line.len() * scale) as i32).min(columns) * self.cell.width;
+
+                if start < self.window.width as i32 {
+                    self.display.draw_rec(start, y_pos, self.window.width - start as u32, self.cell.height as u32, padding);
+                }
+
+                if y_pos + self.cell.height > self.window.height as i32 {
+                    self.display.draw_rec(0, y_pos, self.window.width, self.cell.height as u32, padding);
+                }
+
+                self.hashes[y] = line.content_hash();
+            }
+        }
+
+        self.runs = Runs { runs, glyphs, drawn };
+
+        for image in self.images.iter(self.mode.decalt).filter(|image| image.rows().start - top < self.rows as i32 && image.rows().end > top) {
+            let picture = &image.picture;
+
+            let start = image.visible.start as u32 * self.cell.height as u32;
+            let end = (image.visible.end as u32 * self.cell.height as u32).min(picture.height);
+
+            self.display.draw_image(
+                &picture.pixels,
+                (picture.width, picture.height),
+                (start as i32, end.saturating_sub(start)),
+                image.x as i32 * self.cell.width,
+                (image.rows().start - top) * self.cell.height,
+            );
+        }
+
+        let cursor_y = self.cursor.position.y + self.scroll as i32;
+
+        let cursor_color = self.reverse_video(Attribute::new(&self.config)).fg.raw;
+
+        let cursor_hidden = self.cursor_blinks() && !self.blink_visible;
+
+        if self.mode.dectecm && !cursor_hidden && (cursor_y as usize) < self.buf.len() {
+            let scale = self.buf.get(self.cursor.position.y as usize).map_or(1, |line| line.scale()) as u32;
+            let (column, span) = self.cursor_span();
+            let cells = span * scale;
+            let cursor_x = column * scale as i32 * self.cell.width;
+
+            let width = match self.cursor_style {
+                CursorStyle::Block | CursorStyle::Underline => self.cell.width as u32 * cells,
+                CursorStyle::Line => 2,
+            };
+
+            let height = match self.cursor_style {
+                CursorStyle::Block | CursorStyle::Line => self.cell.height as u32,
+                CursorStyle::Underline => (self.cell.height - self.cell.baseline).max(1) as u32,
+            };
+
+            let y = match self.cursor_style {
+                CursorStyle::Block | CursorStyle::Line => cursor_y * self.cell.height,
+                CursorStyle::Underline => (cursor_y * self.cell.height) + self.cell.baseline,
+            };
+
+            if !self.focused && self.cursor_style == CursorStyle::Block {
+                self.display.outline_rec(
+                    cursor_x,
+                    cursor_y * self.cell.height,
+                    self.cell.width as u32 * cells - 1,
+                    self.cell.height as u32 - 1,
+                    cursor_color,
+                );
+            } else {
+                self.display.draw_rec(
+                    cursor_x,
+                    y,
+                    width,
+                    height,
+                    cursor_color,
+                );
+            }
+
+            // the glyph under a block cursor is drawn again in the background color, a wide character is drawn whole across both cells
+
+            let line = &self.buf[self.cursor.position.y as usize];
+
+            if let Some(character) = line.get(column as usize).filter(|_| self.focused && self.cursor_style == CursorStyle::Block) {
+                let attr = self.resolve(character.attr, false, true);
+                let mut glyph = [0; 4];
+
+                if character.byte != ' ' && matches!(line.size, LineSize::Single | LineSize::DoubleWidth) {
+                    self.display.xft_draw_string(
+                        character.byte.encode_utf8(&mut glyph),
+                        cursor_x + (scale as i32 - 1) * self.cell.width / 2,
+                        cursor_y * self.cell.height + self.cell.baseline,
+                        self.rows as u32,
+                        self.cols as u32,
+                        self.xft.face(attr.flags),
+                        &attr.fg.xft,
+                    );
+                }
+            }
+
+            for x in column..column + span as i32 {
+                self.dirty.set(cursor_y as usize, x as usize);
+            }
+        }
+
+        self.dirty.set(cursor_y as usize, self.cursor.position.x as usize);
+
+        if self.scroll_indicator.is_some() {
+            self.draw_scroll_indicator();
+        }
+
+        if self.inspect {
+            self.draw_inspect();
+        }
+
+        if self.palette.open {
+            self.draw_palette();
+        }
+
+        if self.command_history.open {
+            self.draw_command_history();
+        }
+
+        if self.search.open {
+            self.draw_search();
+        }
+
+        if self.idle_warning.is_some() {
+            self.draw_idle_warning();
+        }
+
+        if self.menu.open {
+            self.draw_menu();
+        }
+
+        self.display.swap_buffers(&self.window);
+
+        self.update_accessibility(repainted);
+
+        self.refresh = false;
+
+        Ok(())
+    }
+
+    // the first column and the number of cells the cursor covers, on either half of a wide character it covers both
+
+    fn cursor_span(&self) -> (i32, u32) {
+        let (y, x) = (self.cursor.position.y as usize, self.cursor.position.x as usize);
+
+        if self.is_wide(y, x, WIDE) {
+            (x as i32, 2)
+        } else if x > 0 && self.is_wide(y, x, WIDE_SPACER) && self.is_wide(y, x - 1, WIDE) {
+            (x as i32 - 1, 2)
+        } else {
+            (x as i32, 1)
+        }
+    }
+
+    // every cell is drawn with the colors from here, each step works on the result of the one before it: reverse video,
+    // faint, conceal, the selection and finally a block cursor on top
+
+    fn resolve(&self, attr: Attribute, selected: bool, cursor: bool) -> Attribute {
+        let mut attr = self.reverse_video(attr);
+
+        if attr.flags & FAINT != 0 {
+            attr.fg = self.faint(attr.fg, attr.bg);
+        }
+
+        if attr.flags & CONCEAL != 0 {
+            attr.fg = attr.bg;
+            attr.underline_color = None;
+        }
+
+        if selected {
+            (attr.fg, attr.bg) = (attr.bg, attr.fg);
+        }
+
+        if cursor {
+            let color = self.reverse_video(Attribute::new(&self.config)).fg;
+
+            attr.fg = if attr.flags & CONCEAL != 0 { color } else { attr.bg };
+            attr.bg = color;
+        }
+
+        attr
+    }
+
+    // faint text is blended halfway into its background, the colors are allocated once and cached since xft colors are never freed
+
+    fn faint(&self, fg: config::UniColor, bg: config::UniColor) -> config::UniColor {
+        let raw = fg.raw.blend(&bg.raw);
+
+        let xft = *self.faint.borrow_mut()
+            .entry(raw.encode())
+            .or_insert_with(|| self.display.xft_color_alloc_value(raw).unwrap_or(fg.xft));
+
+        config::UniColor { raw, xft }
+    }
+
+    fn reverse_video(&self, mut attr: Attribute) -> Attribute {
+        // https://vt100.net/docs/vt510-rm/DECSCNM.html, only the default colors trade places
+
+        if self.mode.decscnm {
+            let swap = |color: config::UniColor| {
+                if color == self.config.fg {
+                    self.config.bg
+                } else if color == self.config.bg {
+                    self.config.fg
+                } else {
+                    color
+                }
+            };
+
+            attr.fg = swap(attr.fg);
+            attr.bg = swap(attr.bg);
+        }
+
+        if attr.flags & INVERSE != 0 {
+            (attr.fg, attr.bg) = (attr.bg, attr.fg);
+        }
+
+        attr
+    }
+
+    // the cursor goes solid once nothing happened for blink_timeout seconds, the loop can then sleep until the next event
+
+    fn cursor_blinks(&self) -> bool {
+        let expired = self.config.blink_timeout > 0 && self.last_activity.elapsed() >= Duration::from_secs(self.config.blink_timeout);
+
+        self.config.blink && self.mode.att610 && !expired
+    }
+
+    fn blink(&mut self) {
+        if self.config.blink && self.last_blink.elapsed() >= BLINK_INTERVAL {
+            self.blink_visible = !self.blink_visible;
+            self.last_blink = Instant::now();
+            self.blinking = false;
+
+            for (y, line) in self.buf.iter().enumerate() {
+                for (x, character) in line.iter().enumerate() {
+                    if character.attr.flags & BLINK != 0 {
+                        self.dirty.set(y + self.scroll, x);
+                        self.blinking = true;
+                    }
+                }
+            }
+
+            if self.blinking || self.mode.att610 {
+                self.dirty.set(self.cursor.position.y as usize + self.scroll, self.cursor.position.x as usize);
+
+                self.refresh = true;
+            }
+        }
+    }
+
+    fn timers(&mut self) {
+        self.blink();
+
+        if self.scroll_indicator.is_some_and(|shown| shown.elapsed() >= SCROLL_INDICATOR_TIMEOUT) {
+            self.scroll_indicator = None;
+
+            self.full_dirt();
+            self.refresh = true;
+        }
+
+        if self.synchronized.is_some_and(|since| since.elapsed() >= SYNC_TIMEOUT) {
+            println!("[+] synchronized update timed out");
+
+            self.synchronized = None;
+        }
+
+        self.watchdog();
+    }
+
+    // after idle_timeout minutes a notice counts down IDLE_GRACE, any input or output in the meantime keeps the window open
+
+    fn watchdog(&mut self) {
+        if self.config.idle_timeout == 0 {
+            return;
+        }
+
+        if self.last_activity > self.idle_since || self.pty.foreground_job() {
+            self.idle_since = Instant::now();
+
+            if self.idle_warning.take().is_some() {
+                self.full_dirt();
+                self.refresh = true;
+            }
+
+            return;
+        }
+
+        match self.idle_warning {
+            Some(shown) if shown.elapsed() >= IDLE_GRACE => {
+                println!("[+] closing after {} idle minutes", self.config.idle_timeout);
+
+                self.should_close = true;
+            },
+            Some(_) => self.refresh = true,
+            None if self.idle_since.elapsed() >= Duration::from_secs(self.config.idle_timeout * 60) => {
+                self.idle_warning = Some(Instant::now());
+                self.refresh = true;
+            },
+            None => {},
+        }
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        let blink = (self.config.blink && (self.blinking || (self.cursor_blinks() && self.mode.dectecm)))
+            .then(|| BLINK_INTERVAL.saturating_sub(self.last_blink.elapsed()));
+
+        let indicator = self.scroll_indicator.map(|shown| SCROLL_INDICATOR_TIMEOUT.saturating_sub(shown.elapsed()));
+
+        let synchronized = self.synchronized.map(|since| SYNC_TIMEOUT.saturating_sub(since.elapsed()));
+
+        // the countdown is redrawn every second
+
+        let idle = (self.config.idle_timeout > 0).then(|| match self.idle_warning {
+            Some(shown) => Duration::from_secs(1).min(IDLE_GRACE.saturating_sub(shown.elapsed())),
+            None => Duration::from_secs(self.config.idle_timeout * 60).saturating_sub(self.idle_since.elapsed()),
+        });
+
+        // a change waiting for its frame wakes the loop once the frame is due, even if nothing else happens
+
+        let frame = (self.refresh && self.synchronized.is_none()).then(|| self.frame_due());
+
+        blink.into_iter().chain(indicator).chain(synchronized).chain(idle).chain(frame).min()
+    }
+
+    // output arriving within frame_interval of the last frame is gathered into the next one
+
+    fn frame_due(&self) -> Duration {
+        Duration::from_millis(self.config.frame_interval).saturating_sub(self.last_frame.elapsed())
+    }
+
+    // the accessible text follows what is shown, only the rows draw repainted are looked at again
+
+    fn update_accessibility(&mut self, repainted: Vec<(usize, Vec<char>)>) {
+        if let Some(a11y) = self.a11y.as_mut() {
+            let cursor = (self.cursor.position.x as usize, self.cursor.position.y as usize + self.scroll);
+
+            if let Err(err) = a11y.update(self.buf.len(), repainted, cursor, self.focused) {
+                println!("[+] failed to update accessibility: {}", err);
+            }
+        }
+    }
+
+    fn draw_scroll_indicator(&mut self) {
+        let text = format!("{}/{}", self.scroll, self.history.len());
+
+        let width = (text.chars().count() as i32 + 2) * self.cell.width;
+        let x = (self.window.width as i32 - width).max(0);
+
+        self.display.draw_rec(x, 0, width as u32, self.cell.height as u32, self.config.bg.raw);
+        self.display.xft_draw_string(&text, x + self.cell.width, self.cell.baseline, self.cell.height as u32, width as u32, self.xft.font, &self.config.fg.xft);
+        self.display.outline_rec(x, 0, width as u32 - 1, self.cell.height as u32 - 1, self.config.fg.raw);
+    }
+
+    fn draw_inspect(&mut self) {
+        let y = self.view_to_abs(self.pointer.y);
+
+        let mut lines = match self.line(y).and_then(|line| line.get(self.pointer.x as usize)) {
+            Some(character) => character.describe(),
+            None => return,
+        };
+
+        lines.push(format!("row: {} column: {}", self.pointer.y, self.pointer.x));
+
+        // open the box away from the pointer so it never covers the inspected cell
+
+        let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) as i32 + 2;
+        let width = columns * self.cell.width;
+        let height = lines.len() as i32 * self.cell.height;
+
+        let x = if (self.pointer.x + 1) * self.cell.width + width > self.window.width as i32 {
+            (self.pointer.x * self.cell.width - width).max(0)
+        } else {
+            (self.pointer.x + 1) * self.cell.width
+        };
+
+        let y = if (self.pointer.y + 1) * self.cell.height + height > self.window.height as i32 {
+            (self.pointer.y * self.cell.height - height).max(0)
+        } else {
+            (self.pointer.y + 1) * self.cell.height
+        };
+
+        self.display.draw_rec(x, y, width as u32, height as u32, self.config.bg.raw);
+
+        for (index, line) in lines.iter().enumerate() {
+            let line_y = y + index as i32 * self.cell.height;
+
+            self.display.xft_draw_string(line, x + self.cell.width, line_y + self.cell.baseline, self.cell.height as u32, width as u32, self.xft.font, &self.config.fg.xft);
+        }
+
+        self.display.outline_rec(x, y, width as u32 - 1, height as u32 - 1, self.config.fg.raw);
+    }
+
+    fn draw_palette(&mut self) {
+        let entries = self.palette.entries(&self.config.keybinds);
+
+        let columns = (self.cols as i32).min(64);
+        let width = columns * self.cell.width;
+        let x = (self.window.width as i32 - width) / 2;
+
+        self.display.draw_rec(x, 0, width as u32, ((entries.len() as i32 + 1) * self.cell.height) as u32, self.config.bg.raw);
+
+        self.display.xft_draw_string(
+            &format!("> {}", self.palette.query),
+            x,
+            self.cell.baseline,
+            self.cell.height as u32,
+            width as u32,
+            self.xft.font,
+            &self.config.fg.xft,
+        );
+
+        for (index, (command, binding)) in entries.iter().enumerate() {
+            let y = (index as i32 + 1) * self.cell.height;
+
+            let (fg, bg) = if index == self.palette.selected {
+                (self.config.bg, self.config.fg)
+            } else {
+                (self.config.fg, self.config.bg)
+            };
+
+            self.display.draw_rec(x, y, width as u32, self.cell.height as u32, bg.raw);
+            self.display.xft_draw_string(command.description(), x, y + self.cell.baseline, self.cell.height as u32, width as u32, self.xft.font, &fg.xft);
+
+            let binding_x = x + width - (binding.chars().count() as i32 + 1) * self.cell.width;
+
+            self.display.xft_draw_string(binding, binding_x, y + self.cell.baseline, self.cell.height as u32, (x + width - binding_x) as u32, self.xft.font, &fg.xft);
+        }
+
+        self.display.outline_rec(x, 0, width as u32 - 1, ((entries.len() as i32 + 1) * self.cell.height) as u32 - 1, self.config.fg.raw);
+    }
+
+    fn draw_command_history(&mut self) {
+        let entries = self.command_history.entries().into_iter().map(|command| command.to_string()).collect::<Vec<String>>();
+
+        let columns = (self.cols as i32).min(80);
+        let width = columns * self.cell.width;
+        let x = (self.window.width as i32 - width) / 2;
+        let height = (entries.len() as i32 + 1) * self.cell.height;
+
+        self.display.draw_rec(x, 0, width as u32, height as u32, self.config.bg.raw);
+
+        self.display.xft_draw_string(
+            &format!("history> {}", self.command_history.query),
+            x,
+            self.cell.baseline,
+            self.cell.height as u32,
+            width as u32,
+            self.xft.font,
+            &self.config.fg.xft,
+        );
+
+        for (index, command) in entries.iter().enumerate() {
+            let y = (index as i32 + 1) * self.cell.height;
+
+            let (fg, bg) = if index == self.command_history.selected {
+                (self.config.bg, self.config.fg)
+            } else {
+                (self.config.fg, self.config.bg)
+            };
+
+            self.display.draw_rec(x, y, width as u32, self.cell.height as u32, bg.raw);
+            self.display.xft_draw_string(command, x, y + self.cell.baseline, self.cell.height as u32, width as u32, self.xft.font, &fg.xft);
+        }
+
+        self.display.outline_rec(x, 0, width as u32 - 1, height as u32 - 1, self.config.fg.raw);
+    }
+
+    fn draw_search(&mut self) {
+        let columns = (self.cols as i32).min(80);
+        let width = columns * self.cell.width;
+        let x = (self.window.width as i32 - width) / 2;
+
+        let status = match (self.search.invalid, self.search.matches.len()) {
+            (true, _) => String::from("invalid"),
+            (false, 0) => String::from("0/0"),
+            (false, count) => format!("{}/{}", self.search.current + 1, count),
+        };
+
+        let status = if self.search.regex { format!("regex {}", status) } else { status };
+        let status_x = x + width - (status.chars().count() as i32 + 1) * self.cell.width;
+
+        self.display.draw_rec(x, 0, width as u32, self.cell.height as u32, self.config.bg.raw);
+
+        self.display.xft_draw_string(
+            &format!("search> {}", self.search.query),
+            x,
+            self.cell.baseline,
+            self.cell.height as u32,
+            (status_x - x) as u32,
+            self.xft.font,
+            &self.config.fg.xft,
+        );
+
+        self.display.xft_draw_string(&status, status_x, self.cell.baseline, self.cell.height as u32, (x + width - status_x) as u32, self.xft.font, &self.config.fg.xft);
+
+        self.display.outline_rec(x, 0, width as u32 - 1, self.cell.height as u32 - 1, self.config.fg.raw);
+    }
+
+    fn draw_idle_warning(&mut self) {
+        let left = IDLE_GRACE.saturating_sub(self.idle_warning.map_or(Duration::ZERO, |shown| shown.elapsed()));
+        let text = format!("idle, closing in {}s. press a key to stay", left.as_secs() + 1);
+
+        let width = (text.chars().count() as i32 + 2) * self.cell.width;
+        let x = (self.window.width as i32 - width).max(0) / 2;
+        let y = (self.rows as i32 / 2) * self.cell.height;
+
+        self.display.draw_rec(x, y, width as u32, self.cell.height as u32, self.config.bg.raw);
+        self.display.xft_draw_string(&text, x + self.cell.width, y + self.cell.baseline, self.cell.height as u32, width as u32, self.xft.font, &self.config.fg.xft);
+        self.display.outline_rec(x, y, width as u32 - 1, self.cell.height as u32 - 1, self.config.fg.raw);
+    }
+
+    fn close_menu(&mut self) {
+        self.menu.open = false;
+
+        self.full_dirt();
+
+        self.refresh = true;
+    }
+
+    fn draw_menu(&mut self) {
+        for (index, (_, label)) in menu::ENTRIES.iter().enumerate() {
+            let y = self.menu.y + index as i32 * self.menu.entry_height;
+
+            let (fg, bg) = if self.menu.selected == Some(index) {
+                (self.config.bg, self.config.fg)
+            } else {
+                (self.config.fg, self.config.bg)
+            };
+
+            self.display.draw_rec(self.menu.x, y, self.menu.width as u32, self.menu.entry_height as u32, bg.raw);
+            self.display.xft_draw_string(label, self.menu.x + self.cell.width, y + self.cell.baseline, self.cell.height as u32, self.menu.width as u32, self.xft.font, &fg.xft);
+        }
+
+        self.display.outline_rec(self.menu.x, self.menu.y, self.menu.width as u32 - 1, self.menu.height() as u32 - 1, self.config.fg.raw);
+    }
+}
+
+impl Terminal {
+    pub fn new(command: &[String]) -> Result<Terminal, Box<dyn std::error::Error>> {
+        Terminal::with_pty(|env| Ok(Box::new(Pty::new(command, env)?)))
+    }
+
+    pub fn exit_status(&mut self) -> Option<i32> {
+        self.screen.pty.exit_status()
+    }
+
+    // the child is spawned once the window exists, spawn gets the variables that describe it
+
+    pub fn with_pty(spawn: impl FnOnce(&[(&str, String)]) -> Result<Box<dyn PtyLike>, Box<dyn std::error::Error>>) -> Result<Terminal, Box<dyn std::error::Error>> {
+        let startup = config::startup()?;
+
+        let mut display = xlib::Display::open(startup.follow_pointer)?;
+
+        let window_attr = display.get_window_attributes();
+
+        let config = Config::load(&display)?;
+
+        let ipc = [(config.ipc, false), (startup.single_instance, true)].into_iter()
+            .filter(|(enabled, _)| *enabled)
+            .filter_map(|(_, instance)| Ipc::bind(instance).map_err(|err| println!("[+] ipc unavailable: {}", err)).ok())
+            .collect::<Vec<Ipc>>();
+
+        let pty = spawn(&environment(display.window_id(), &config, &ipc))?;
+
+        let a11y = Accessibility::connect().unwrap_or_else(|err| {
+            println!("[+] accessibility unavailable: {}", err);
+
+            None
+        });
+
+        let window = Window {
+            width: window_attr.width as u32,
+            height: window_attr.height as u32,
+        };
+
+        Terminal::assemble(Box::new(display), config, window, pty, ipc, a11y)
+    }
+
+    // everything past the frontend is the same for the x window and a headless screen
+
+    fn assemble(
+        mut display: Box<dyn Frontend>,
+        config: Config,
+        window: Window,
+        pty: Box<dyn PtyLike>,
+        ipc: Vec<Ipc>,
+        a11y: Option<Accessibility>,
+    ) -> Result<Terminal, Box<dyn std::error::Error>> {
+        let xft = Xft::load(display.as_mut(), &config.font)?;
+
+        let attr = Attribute::new(&config);
+
+        let cell = Cell::from_font(display.as_ref(), xft.font);
+
+        let font_size = config::font_size(&config.font);
+
+        let (cols, rows) = (window.width as usize / cell.width as usize, window.height as usize / cell.height as usize);
+
+        let alt = AltScreen::new(&config, cols, rows);
+
+        let tabs = (0..cols + 1).map(|x| x % config.tab_interval == 0).collect::<Vec<bool>>();
+
+        let audio = Audio::new(&config)?;
+
+        let command_history = CommandHistory::load(config.command_history);
+
+        Ok(Terminal {
+            parser: Parser::new(),
+            reader: None,
+            screen: Screen {
+                display,
+                selection: Selection {
+                    start: Position { x: 0, y: 0 },
+                    end: Position { x: 0, y: 0 },
+                    selecting: false,
+                },
+                cursor: Cursor {
+                    position: Position {
+                        x: 0,
+                        y: 0,
+                    },
+                    save: Position {
+                        x: 0,
+                        y: 0,
+                    },
+                    save_charsets: ([Charset::Ascii; 2], 0),
+                },
+                window,
+                cols,
+                rows,
+                buttons: Buttons::None,
+                attr,
+                config,
+                audio,
+                scrolling_region: ScrollingRegion {
+                    top: 0,
+                    bottom: rows - 1,
+                    left: 0,
+                    right: cols - 1,
+                },
+                buf: vec![Line::new(Character { attr, byte: ' ' }, cols + 1); rows + 1],
+                history: VecDeque::new(),
+                dirty: Dirty::new(rows + 1, cols + 1),
+                hashes: Vec::new(),
+                cell,
+                mode: Mode::new(),
+                xft,
+                cursor_style: CursorStyle::Block,
+                pty,
+                palette: Palette::new(),
+                command_history,
+                command_start: None,
+                ipc,
+                search: Search::new(),
+                menu: Menu::new(),
+                a11y,
+                pointer: Position { x: 0, y: 0 },
+                alt,
+                tabs,
+                font_size,
+                scale: 1,
+                scroll: 0,
+                last_notification: None,
+                notifier: None,
+                user_vars: HashMap::new(),
+                images: Placements::default(),
+                graphics: GraphicsAttributes::default(),
+                image_rows: Vec::new(),
+                app_title: None,
+                titles: Vec::new(),
+                semantic_prompts: false,
+                links: Links::default(),
+                faint: std::cell::RefCell::new(HashMap::new()),
+                runs: Runs::default(),
+                blit: None,
+                last_blink: Instant::now(),
+                last_activity: Instant::now(),
+                last_frame: Instant::now(),
+                idle_since: Instant::now(),
+                idle_warning: None,
+                blink_visible: true,
+                blinking: false,
+                xkb_event: None,
+                scroll_indicator: None,
+                synchronized: None,
+                other_keys: 0,
+                keyboard: Vec::new(),
+                keys_down: Vec::new(),
+                binding_repeat: None,
+                urgent: false,
+                wrap_pending: None,
+                last_printed: None,
+                auto_print: false,
+                printer: None,
+                charsets: [Charset::Ascii; 2],
+                shift: 0,
+                layout: None,
+                inspect: false,
+                refresh: true,
+                focused: true,
+                scroll_set: false,
+                should_close: false,
+            },
+        })
+    }
+
+    // run hands reading over to the reader thread, until then the pty is read here directly like the tests do
+
+    fn read_tty(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(reader) = self.reader.as_mut() else {
+            return self.read_pty();
+        };
+
+        let received = reader.receive()?;
+
+        for chunk in &received.chunks {
+            self.handle_bytes(chunk)?;
+        }
+
+        if received.closed {
+            self.screen.should_close = true;
+        }
+
+        Ok(!received.chunks.is_empty())
+    }
+
+    fn read_pty(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut more_to_read = true;
+        let mut read = false;
+
+        while more_to_read {
+            let mut buffer: Vec<u8> = vec![0; 2048];
+
+            match self.screen.pty.read(&mut buffer) {
+                Ok(0) => {},
+                Ok(bytes) => {
+                    self.handle_bytes(&buffer[..bytes])?;
+
+                    read = true;
+                },
+                Err(err) => {
+                    match err.kind() {
+                        ErrorKind::WouldBlock => more_to_read = false,
+                        ErrorKind::Interrupted => {},
+                        // the master reads EIO once the child and everything it left running closed the slave side
+                        _ if err.raw_os_error() == Some(libc::EIO) => {
+                            self.screen.should_close = true;
+
+                            more_to_read = false;
+                        },
+                        _ => return Err(Box::new(err)),
+                    }
+                },
+            }
+        }
+
+        Ok(read)
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>) -> Result<(), Box<dyn std::error::Error>> {
+        // xlib may already hold events it read off the socket, those would never wake up poll
+
+        if self.screen.display.pending() == 0 {
+            let mut fds = vec![
+                libc::pollfd { fd: self.reader.as_ref().map_or(self.screen.pty.as_raw_fd(), Reader::as_raw_fd), events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: self.screen.display.connection_number(), events: libc::POLLIN, revents: 0 },
+            ];
+
+            fds.extend(self.screen.ipc.iter().map(|ipc| libc::pollfd { fd: ipc.as_raw_fd(), events: libc::POLLIN, revents: 0 }));
+
+            // rounded up, a deadline less than a millisecond away would otherwise poll without waiting until it passed
+
+            let timeout = timeout.map_or(-1, |timeout| timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32);
+
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } == -1 {
+                let err = io::Error::last_os_error();
+
+                if err.kind() != ErrorKind::Interrupted {
+                    return Err(Box::new(err));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_bytes(&mut self, bytes: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        Terminal::feed(&mut self.parser, &mut self.screen, bytes, 0)?;
+
+        self.screen.refresh = true;
+
+        Ok(())
+    }
+
+    // a sequence tmux passed through is handled as if it came in directly, with a parser of its own so a truncated one
+    // can't leave the main parser inside it. tmux inside tmux nests them, deeper than MAX_PASSTHROUGH they are dropped
+
+    fn feed(parser: &mut Parser, screen: &mut Screen, bytes: &[u8], depth: usize) -> Result<(), Box<dyn std::error::Error>> {
+        for byte in bytes {
+            if let Ok(Some(action)) = parser.advance(*byte) {
+                match action {
+                    Action::Print(c) => {
+                        screen.print(c);
+                    },
+                    Action::Execute(byte) => {
+                        screen.execute(byte);
+                    },
+                    Action::CsiDispatch(prefix, params, subparams, intermediates, c) => {
+                        screen.csi_dispatch(prefix, params, subparams, intermediates, c)?;
+                    },
+                    Action::EscDispatch(intermediates, c) => {
+                        screen.esc_dispatch(intermediates, c)?;
+                    },
+                    Action::OscDispatch(data) => {
+                        screen.osc_dispatch(data)?;
+                    },
+                    Action::DcsDispatch(data) if data.starts_with(b"tmux;") => {
+                        if depth < MAX_PASSTHROUGH {
+                            let inner = data[5..].to_vec();
+
+                            Terminal::feed(&mut Parser::new(), screen, &inner, depth + 1)?;
+                        }
+                    },
+                    Action::DcsDispatch(data) => {
+                        screen.dcs_dispatch(data)?;
+                    },
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.screen.update_title();
+        self.screen.display.define_cursor(xlib::DEFAULT_CURSOR);
+        self.screen.display.select_input();
+        self.screen.watch_layout();
+
+        if self.screen.config.render_scale > 1 {
+            self.screen.set_scale(self.screen.config.render_scale)?;
+        }
+
+        self.screen.display.map_window();
+        self.screen.display.flush();
+
+        // TODO: clean up mode and button handling
+
+        unsafe {
+            let flags = libc::fcntl(self.screen.pty.as_raw_fd(), libc::F_GETFL, 0) | libc::O_NONBLOCK;
+
+            libc::fcntl(self.screen.pty.as_raw_fd(), libc::F_SETFL, flags);
+        }
+
+        self.reader = Some(Reader::spawn(self.screen.pty.as_raw_fd())?);
+
+        while !self.screen.should_close {
+            let mut idle = !self.read_tty()?;
+
+            if let Some(events) = self.screen.display.poll_event() {
+                for event in events {
+                    self.screen.handle_event(event)?;
+                }
+
+                idle = false;
+            }
+
+            if !idle {
+                self.screen.last_activity = Instant::now();
+            }
+
+            self.screen.handle_ipc();
+
+            self.screen.timers();
+
+            if self.screen.refresh && self.screen.synchronized.is_none() && self.screen.frame_due().is_zero() {
+                self.screen.draw()?;
+                self.screen.last_frame = Instant::now();
+            }
+
+            self.screen.display.flush();
+
+            self.wait(self.screen.timeout())?;
+        }
+
+        Ok(())
+    }
+}
+
+// the variables the child is spawned with
+
+fn environment(window: u64, config: &Config, ipc: &[Ipc]) -> Vec<(&'static str, String)> {
+    let mut env = vec![("WINDOWID", window.to_string()), ("COLORFGBG", config.colorfgbg())];
+
+    // the shell only learns about the socket when there is one to talk to
+
+    if ipc.iter().any(|ipc| !ipc.instance) {
+        env.push(("TERMAL_SOCKET", ipc::path().display().to_string()));
+    }
+
+    env
+}
+
+fn draw_underline(display: &mut dyn Frontend, cell: &Cell, x: i32, y: i32, style: Underline, color: xlib::Color) {
+    let thickness = (cell.height / 16).clamp(1, 2);
+    let top = y + (cell.baseline + 1).min(cell.height - thickness);
+
+    match style {
+        Underline::None => {},
+        Underline::Single => display.draw_rec(x, top, cell.width as u32, thickness as u32, color),
+        Underline::Double => {
+            let bottom = (top + thickness * 2).min(y + cell.height - thickness);
+
+            display.draw_rec(x, top, cell.width as u32, thickness as u32, color);
+            display.draw_rec(x, bottom, cell.width as u32, thickness as u32, color);
+        },
+        Underline::Curly => {
+            // one wave per cell so neighbouring cells join up
+
+            let amplitude = (cell.height - cell.baseline - 1).clamp(1, 2);
+
+            let points = [0, 1, 2, 3, 4]
+                .map(|step| (x + cell.width * step / 4, top + amplitude * [0, -1, 0, 1, 0][step as usize]));
+
+            display.draw_lines(&points, color);
+        },
+        Underline::Dotted => {
+            for dot in (0..cell.width).step_by(thickness as usize * 2) {
+                display.draw_rec(x + dot, top, thickness as u32, thickness as u32, color);
+            }
+        },
+        Underline::Dashed => {
+            let dash = (cell.width / 3).max(1);
+
+            display.draw_rec(x, top, dash as u32, thickness as u32, color);
+            display.draw_rec(x + dash * 2, top, (cell.width - dash * 2) as u32, thickness as u32, color);
+        },
+    }
+}
+
+// https://en.wikipedia.org/wiki/ANSI_escape_code#8-bit, a 6x6x6 color cube followed by 24 shades of gray
+
+fn indexed_color(index: u16) -> xlib::Color {
+    let level = |value: u16| if value == 0 { 0 } else { 55 + value as u64 * 40 };
+
+    match index {
+        16..=231 => {
+            let index = index - 16;
+
+            xlib::Color::new(level(index / 36), level((index / 6) % 6), level(index % 6))
+        },
+        _ => {
+            let gray = 8 + (index.clamp(232, 255) - 232) as u64 * 10;
+
+            xlib::Color::new(gray, gray, gray)
+        },
+    }
+}
+
+fn cursor_key(keysym: u32) -> Option<char> {
+    match keysym {
+        x11::keysym::XK_Up => Some('A'),
+        x11::keysym::XK_Down => Some('B'),
+        x11::keysym::XK_Right => Some('C'),
+        x11::keysym::XK_Left => Some('D'),
+        x11::keysym::XK_End => Some('F'),
+        x11::keysym::XK_Home => Some('H'),
+        _ => None,
+    }
+}
+
+// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-PC-Style-Function-Keys, f1 to f4 are sent like the cursor keys
+
+fn function_key(keysym: u32) -> Option<(u32, char)> {
+    match keysym {
+        x11::keysym::XK_Insert => Some((2, '~')),
+        x11::keysym::XK_Delete => Some((3, '~')),
+        x11::keysym::XK_Prior => Some((5, '~')),
+        x11::keysym::XK_Next => Some((6, '~')),
+        x11::keysym::XK_F1 => Some((1, 'P')),
+        x11::keysym::XK_F2 => Some((1, 'Q')),
+        x11::keysym::XK_F3 => Some((1, 'R')),
+        x11::keysym::XK_F4 => Some((1, 'S')),
+        x11::keysym::XK_F5 => Some((15, '~')),
+        x11::keysym::XK_F6 => Some((17, '~')),
+        x11::keysym::XK_F7 => Some((18, '~')),
+        x11::keysym::XK_F8 => Some((19, '~')),
+        x11::keysym::XK_F9 => Some((20, '~')),
+        x11::keysym::XK_F10 => Some((21, '~')),
+        x11::keysym::XK_F11 => Some((23, '~')),
+        x11::keysym::XK_F12 => Some((24, '~')),
+        _ => None,
+    }
+}
+
+// 1 + shift + alt * 2 + ctrl * 4 + super * 8, 1 means no modifiers
+
+fn modifier_param(state: u32) -> u32 {
+    1 + (state & x11::xlib::ShiftMask != 0) as u32
+        + (state & x11::xlib::Mod1Mask != 0) as u32 * 2
+        + (state & x11::xlib::ControlMask != 0) as u32 * 4
+        + (state & x11::xlib::Mod4Mask != 0) as u32 * 8
+}
+
+// linked runs of cells are replaced by their uri or followed by it, depending on the copy
+
+fn copy_links(cells: impl Iterator<Item = (char, u32)>, links: &Links, copy: LinkCopy) -> String {
+    let mut content = String::new();
+    let mut cells = cells.peekable();
+
+    while let Some((c, link)) = cells.next() {
+        match links.get(link).filter(|_| copy != LinkCopy::Text) {
+            Some(uri) => {
+                let mut text = String::from(c);
+
+                while let Some((c, _)) = cells.next_if(|(_, next)| *next == link) {
+                    text.push(c);
+                }
+
+                match copy {
+                    LinkCopy::Uri => content.push_str(uri),
+                    _ => content.push_str(&format!("{} ({})", text.trim_end(), uri)),
+                }
+            },
+            None => content.push(c),
+        }
+    }
+
+    content
+}
+
+fn keypad_key(keysym: u32) -> Option<char> {
+    match keysym {
+        x11::keysym::XK_KP_0..=x11::keysym::XK_KP_9 => char::from_u32('p' as u32 + keysym - x11::keysym::XK_KP_0),
+        x11::keysym::XK_KP_Enter => Some('M'),
+        x11::keysym::XK_KP_Multiply => Some('j'),
+        x11::keysym::XK_KP_Add => Some('k'),
+        x11::keysym::XK_KP_Separator => Some('l'),
+        x11::keysym::XK_KP_Subtract => Some('m'),
+        x11::keysym::XK_KP_Decimal => Some('n'),
+        x11::keysym::XK_KP_Divide => Some('o'),
+        x11::keysym::XK_KP_Equal => Some('X'),
+        _ => None,
+    }
+}
+
+fn key_sequence(keysym: u32, state: u32, decckm: bool, deckpam: bool) -> Option<String> {
+    let modifiers = modifier_param(state);
+
+    if let Some(key) = cursor_key(keysym) {
+        if modifiers > 1 {
+            // https://git.suckless.org/st/file/config.def.h.html#l327
+            Some(format!("\x1b[1;{}{}", modifiers, key))
+        } else if decckm {
+            Some(format!("\x1bO{}", key))
+        } else {
+            Some(format!("\x1b[{}", key))
+        }
+    } else if let Some(key) = keypad_key(keysym).filter(|_| deckpam) {
+        // https://vt100.net/docs/vt510-rm/DECKPAM.html
+
+        Some(format!("\x1bO{}", key))
+    } else if let Some((number, last)) = function_key(keysym) {
+        match (last, modifiers) {
+            ('~', 1) => Some(format!("\x1b[{}~", number)),
+            ('~', _) => Some(format!("\x1b[{};{}~", number, modifiers)),
+            (_, 1) => Some(format!("\x1bO{}", last)),
+            (_, _) => Some(format!("\x1b[1;{}{}", modifiers, last)),
+        }
+    } else {
+        match keysym {
+            x11::keysym::XK_BackSpace => Some(String::from("\x7f")),
+            x11::keysym::XK_Escape => Some(String::from("\x1b")),
+            x11::keysym::XK_Tab if state & x11::xlib::ShiftMask != 0 => Some(String::from("\x1b[Z")),
+            x11::keysym::XK_ISO_Left_Tab => Some(String::from("\x1b[Z")),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MouseEncoding {
+    X10,
+    Utf8,
+    Sgr,
+    Urxvt,
+}
+
+// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Mouse-Tracking, only sgr tells which button was released.
+// the x10 and utf-8 encodings run out of room at column 223 and 2015, positions past that are not reported
+
+fn mouse_report(encoding: MouseEncoding, code: usize, x: i32, y: i32, release: bool) -> Option<Vec<u8>> {
+    let code = if release && encoding != MouseEncoding::Sgr { 3 } else { code as i32 };
+
+    match encoding {
+        MouseEncoding::Sgr => Some(format!("\x1b[<{};{};{}{}", code, x, y, if release { 'm' } else { 'M' }).into_bytes()),
+        MouseEncoding::Urxvt => Some(format!("\x1b[{};{};{}M", code + 32, x, y).into_bytes()),
+        MouseEncoding::Utf8 => {
+            let values = [code, x, y].map(|value| char::from_u32((value + 32) as u32).filter(|_| value + 32 < 2048));
+
+            values.iter().all(|value| value.is_some()).then(|| format!("\x1b[M{}", values.iter().flatten().collect::<String>()).into_bytes())
+        },
+        MouseEncoding::X10 => {
+            let values = [code, x, y].map(|value| value + 32);
+
+            values.iter().all(|value| *value < 256).then(|| [b"\x1b[M".as_slice(), &values.map(|value| value as u8)].concat())
+        },
+    }
+}
+
+// https://invisible-island.net/xterm/modified-keys.html, the key is reported unshifted with shift counted in the modifiers.
+// level 1 leaves keys with a well known meaning alone, like ctrl + letter, level 2 reports every modified key but plain shifted text
+
+fn other_key_sequence(keysym: u32, state: u32, level: u16, format: config::KeyFormat) -> Option<String> {
+    let code = match keysym {
+        x11::keysym::XK_Return => 13,
+        x11::keysym::XK_Tab => 9,
+        x11::keysym::XK_BackSpace => 127,
+        x11::keysym::XK_Escape => 27,
+        keysym => xlib::keysym_char(keysym)? as u32,
+    };
+
+    let shift = state & x11::xlib::ShiftMask != 0;
+    let alt = state & x11::xlib::Mod1Mask != 0;
+    let ctrl = state & x11::xlib::ControlMask != 0;
+
+    let printable = char::from_u32(code).filter(|c| !c.is_control());
+    let well_known = printable.is_some_and(|c| c.is_ascii_lowercase() || "@[\\]^_ ".contains(c)) && !shift;
+
+    let report = match level {
+        1 => ctrl && printable.is_some() && !well_known,
+        2 => ctrl || alt || (shift && printable.is_none()),
+        _ => false,
+    };
+
+    let modifiers = 1 + shift as u32 + alt as u32 * 2 + ctrl as u32 * 4;
+
+    report.then(|| match format {
+        config::KeyFormat::Xterm => format!("\x1b[27;{};{}~", modifiers, code),
+        config::KeyFormat::CsiU => format!("\x1b[{};{}u", code, modifiers),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pty::FakePty;
+
+    use headless::ScreenBuilder;
+
+    fn terminal(pty: &FakePty) -> Terminal {
+        ScreenBuilder::new(80, 24).pty(Box::new(pty.clone())).build().unwrap()
+    }
+
+    #[test]
+    fn line_hashes() {
+        // only the raw colors matter here, the xft half is never looked at
+
+        let color = config::UniColor { raw: xlib::Color::new(0, 0, 0), xft: unsafe { std::mem::zeroed() } };
+        let blank = Character { attr: Attribute { fg: color, bg: color, flags: 0, underline: Underline::None, underline_color: None, link: 0 }, byte: ' ' };
+
+        let mut line = Line::new(blank, 8);
+        let hash = line.content_hash();
+
+        assert_eq!(Line::new(blank, 8).content_hash(), hash);
+
+        line[3].byte = 'a';
+
+        assert_ne!(line.content_hash(), hash);
+
+        line[3].byte = ' ';
+
+        assert_eq!(line.content_hash(), hash);
+    }
+
+    #[test]
+    fn key_sequences() {
+        assert_eq!(key_sequence(x11::keysym::XK_Up, 0, false, false).as_deref(), Some("\x1b[A"));
+        assert_eq!(key_sequence(x11::keysym::XK_Left, 0, true, false).as_deref(), Some("\x1bOD"));
+        assert_eq!(key_sequence(x11::keysym::XK_Right, x11::xlib::ControlMask, true, false).as_deref(), Some("\x1b[1;5C"));
+        assert_eq!(key_sequence(x11::keysym::XK_BackSpace, 0, false, false).as_deref(), Some("\x7f"));
+        assert_eq!(key_sequence(x11::keysym::XK_a, 0, false, false), None);
+        assert_eq!(key_sequence(x11::keysym::XK_KP_7, 0, false, true).as_deref(), Some("\x1bOw"));
+        assert_eq!(key_sequence(x11::keysym::XK_KP_Enter, 0, false, true).as_deref(), Some("\x1bOM"));
+        assert_eq!(key_sequence(x11::keysym::XK_KP_7, 0, false, false), None);
+        assert_eq!(key_sequence(x11::keysym::XK_Home, 0, false, false).as_deref(), Some("\x1b[H"));
+        assert_eq!(key_sequence(x11::keysym::XK_End, 0, true, false).as_deref(), Some("\x1bOF"));
+        assert_eq!(key_sequence(x11::keysym::XK_Delete, 0, false, false).as_deref(), Some("\x1b[3~"));
+        assert_eq!(key_sequence(x11::keysym::XK_Prior, x11::xlib::ShiftMask, false, false).as_deref(), Some("\x1b[5;2~"));
+        assert_eq!(key_sequence(x11::keysym::XK_F1, 0, false, false).as_deref(), Some("\x1bOP"));
+        assert_eq!(key_sequence(x11::keysym::XK_F3, x11::xlib::Mod1Mask, false, false).as_deref(), Some("\x1b[1;3R"));
+        assert_eq!(key_sequence(x11::keysym::XK_F12, x11::xlib::ControlMask, false, false).as_deref(), Some("\x1b[24;5~"));
+        assert_eq!(key_sequence(x11::keysym::XK_Tab, x11::xlib::ShiftMask, false, false).as_deref(), Some("\x1b[Z"));
+        assert_eq!(key_sequence(x11::keysym::XK_Tab, 0, false, false), None);
+    }
+
+    #[test]
+    fn keyboard_flags() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b[>1u\x1b[>11u\x1b[?u").unwrap();
+
+        assert_eq!(terminal.screen.keyboard, vec![1, 11]);
+        assert_eq!(pty.take_output(), "\x1b[?11u");
+
+        terminal.handle_bytes(b"\x1b[=2;2u\x1b[<u").unwrap();
+
+        assert_eq!(terminal.screen.keyboard, vec![1]);
+
+        terminal.handle_bytes(b"\x1b[<5u").unwrap();
+
+        assert!(terminal.screen.keyboard.is_empty());
+    }
+
+    #[test]
+    fn mouse_reports() {
+        assert_eq!(mouse_report(MouseEncoding::Sgr, 0, 3, 4, true), Some(b"\x1b[<0;3;4m".to_vec()));
+        assert_eq!(mouse_report(MouseEncoding::Urxvt, 2, 3, 4, false), Some(b"\x1b[34;3;4M".to_vec()));
+        assert_eq!(mouse_report(MouseEncoding::Urxvt, 2, 3, 4, true), Some(b"\x1b[35;3;4M".to_vec()));
+        assert_eq!(mouse_report(MouseEncoding::X10, 0, 1, 1, false), Some(b"\x1b[M !!".to_vec()));
+        assert_eq!(mouse_report(MouseEncoding::X10, 0, 300, 1, false), None);
+        assert_eq!(mouse_report(MouseEncoding::Utf8, 0, 300, 1, false), Some("\x1b[M \u{14c}!".as_bytes().to_vec()));
+        assert_eq!(mouse_report(MouseEncoding::Utf8, 0, 3000, 1, false), None);
+    }
+
+    #[test]
+    fn other_keys() {
+        let ctrl = x11::xlib::ControlMask;
+        let shift = x11::xlib::ShiftMask;
+
+        assert_eq!(other_key_sequence(x11::keysym::XK_comma, ctrl, 1, config::KeyFormat::Xterm).as_deref(), Some("\x1b[27;5;44~"));
+        assert_eq!(other_key_sequence(x11::keysym::XK_a, ctrl, 1, config::KeyFormat::Xterm), None);
+        assert_eq!(other_key_sequence(x11::keysym::XK_a, ctrl | shift, 1, config::KeyFormat::Xterm).as_deref(), Some("\x1b[27;6;97~"));
+        assert_eq!(other_key_sequence(x11::keysym::XK_a, ctrl, 2, config::KeyFormat::CsiU).as_deref(), Some("\x1b[97;5u"));
+        assert_eq!(other_key_sequence(x11::keysym::XK_a, shift, 2, config::KeyFormat::CsiU), None);
+        assert_eq!(other_key_sequence(x11::keysym::XK_Return, shift, 2, config::KeyFormat::CsiU).as_deref(), Some("\x1b[13;2u"));
+        assert_eq!(other_key_sequence(x11::keysym::XK_comma, ctrl, 0, config::KeyFormat::Xterm), None);
+        assert_eq!(other_key_sequence(x11::keysym::XK_Up, ctrl, 2, config::KeyFormat::Xterm), None);
+    }
+
+    #[test]
+    fn links() {
+        let mut links = Links::default();
+
+        let link = links.intern(String::from("https://example.com"));
+        let cells = || "see docs now".chars().enumerate().map(|(x, c)| (c, if (4..8).contains(&x) { link } else { 0 }));
+
+        assert_eq!(copy_links(cells(), &links, LinkCopy::Text), "see docs now");
+        assert_eq!(copy_links(cells(), &links, LinkCopy::Uri), "see https://example.com now");
+        assert_eq!(copy_links(cells(), &links, LinkCopy::Both), "see docs (https://example.com) now");
+    }
+
+    #[test]
+    fn wide() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let row = |terminal: &Terminal| terminal.screen.buf[0].iter().take(5).map(|c| c.byte).collect::<String>();
+
+        terminal.handle_bytes("a字b".as_bytes()).unwrap();
+
+        assert_eq!(row(&terminal), "a字 b ");
+        assert_eq!(terminal.screen.cursor.position.x, 4);
+
+        // erasing the spacer takes the whole character with it, the cursor never lands on the spacer
+
+        terminal.handle_bytes(b"\x1b[3G\x1b[X").unwrap();
+
+        assert_eq!(row(&terminal), "a  b ");
+        assert_eq!(terminal.screen.cursor.position.x, 1);
+
+        // deleting up to the left half leaves no spacer behind
+
+        terminal.handle_bytes("\x1b[Ha字bc\x1b[H\x1b[2P".as_bytes()).unwrap();
+
+        assert_eq!(row(&terminal), " bc  ");
+        assert_eq!(terminal.screen.buf[0][0].attr.flags & WIDE_SPACER, 0);
+    }
+
+    #[test]
+    fn mode_reports() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b[?2004h\x1b[?2004$p\x1b[?7l\x1b[?7$p\x1b[?3$p\x1b[?9999$p").unwrap();
+
+        assert_eq!(pty.take_output(), "\x1b[?2004;1$y\x1b[?7;2$y\x1b[?3;4$y\x1b[?9999;0$y");
+
+        terminal.handle_bytes(b"\x1b[?4h\x1b[4$p\x1b[4h\x1b[4$p\x1b[?4$p").unwrap();
+
+        assert_eq!(pty.take_output(), "\x1b[4;2$y\x1b[4;1$y\x1b[?4;4$y");
+    }
+
+    #[test]
+    fn status_strings() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b[1;4:3;31m\x1bP$qm\x1b\\\x1b[4 q\x1bP$q q\x1b\\\x1b[2;10r\x1bP$qr\x1b\\\x1bP$qx\x1b\\").unwrap();
+
+        assert_eq!(pty.take_output(), "\x1bP1$r0;1;4:3;31m\x1b\\\x1bP1$r4 q\x1b\\\x1bP1$r2;10r\x1b\\\x1bP0$r\x1b\\");
+    }
+
+    #[test]
+    fn window_ops() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let (width, height) = (terminal.screen.window.width, terminal.screen.window.height);
+        terminal.handle_bytes(b"\x1b[18t\x1b[14t").unwrap();
+
+        assert_eq!(pty.take_output(), format!("\x1b[8;{};{}t\x1b[4;{};{}t", terminal.screen.rows, terminal.screen.cols, height, width));
+
+        terminal.handle_bytes(b"\x1b]2;vim\x07\x1b[22;0t\x1b]0;less\x07").unwrap();
+
+        assert_eq!(terminal.screen.app_title.as_deref(), Some("less"));
+
+        terminal.handle_bytes(b"\x1b[23;0t").unwrap();
+
+        assert_eq!(terminal.screen.app_title.as_deref(), Some("vim"));
+    }
+
+    #[test]
+    fn synchronized_output() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b[?2026h\x1b[?2026$p").unwrap();
+
+        assert!(terminal.screen.synchronized.is_some());
+        assert_eq!(pty.take_output(), "\x1b[?2026;1$y");
+
+        terminal.handle_bytes(b"\x1b[?2026l\x1b[?2026$p").unwrap();
+
+        assert!(terminal.screen.synchronized.is_none());
+        assert_eq!(pty.take_output(), "\x1b[?2026;2$y");
+    }
+
+    #[test]
+    fn line_sizes() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let columns = terminal.screen.cols;
+        let hash = terminal.screen.buf[0].content_hash();
+
+        terminal.handle_bytes(b"\x1b#6").unwrap();
+
+        assert_eq!(terminal.screen.buf[0].size, LineSize::DoubleWidth);
+        assert_ne!(terminal.screen.buf[0].content_hash(), hash);
+
+        terminal.handle_bytes(&vec![b'x'; columns / 2 + 1]).unwrap();
+
+        assert_eq!(terminal.screen.cursor.position.y, 1);
+
+        terminal.handle_bytes(b"\x1b#3\x1b#5").unwrap();
+
+        assert_eq!(terminal.screen.buf[1].size, LineSize::Single);
+    }
+
+    #[test]
+    fn scrolling_region() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b[2;4r\x1b[4Hbottom\x1b[2Htop\x1bD\x1b[4H\x1bD").unwrap();
+
+        assert_eq!(terminal.screen.cursor.position.y, 3);
+        assert_eq!(terminal.screen.buf[2].cells[0].byte, 'b');
+        assert_eq!(terminal.screen.buf[3].cells[0].byte, ' ');
+
+        terminal.handle_bytes(b"\x1b[2H\x1bM\n").unwrap();
+
+        assert_eq!(terminal.screen.cursor.position.y, 2);
+        assert_eq!(terminal.screen.buf[1].cells[0].byte, ' ');
+        assert_eq!(terminal.screen.buf[3].cells[0].byte, 'b');
+
+        let rows = terminal.screen.rows as i32;
+
+        terminal.handle_bytes(format!("\x1b[1H\x1bM\x1b[r\x1b[{}H\x1bE", rows).as_bytes()).unwrap();
+
+        assert_eq!(terminal.screen.cursor.position, Position { x: 0, y: rows - 1 });
+
+        // a bottom past the screen is cut to the last line, a region of one line is ignored
+
+        terminal.handle_bytes(b"\x1b[1;999r\x1b[5;5r\x1b[H\x1bM").unwrap();
+
+        assert_eq!((terminal.screen.scrolling_region.top, terminal.screen.scrolling_region.bottom), (0, rows as usize - 1));
+    }
+
+    #[test]
+    fn repeat() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes("x\x1b[3b\x1b(0q\x1b[b".as_bytes()).unwrap();
+
+        assert_eq!(terminal.screen.buf[0].iter().take(7).map(|character| character.byte).collect::<String>(), "xxxx── ");
+        assert_eq!(terminal.screen.cursor.position.x, 6);
+    }
+
+    #[test]
+    fn last_output() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x07a\r\nb\r\n\x1b]133;D\x07\x1b]133;A\x07$ ").unwrap();
+
+        assert_eq!(terminal.screen.extent_text(ipc::Extent::LastCommand, false).as_deref(), Some("a\nb\n"));
+    }
+
+    #[test]
+    fn wide_cursor() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes("a😀".as_bytes()).unwrap();
+
+        assert_eq!(terminal.screen.cursor_span(), (3, 1));
+
+        terminal.handle_bytes(b"\x1b[3G").unwrap();
+
+        assert_eq!(terminal.screen.cursor_span(), (1, 2));
+
+        terminal.handle_bytes(b"\x1b[2G").unwrap();
+
+        assert_eq!(terminal.screen.cursor_span(), (1, 2));
+    }
+
+    #[test]
+    fn scroll_blit() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let rows = terminal.screen.rows;
+
+        terminal.handle_bytes(format!("\x1b[{}H1\r\n2", rows - 1).as_bytes()).unwrap();
+        terminal.screen.draw().unwrap();
+        terminal.handle_bytes(b"\r\n3\r\n").unwrap();
+
+        // only the two rows that came in are drawn, the ones that moved up are copied on the back buffer
+
+        assert_eq!(terminal.screen.blit, Some((0, rows - 1, -2)));
+        assert!((0..rows - 3).all(|y| !terminal.screen.dirty.row(y)));
+        assert!(terminal.screen.dirty.row(rows - 2) && terminal.screen.dirty.row(rows - 1));
+
+        terminal.screen.draw().unwrap();
+
+        assert_eq!(terminal.screen.blit, None);
+
+        // a scroll while an overlay is shown falls back to drawing every row that changed
+
+        terminal.screen.search.toggle();
+        terminal.handle_bytes(b"\r\n").unwrap();
+
+        assert_eq!(terminal.screen.blit, None);
+        assert!(terminal.screen.dirty.row(rows - 5));
+    }
+
+    #[test]
+    fn tmux_passthrough() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1bPtmux;\x1b\x1b[1m\x1b\x1bP$qm\x1b\x1b\\\x1b\\a").unwrap();
+
+        assert_eq!(pty.take_output(), "\x1bP1$r0;1m\x1b\\");
+        assert_eq!(terminal.screen.buf[0][0].byte, 'a');
+        assert!(terminal.screen.buf[0][0].attr.flags & BOLD != 0);
+
+        // an unterminated title inside the passthrough ends with it instead of swallowing what follows
+
+        terminal.handle_bytes(b"\x1bPtmux;\x1b\x1b]0;title\x1b\\b").unwrap();
+
+        assert_eq!(terminal.screen.buf[0][1].byte, 'b');
+    }
+
+    #[test]
+    fn padding_cells() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"abc\r\ndef").unwrap();
+
+        let screen = &mut terminal.screen;
+        let (width, height) = (screen.cell.width, screen.cell.height);
+        let (cols, rows) = (screen.cols as i32, screen.rows as i32);
+        let top = screen.history.len() as i32;
+
+        assert!(screen.cell_at(cols * width + width / 2, rows * height + 1) == Position { x: cols - 1, y: rows - 1 });
+        assert!(screen.cell_at(-width, -height) == Position { x: 0, y: 0 });
+        assert!(screen.selection_at(cols * width + width / 2, rows * height + 1) == Position { x: cols, y: top + rows - 1 });
+        assert!(screen.selection_at(-1, -1) == Position { x: 0, y: top - 1 });
+
+        // dragging out of the left edge used to leave a start past the end of the line
+
+        screen.selection.start = screen.selection_at(-3 * width, 0);
+        screen.selection.end = screen.selection_at(2 * width, 0);
+
+        assert_eq!(screen.get_selection(LinkCopy::Text).as_deref(), Some("ab"));
+
+        screen.selection.start = Position { x: cols + 5, y: top };
+        screen.selection.end = Position { x: 1, y: top };
+
+        assert_eq!(screen.get_selection(LinkCopy::Text).as_deref().map(str::trim_end), Some("bc"));
+
+        terminal.handle_bytes(b"\x1b[?1000;1006h").unwrap();
+        terminal.screen.handle_mouse_motion(cols * width + width / 2, rows * height + 1, x11::xlib::ButtonPress).unwrap();
+
+        assert_eq!(pty.take_output(), format!("\x1b[<{};{};{}M", terminal.screen.buttons.as_code(), cols, rows));
+    }
+
+    #[test]
+    fn horizontal_margins() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let row = |terminal: &Terminal, y: i32| terminal.screen.row_text(y, 0, usize::MAX, false);
+
+        // DECSLRM is save cursor until DECLRMM is set
+
+        terminal.handle_bytes(b"\x1b[2;4s\x1b[?69h\x1b[?69$p").unwrap();
+
+        assert_eq!(pty.take_output(), "\x1b[?69;1$y");
+
+        terminal.handle_bytes(b"\x1b[3;5s\x1bP$qs\x1b\\").unwrap();
+
+        assert_eq!(pty.take_output(), "\x1bP1$r3;5s\x1b\\");
+
+        terminal.handle_bytes(b"\x1b[1;3Habcdef").unwrap();
+
+        assert_eq!(row(&terminal, 0), "  abc");
+        assert_eq!(row(&terminal, 1), "  def");
+
+        terminal.handle_bytes(b"\x1b[1;3H\x1b[@\x1b[2;4H\x1b[P").unwrap();
+
+        assert_eq!(row(&terminal, 0), "   ab");
+        assert_eq!(row(&terminal, 1), "  df");
+
+        terminal.handle_bytes(b"\x1b[1;1Hxy\x1b[1;4H\x1b[M").unwrap();
+
+        assert_eq!(row(&terminal, 0), "xydf");
+        assert_eq!(row(&terminal, 1), "");
+        assert_eq!(terminal.screen.cursor.position.x, 2);
+
+        terminal.handle_bytes(b"\x1b[L").unwrap();
+
+        assert_eq!(row(&terminal, 0), "xy");
+        assert_eq!(row(&terminal, 1), "  df");
+
+        terminal.handle_bytes(b"\x1b[?69l\x1bP$qs\x1b\\").unwrap();
+
+        assert_eq!(pty.take_output(), format!("\x1bP1$r1;{}s\x1b\\", terminal.screen.cols));
+    }
+
+    #[test]
+    fn column_edits() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let rows = |terminal: &Terminal| (terminal.screen.row_text(0, 0, usize::MAX, false), terminal.screen.row_text(1, 0, usize::MAX, false));
+
+        terminal.handle_bytes(b"abcdefgh\r\nijklmnop\x1b[1;3H\x1b['}").unwrap();
+
+        assert_eq!(rows(&terminal), (String::from("ab cdefgh"), String::from("ij klmnop")));
+
+        terminal.handle_bytes(b"\x1b[2'~").unwrap();
+
+        assert_eq!(rows(&terminal), (String::from("abdefgh"), String::from("ijlmnop")));
+
+        // with DECLRMM set the columns past the right margin stay put
+
+        terminal.handle_bytes(b"\x1b[?69h\x1b[2;5s\x1b[1;3H\x1b['}").unwrap();
+
+        assert_eq!(rows(&terminal), (String::from("ab degh"), String::from("ij lmop")));
+
+        terminal.handle_bytes(b"\x1b[2'~").unwrap();
+
+        assert_eq!(rows(&terminal), (String::from("abe  gh"), String::from("ijm  op")));
+
+        // outside the margins neither does anything
+
+        terminal.handle_bytes(b"\x1b[1;7H\x1b['}\x1b['~").unwrap();
+
+        assert_eq!(rows(&terminal), (String::from("abe  gh"), String::from("ijm  op")));
+    }
+
+    #[test]
+    fn idle_watchdog() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.screen.config.idle_timeout = 1;
+        terminal.screen.idle_since = Instant::now() - Duration::from_secs(61);
+        terminal.screen.last_activity = terminal.screen.idle_since;
+
+        terminal.screen.timers();
+
+        assert!(terminal.screen.idle_warning.is_some());
+
+        terminal.screen.idle_warning = Some(Instant::now() - IDLE_GRACE);
+        terminal.screen.timers();
+
+        assert!(terminal.screen.should_close);
+
+        terminal.screen.should_close = false;
+        terminal.screen.last_activity = Instant::now();
+        terminal.screen.timers();
+
+        assert!(terminal.screen.idle_warning.is_none());
+    }
+
+    #[test]
+    fn frame_deadline() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.screen.config.blink = false;
+        terminal.screen.config.idle_timeout = 0;
+        terminal.screen.config.frame_interval = 8;
+        terminal.screen.draw().unwrap();
+        terminal.screen.last_frame = Instant::now();
+
+        assert_eq!(terminal.screen.timeout(), None);
+
+        terminal.handle_bytes(b"hello").unwrap();
+
+        assert!(terminal.screen.timeout().is_some_and(|timeout| !timeout.is_zero() && timeout <= Duration::from_millis(8)));
+
+        terminal.screen.last_frame = Instant::now() - Duration::from_millis(8);
+
+        assert_eq!(terminal.screen.timeout(), Some(Duration::ZERO));
+
+        // held back frames wait for the update to end instead
+
+        terminal.handle_bytes(b"\x1b[?2026h").unwrap();
+
+        assert!(terminal.screen.timeout().is_some_and(|timeout| timeout > Duration::from_millis(8)));
+    }
+
+    #[test]
+    fn decoration_colors() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b[31;4;9mA\x1b[7mB\x1b[58:2::1:2:3mC\x1b[59mD\x1b[27mE\x1b[0mF").unwrap();
+
+        let (fg, bg, red) = (terminal.screen.config.fg.raw, terminal.screen.config.bg.raw, terminal.screen.config.colors[1].raw);
+        let drawn = (0..6).map(|x| terminal.screen.resolve(terminal.screen.buf[0][x].attr, false, false)).collect::<Vec<Attribute>>();
+        let selected = (0..6).map(|x| terminal.screen.resolve(terminal.screen.buf[0][x].attr, true, false)).collect::<Vec<Attribute>>();
+
+        assert!(drawn[0].decorations() == (red, red));
+        assert!(drawn[1].fg.raw == bg && drawn[1].bg.raw == red);
+        assert!(drawn[1].decorations() == (bg, bg));
+        assert!(selected[1].decorations() == (red, red));
+
+        // SGR 58 is neither inverted nor replaced by the selection
+
+        let (underline, strike) = drawn[2].decorations();
+
+        assert_eq!(underline.rgb(), (1, 2, 3));
+        assert!(strike == bg);
+        assert!(selected[2].decorations().0 == underline);
+        assert!(drawn[3].decorations() == (bg, bg));
+        assert!(drawn[4].decorations() == (red, red));
+        assert!(drawn[5] == Attribute::new(&terminal.screen.config));
+        assert!(drawn[5].decorations() == (fg, fg));
+
+        terminal.handle_bytes(b"\x1b[7;9m\x1bP$qm\x1b\\").unwrap();
+
+        assert_eq!(pty.take_output(), "\x1bP1$r0;7;9m\x1b\\");
+    }
+
+    #[test]
+    fn resolution_order() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b[2;31mA\x1b[7mB\x1b[0;8;4:1;58:5:2mC\x1b[28mD").unwrap();
+
+        let (bg, red, green) = (terminal.screen.config.bg.raw, terminal.screen.config.colors[1].raw, terminal.screen.config.colors[2].raw);
+        let resolve = |x: usize, selected: bool, cursor: bool| terminal.screen.resolve(terminal.screen.buf[0][x].attr, selected, cursor);
+
+        // faint keeps the color it was given and fades it into the background, after SGR 7 it fades into the red
+
+        assert!(resolve(0, false, false).fg.raw == red.blend(&bg));
+        assert!(resolve(0, false, false).bg.raw == bg);
+        assert!(resolve(1, false, false).fg.raw == bg.blend(&red));
+        assert!(resolve(1, false, false).bg.raw == red);
+        assert!(resolve(1, true, false).bg.raw == bg.blend(&red));
+
+        // concealed text and its underline take the background, a block cursor still shows over it
+
+        assert!(resolve(2, false, false).decorations() == (bg, bg));
+        assert!(resolve(2, false, true).fg.raw == resolve(2, false, true).bg.raw);
+        assert!(resolve(3, false, false).decorations().0 == green);
+
+        let cursor = resolve(0, false, true);
+
+        assert!(cursor.fg.raw == bg && cursor.bg.raw == terminal.screen.config.fg.raw);
+
+        terminal.handle_bytes(b"\x1b[8m\x1bP$qm\x1b\\").unwrap();
+
+        assert_eq!(pty.take_output(), "\x1bP1$r0;8m\x1b\\");
+    }
+
+    #[test]
+    fn inline_images() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let png = "iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAYAAAD0In+KAAAAD0lEQVR42mP4z8AARAwMAAz8Af/lhqjSAAAAAElFTkSuQmCC";
+
+        terminal.handle_bytes(format!("ab\x1b]1337;File=inline=1;width=2;height=3;preserveAspectRatio=0:{}\x07", png).as_bytes()).unwrap();
+
+        let image = terminal.screen.images.iter(false).next().map(|image| (image.x, image.rows()));
+
+        assert_eq!(image, Some((2, 0..3)));
+        assert_eq!(terminal.screen.cursor.position, Position { x: 2, y: 3 });
+
+        // downloads and broken files are dropped
+
+        terminal.handle_bytes(format!("\x1b]1337;File=name=YQ==:{}\x07\x1b]1337;File=inline=1:AAAA\x07", png).as_bytes()).unwrap();
+
+        assert_eq!(terminal.screen.images.iter(false).count(), 1);
+
+        // a scroll region moves the image and cuts off what it pushes past the margin, erasing the screen removes it
+
+        terminal.handle_bytes(b"\x1b[H\x1b[2L").unwrap();
+
+        assert_eq!(terminal.screen.images.iter(false).next().map(|image| image.rows()), Some(2..5));
+
+        terminal.handle_bytes(b"\x1b[2;4r\x1b[2H\x1b[2M").unwrap();
+
+        assert_eq!(terminal.screen.images.iter(false).next().map(|image| image.rows()), Some(1..3));
+
+        terminal.handle_bytes(b"\x1b[r\x1b[?1049h").unwrap();
+        terminal.handle_bytes(format!("\x1b]1337;File=inline=1;width=2;height=3;preserveAspectRatio=0:{}\x07\x1b[?1049l", png).as_bytes()).unwrap();
+
+        assert_eq!(terminal.screen.images.iter(true).count(), 0);
+
+        terminal.handle_bytes(b"\x1b[2J").unwrap();
+
+        assert_eq!(terminal.screen.images.iter(false).count(), 0);
+    }
+
+    #[test]
+    fn cursor_styles() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let styles = [(0, 1), (1, 1), (2, 2), (3, 3), (4, 4), (5, 5), (6, 6)];
+
+        for (style, report) in styles {
+            terminal.handle_bytes(format!("\x1b[{} q\x1bP$q q\x1b\\", style).as_bytes()).unwrap();
+
+            assert_eq!(pty.take_output(), format!("\x1bP1$r{} q\x1b\\", report));
+        }
+
+        // DECLL and unknown styles leave the cursor alone
+
+        terminal.handle_bytes(b"\x1b[1q\x1b[9 q").unwrap();
+
+        assert!(terminal.screen.cursor_style == CursorStyle::Line && !terminal.screen.mode.att610);
+    }
+
+    #[test]
+    fn media_copy() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.screen.config.print_command = String::from("cat > /dev/null");
+
+        terminal.handle_bytes(b"\x1b[?5i").unwrap();
+
+        assert!(terminal.screen.auto_print);
+
+        // every line goes to the same print command, which ends with auto print
+
+        terminal.handle_bytes(b"a\r\nb\r\n").unwrap();
+
+        assert!(terminal.screen.printer.is_some());
+
+        terminal.handle_bytes(b"\x1b[?4i").unwrap();
+
+        assert!(!terminal.screen.auto_print);
+        assert!(terminal.screen.printer.is_none());
+
+        terminal.handle_bytes(b"\x1b[?5i\x1bc").unwrap();
+
+        assert!(!terminal.screen.auto_print);
+    }
+
+    #[test]
+    fn scroll_counts() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let rows = terminal.screen.buf.len();
+
+        terminal.handle_bytes(b"\x1b[2;5r\x1b[2Ha\x1b[3Hb\x1b[4Hc\x1b[5Hd\x1b[2S").unwrap();
+
+        let column = |terminal: &Terminal| terminal.screen.buf[..6].iter().map(|line| line.cells[0].byte).collect::<String>();
+
+        assert_eq!(column(&terminal), " cd   ");
+
+        terminal.handle_bytes(b"\x1b[3T").unwrap();
+
+        assert_eq!(column(&terminal), "    c ");
+        assert_eq!(terminal.screen.buf.len(), rows);
+    }
+
+    #[test]
+    fn scrollback_eviction() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.screen.config.scrollback = 10;
+
+        let rows = terminal.screen.buf.len();
+        let lines = |count: usize| (0..count).map(|n| format!("{}\r\n", n)).collect::<String>();
+
+        terminal.handle_bytes(lines(rows + 10).as_bytes()).unwrap();
+        terminal.screen.scroll_view(3);
+
+        let top = |terminal: &Terminal| terminal.screen.line(terminal.screen.view_to_abs(0)).map(|line| line.iter().map(|character| character.byte).collect::<String>());
+        let shown = top(&terminal);
+
+        // the history is full, every line pushed drops the oldest one but the view stays on the same content
+
+        terminal.handle_bytes(lines(4).as_bytes()).unwrap();
+
+        assert_eq!(terminal.screen.history.len(), 10);
+        assert_eq!(terminal.screen.scroll, 7);
+        assert_eq!(top(&terminal), shown);
+    }
+
+    #[test]
+    fn tab_stops() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let last = terminal.screen.tabs.len() as i32 - 1;
+
+        terminal.handle_bytes(b"\x1b[2I").unwrap();
+
+        assert_eq!(terminal.screen.cursor.position.x, 16);
+
+        terminal.handle_bytes(b"\x1b[Z\x1b[5Z").unwrap();
+
+        assert_eq!(terminal.screen.cursor.position.x, 0);
+
+        terminal.handle_bytes(b"\x1b[3g\x1b[I\x1bH\x1b[g\x1b[999I").unwrap();
+
+        assert_eq!(terminal.screen.cursor.position.x, last);
+        assert!(terminal.screen.tabs.iter().all(|stop| !stop));
+    }
+
+    #[test]
+    fn rectangles() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let rows = |terminal: &Terminal| terminal.screen.buf[..4].iter().map(|line| line[..5].iter().map(|character| character.byte).collect::<String>()).collect::<Vec<String>>();
+
+        terminal.handle_bytes(b"\x1b[35;2;2;3;4$x").unwrap();
+
+        assert_eq!(rows(&terminal), ["     ", " ### ", " ### ", "     "]);
+
+        terminal.handle_bytes(b"\x1b[2;2;3;3;1;1;1$v\x1b[3;3;3;4$z").unwrap();
+
+        assert_eq!(rows(&terminal), ["##   ", "#### ", " #   ", "     "]);
+    }
+
+    #[test]
+    fn full_reset() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b[3g\x1b[2;5r\x1b[?6;7l\x1b[4h\x1b[?1049h\x1b(0x\x1bc").unwrap();
+
+        assert!(!terminal.screen.mode.decalt && !terminal.screen.mode.decom && !terminal.screen.mode.decim);
+        assert!(terminal.screen.mode.decawm);
+        assert_eq!((terminal.screen.scrolling_region.top, terminal.screen.scrolling_region.bottom), (0, terminal.screen.buf.len() - 2));
+        assert!(terminal.screen.tabs[8]);
+        assert_eq!(terminal.screen.charsets[0], Charset::Ascii);
+        assert_eq!(terminal.screen.buf[0].cells[0].byte, ' ');
+    }
+
+    #[test]
+    fn child_environment() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let env = environment(7, &terminal.screen.config, &[]).into_iter().collect::<HashMap<&str, String>>();
+
+        assert_eq!(env.get("WINDOWID").map(String::as_str), Some("7"));
+        assert_eq!(env.get("COLORFGBG"), Some(&terminal.screen.config.colorfgbg()));
+        assert!(!env.contains_key("TERMAL_SOCKET"));
+
+        // the default background is darker than anything in the palette, vim reads the 0 as a dark theme
+
+        terminal.screen.config.bg.raw = xlib::Color::new(0x0d, 0x16, 0x17);
+        terminal.screen.config.fg.raw = xlib::Color::new(0xfa, 0xfa, 0xfa);
+
+        assert_eq!(terminal.screen.config.colorfgbg(), "15;0");
+    }
+
+    #[test]
+    fn sgr_param_limit() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        let fg = terminal.screen.attr.fg;
+
+        terminal.handle_bytes(format!("\x1b[{}38m", "0;".repeat(127)).as_bytes()).unwrap();
+
+        assert!(terminal.screen.attr.fg == fg);
+    }
+
+    #[test]
+    fn prefixed_finals() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"top\r\n").unwrap();
+
+        // XTQMODKEYS and XTRMTITLE share their final with SGR and SD
+
+        terminal.handle_bytes(b"\x1b[?4m\x1b[>0T").unwrap();
+
+        assert!(terminal.screen.attr.underline == Underline::None);
+        assert_eq!(terminal.screen.buf[0][0].byte, 't');
+    }
+
+    #[test]
+    fn user_var_limits() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        for n in 0..MAX_USER_VARS + 1 {
+            terminal.handle_bytes(format!("\x1b]1337;SetUserVar=var{}=YQ==\x07", n).as_bytes()).unwrap();
+        }
+
+        assert_eq!(terminal.screen.user_vars.len(), MAX_USER_VARS);
+
+        terminal.handle_bytes(format!("\x1b]1337;SetUserVar=var0={}\x07", "YWFh".repeat(MAX_USER_VAR_BYTES / 4)).as_bytes()).unwrap();
+        terminal.handle_bytes(b"\x1b]1337;SetUserVar=var1=Yg==\x07").unwrap();
+
+        assert_eq!(terminal.screen.user_vars.get("var0").map(String::as_str), Some("a"));
+        assert_eq!(terminal.screen.user_vars.get("var1").map(String::as_str), Some("b"));
+    }
+
+    #[test]
+    fn replies() {
+        let pty = FakePty::default();
+
+        let mut terminal = terminal(&pty);
+
+        terminal.handle_bytes(b"\x1b[c\x1b[5n\x1b[>c\x1b[>0q").unwrap();
+
+        assert_eq!(pty.take_output(), "\x1b[?62;22c\x1b[0n\x1b[>1;100;0c\x1bP>|termal 0.1.0\x1b\\");
+
+        pty.input.borrow_mut().extend(b"\x1b[3;5H\x1b[6n");
+
+        assert!(terminal.read_tty().unwrap());
+        assert_eq!(pty.take_output(), "\x1b[3;5R");
+        assert!(!terminal.read_tty().unwrap());
+    }
+}
 
@@ -0,0 +1,60 @@
+use zbus::blocking::Connection;
+use zbus::zvariant::Value;
+
+use std::sync::mpsc::{self, SyncSender};
+use std::collections::HashMap;
+use std::thread;
+
+// notifications waiting for the notification server, more than this are dropped while it is slow to answer
+
+const BACKLOG: usize = 16;
+
+
+// https://specifications.freedesktop.org/notification-spec/latest/protocol.html, Notify is called from a thread that keeps
+// the session bus connection so a slow notification server never holds up the terminal
+
+pub struct Notifier {
+    notifications: SyncSender<(String, String)>,
+}
+
+impl Notifier {
+    pub fn new() -> Notifier {
+        let (notifications, received) = mpsc::sync_channel::<(String, String)>(BACKLOG);
+
+        thread::spawn(move || {
+            let mut connection = None;
+
+            for (title, body) in received {
+                if connection.is_none() {
+                    connection = Connection::session().map_err(|err| println!("[+] failed to connect to the session bus: {}", err)).ok();
+                }
+
+                if let Some(connection) = connection.as_ref() {
+                    if let Err(err) = notify(connection, &title, &body) {
+                        println!("[+] failed to send notification: {}", err);
+                    }
+                }
+            }
+        });
+
+        Notifier {
+            notifications,
+        }
+    }
+
+    pub fn send(&self, title: &str, body: &str) {
+        let _ = self.notifications.try_send((title.to_string(), body.to_string()));
+    }
+}
+
+fn notify(connection: &Connection, title: &str, body: &str) -> Result<(), Box<dyn std::error::Error>> {
+    connection.call_method(
+        Some("org.freedesktop.Notifications"),
+        "/org/freedesktop/Notifications",
+        Some("org.freedesktop.Notifications"),
+        "Notify",
+        &("termal", 0u32, "", title, body, Vec::<&str>::new(), HashMap::<&str, Value>::new(), -1i32),
+    )?;
+
+    Ok(())
+}
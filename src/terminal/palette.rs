@@ -0,0 +1,103 @@
+use crate::keybind::{Command, Keybind};
+
+use std::cmp::Reverse;
+
+
+pub const MAX_ENTRIES: usize = 10;
+
+pub struct Palette {
+    pub open: bool,
+    pub query: String,
+    pub selected: usize,
+}
+
+impl Palette {
+    pub fn new() -> Palette {
+        Palette {
+            open: false,
+            query: String::new(),
+            selected: 0,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.query.clear();
+        self.selected = 0;
+    }
+
+    pub fn entries(&self, keybinds: &[Keybind]) -> Vec<(Command, String)> {
+        let mut entries = Command::ALL.iter()
+            .filter(|command| **command != Command::Palette)
+            .filter_map(|command| {
+                let binding = keybinds.iter()
+                    .find(|keybind| keybind.command == *command)
+                    .map(|keybind| keybind.binding.clone())
+                    .unwrap_or_default();
+
+                fuzzy_score(&self.query, command.description()).map(|score| (score, *command, binding))
+            })
+            .collect::<Vec<(i32, Command, String)>>();
+
+        // stable sort keeps the declaration order for equal scores
+
+        entries.sort_by_key(|entry| Reverse(entry.0));
+
+        entries.into_iter().take(MAX_ENTRIES).map(|(_, command, binding)| (command, binding)).collect()
+    }
+
+    pub fn select_next(&mut self, count: usize) {
+        if count > 0 {
+            self.selected = (self.selected + 1) % count;
+        }
+    }
+
+    pub fn select_prev(&mut self, count: usize) {
+        if count > 0 {
+            self.selected = (self.selected + count - 1) % count;
+        }
+    }
+}
+
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    // every query character has to appear in order, consecutive and word-start hits score higher
+
+    let candidate = candidate.to_lowercase().chars().collect::<Vec<char>>();
+    let mut score = 0;
+    let mut position = 0;
+    let mut last: Option<usize> = None;
+
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let index = position + candidate[position..].iter().position(|x| *x == c)?;
+
+        if last.is_some_and(|last| last + 1 == index) {
+            score += 5;
+        }
+
+        if index == 0 || candidate[index - 1] == ' ' {
+            score += 3;
+        }
+
+        score -= (index - position) as i32;
+
+        last = Some(index);
+        position = index + 1;
+    }
+
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy() {
+        assert!(fuzzy_score("", "Copy selection to clipboard").is_some());
+        assert!(fuzzy_score("cpy", "Copy selection to clipboard").is_some());
+        assert!(fuzzy_score("zoom", "Copy selection to clipboard").is_none());
+
+        assert!(fuzzy_score("paste", "Paste from clipboard") > fuzzy_score("paste", "Open a new window"));
+        assert!(fuzzy_score("inc", "Increase font size") > fuzzy_score("inc", "Reset font size"));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::config::PasteTransform;
+
+
+pub fn transform(text: &str, transform: &PasteTransform) -> String {
+    let mut text = if transform.crlf { text.replace("\r\n", "\n") } else { text.to_string() };
+
+    if transform.dedent {
+        text = dedent(&text);
+    }
+
+    if transform.strip_newline {
+        text.truncate(text.trim_end_matches(['\r', '\n']).len());
+    }
+
+    text
+}
+
+// removes the indentation shared by every non blank line, blank lines dont count towards it
+
+fn dedent(text: &str) -> String {
+    let indent = text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+
+    text.split_inclusive('\n')
+        .map(|line| if line.trim().is_empty() { line.trim_start_matches([' ', '\t']) } else { &line[indent..] })
+        .collect()
+}
+
+// cuts the text down to at most limit bytes without splitting a character, zero means no limit
+
+pub fn truncate(text: &mut String, limit: usize) -> bool {
+    if limit == 0 || text.len() <= limit {
+        return false;
+    }
+
+    let end = (0..=limit).rev().find(|end| text.is_char_boundary(*end)).unwrap_or(0);
+
+    text.truncate(end);
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms() {
+        let all = PasteTransform { strip_newline: true, crlf: true, dedent: true };
+        let none = PasteTransform { strip_newline: false, crlf: false, dedent: false };
+
+        assert_eq!(transform("ls\r\n", &none), "ls\r\n");
+        assert_eq!(transform("ls\r\n\n", &all), "ls");
+        assert_eq!(transform("    if x:\r\n        y\r\n\r\n    z\n", &all), "if x:\n    y\n\nz");
+        assert_eq!(transform("\ta\n\t\tb\n", &PasteTransform { dedent: true, ..none }), "a\n\tb\n");
+    }
+
+    #[test]
+    fn limits() {
+        let mut text = String::from("aßc");
+
+        assert!(!truncate(&mut text, 0));
+        assert!(!truncate(&mut text, 4));
+        assert!(truncate(&mut text, 2));
+        assert_eq!(text, "a");
+    }
+}
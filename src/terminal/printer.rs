@@ -0,0 +1,83 @@
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, SyncSender};
+use std::io::{self, Write};
+use std::thread;
+
+// lines waiting for a slow print command, anything past this is dropped so the terminal never waits on the printer
+
+const BACKLOG: usize = 1024;
+
+
+// one run of the print command, text sent here is written to its stdin from another thread. dropping the printer
+// closes stdin, which is how the command learns that the print job is over
+
+pub struct Printer {
+    text: SyncSender<String>,
+}
+
+impl Printer {
+    pub fn open(command: &str) -> io::Result<Printer> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take();
+
+        let (text, received) = mpsc::sync_channel::<String>(BACKLOG);
+
+        thread::spawn(move || {
+            if let Some(stdin) = stdin.as_mut() {
+                for text in received {
+                    if stdin.write_all(text.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            }
+
+            drop(stdin);
+
+            child.wait()
+        });
+
+        Ok(Printer {
+            text,
+        })
+    }
+
+    pub fn send(&self, text: String) {
+        let _ = self.text.try_send(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+    use std::fs;
+
+    #[test]
+    fn streams() {
+        let path = std::env::temp_dir().join(format!("termal-printer-{}", std::process::id()));
+
+        let printer = Printer::open(&format!("cat > {}", path.display())).unwrap();
+
+        printer.send(String::from("first\n"));
+        printer.send(String::from("second\n"));
+
+        drop(printer);
+
+        let started = Instant::now();
+
+        while fs::read_to_string(&path).unwrap_or_default() != "first\nsecond\n" && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,149 @@
+use regex::{Regex, RegexBuilder};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub y: i32,
+    pub start: usize,
+    pub end: usize,
+}
+
+// matches are kept in screen order, the newest match is selected first since that is closest to the prompt
+
+pub struct Search {
+    pub open: bool,
+    pub query: String,
+    pub regex: bool,
+    pub invalid: bool,
+    pub matches: Vec<Match>,
+    pub current: usize,
+}
+
+impl Search {
+    pub fn new() -> Search {
+        Search {
+            open: false,
+            query: String::new(),
+            regex: false,
+            invalid: false,
+            matches: Vec::new(),
+            current: 0,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.query.clear();
+        self.matches.clear();
+        self.invalid = false;
+        self.current = 0;
+    }
+
+    // smart case, the query is case insensitive until it contains an uppercase letter
+
+    pub fn pattern(&self) -> Result<Option<Regex>, regex::Error> {
+        if self.query.is_empty() {
+            return Ok(None);
+        }
+
+        let pattern = if self.regex { self.query.clone() } else { regex::escape(&self.query) };
+
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.query.chars().any(char::is_uppercase))
+            .build()
+            .map(Some)
+    }
+
+    pub fn update<I: Iterator<Item = (i32, Vec<char>)>>(&mut self, lines: I) {
+        self.matches.clear();
+
+        match self.pattern() {
+            Ok(Some(pattern)) => {
+                for (y, line) in lines {
+                    self.matches.extend(find(&line, &pattern).into_iter().map(|(start, end)| Match { y, start, end }));
+                }
+
+                self.invalid = false;
+            },
+            Ok(None) => self.invalid = false,
+            Err(_) => self.invalid = true,
+        }
+
+        self.current = self.matches.len().saturating_sub(1);
+    }
+
+    pub fn selected(&self) -> Option<Match> {
+        self.matches.get(self.current).copied()
+    }
+
+    pub fn select_older(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + self.matches.len() - 1) % self.matches.len();
+        }
+    }
+
+    pub fn select_newer(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + 1) % self.matches.len();
+        }
+    }
+}
+
+// returns the matched column ranges, a line holds one char per cell so char indices are columns
+
+pub fn find(line: &[char], pattern: &Regex) -> Vec<(usize, usize)> {
+    let text = line.iter().collect::<String>();
+
+    let column = |offset: usize| text[..offset].chars().count();
+
+    pattern.find_iter(&text)
+        .filter(|found| !found.is_empty())
+        .map(|found| (column(found.start()), column(found.end())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(query: &str, regex: bool, lines: &[&str]) -> Search {
+        let mut search = Search::new();
+
+        search.query = query.to_string();
+        search.regex = regex;
+        search.update(lines.iter().enumerate().map(|(y, line)| (y as i32, line.chars().collect())));
+
+        search
+    }
+
+    #[test]
+    fn smart_case() {
+        let lines = ["Straße error", "STRASSE ERROR", "straße Error"];
+
+        assert_eq!(search("error", false, &lines).matches.len(), 3);
+        assert_eq!(search("Error", false, &lines).matches, vec![Match { y: 2, start: 7, end: 12 }]);
+        assert_eq!(search("STRAßE", false, &lines).matches.len(), 0);
+        assert_eq!(search("straße", false, &lines).matches.len(), 2);
+        assert_eq!(search("a.b", false, &["axb a.b"]).matches, vec![Match { y: 0, start: 4, end: 7 }]);
+    }
+
+    #[test]
+    fn regex() {
+        let found = search(r"e\w+r", true, &["ßeer x", "error"]);
+
+        assert_eq!(found.matches, vec![Match { y: 0, start: 1, end: 4 }, Match { y: 1, start: 0, end: 5 }]);
+        assert_eq!(found.selected(), Some(Match { y: 1, start: 0, end: 5 }));
+        assert!(search("(", true, &["("]).invalid);
+        assert!(search("x*", true, &["abc"]).matches.is_empty());
+
+        let mut found = found;
+
+        found.select_newer();
+
+        assert_eq!(found.current, 0);
+
+        found.select_older();
+
+        assert_eq!(found.current, 1);
+    }
+}
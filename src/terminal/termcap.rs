@@ -0,0 +1,73 @@
+use crate::pty;
+
+
+// answers for XTGETTCAP, the key caps match what handle_key sends with DECCKM set as terminfo expects, an empty value is a boolean cap
+
+pub fn capability(name: &str) -> Option<&'static str> {
+    match name {
+        "TN" | "name" => Some(pty::term()),
+        "Co" | "colors" => Some("256"),
+        "RGB" => Some(""),
+        "Tc" => Some(""),
+        "kcuu1" => Some("\x1bOA"),
+        "kcud1" => Some("\x1bOB"),
+        "kcuf1" => Some("\x1bOC"),
+        "kcub1" => Some("\x1bOD"),
+        "khome" => Some("\x1bOH"),
+        "kend" => Some("\x1bOF"),
+        "kich1" => Some("\x1b[2~"),
+        "kdch1" => Some("\x1b[3~"),
+        "kpp" => Some("\x1b[5~"),
+        "knp" => Some("\x1b[6~"),
+        "kcbt" => Some("\x1b[Z"),
+        "kbs" => Some("\x7f"),
+        "kf1" => Some("\x1bOP"),
+        "kf2" => Some("\x1bOQ"),
+        "kf3" => Some("\x1bOR"),
+        "kf4" => Some("\x1bOS"),
+        "kf5" => Some("\x1b[15~"),
+        "kf6" => Some("\x1b[17~"),
+        "kf7" => Some("\x1b[18~"),
+        "kf8" => Some("\x1b[19~"),
+        "kf9" => Some("\x1b[20~"),
+        "kf10" => Some("\x1b[21~"),
+        "kf11" => Some("\x1b[23~"),
+        "kf12" => Some("\x1b[24~"),
+        _ => None,
+    }
+}
+
+pub fn decode_hex(hex: &str) -> Option<String> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    String::from_utf8(bytes).ok()
+}
+
+pub fn encode_hex(text: &str) -> String {
+    text.bytes().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex() {
+        assert_eq!(decode_hex("544E").as_deref(), Some("TN"));
+        assert_eq!(decode_hex("6b637575").as_deref(), Some("kcuu"));
+        assert_eq!(decode_hex("54E"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(encode_hex("\x1bOA"), "1B4F41");
+        assert_eq!(capability("Co"), Some("256"));
+        assert_eq!(capability("Tc"), Some(""));
+        assert_eq!(capability("RGB"), Some(""));
+        assert_eq!(capability("smkx"), None);
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+
+// "{name}" is replaced with the user var of that name, unset vars expand to nothing and "{{" is a literal brace
+
+pub fn expand(template: &str, vars: &HashMap<String, String>) -> String {
+    let mut title = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+
+                title.push('{');
+            },
+            '{' => {
+                let name = chars.by_ref().take_while(|c| *c != '}').collect::<String>();
+
+                title.push_str(vars.get(&name).map_or("", |value| value.as_str()));
+            },
+            c => title.push(c),
+        }
+    }
+
+    title
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates() {
+        let vars = HashMap::from([
+            (String::from("branch"), String::from("main")),
+            (String::from("venv"), String::from("py3")),
+        ]);
+
+        assert_eq!(expand("termal", &vars), "termal");
+        assert_eq!(expand("termal ({branch}) {venv}", &vars), "termal (main) py3");
+        assert_eq!(expand("{missing}termal {{x}", &vars), "termal {x}");
+    }
+}
@@ -0,0 +1,63 @@
+const SCHEMES: [&str; 4] = ["https://", "http://", "file://", "mailto:"];
+
+
+// finds the url in the whitespace separated word around column x, trailing punctuation is most likely part of the sentence
+
+pub fn find(line: &[char], x: usize) -> Option<String> {
+    if line.get(x).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+
+    let start = line[..x].iter().rposition(|c| c.is_whitespace()).map_or(0, |start| start + 1);
+    let end = line[x..].iter().position(|c| c.is_whitespace()).map_or(line.len(), |end| x + end);
+
+    let word = line[start..end].iter().collect::<String>();
+
+    let offset = SCHEMES.iter()
+        .filter_map(|scheme| word.find(scheme))
+        .filter(|offset| word[..*offset].chars().count() <= x - start)
+        .min()?;
+
+    let mut url = &word[offset..];
+
+    while let Some(trimmed) = url.strip_suffix(['.', ',', ';', ':', '!', '?', '\'', '"', '>', ']']).or_else(|| {
+        url.strip_suffix(')').filter(|trimmed| trimmed.matches('(').count() < trimmed.matches(')').count() + 1)
+    }) {
+        url = trimmed;
+    }
+
+    SCHEMES.iter().all(|scheme| url != *scheme).then(|| url.to_string())
+}
+
+// https://datatracker.ietf.org/doc/html/rfc3986#section-2.3, everything but the unreserved characters is escaped
+
+pub fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        let line = "see (https://example.com/a_(b)). or http://x.org, ok".chars().collect::<Vec<char>>();
+
+        assert_eq!(find(&line, 10).as_deref(), Some("https://example.com/a_(b)"));
+        assert_eq!(find(&line, 4), None);
+        assert_eq!(find(&line, 40).as_deref(), Some("http://x.org"));
+        assert_eq!(find(&line, 1), None);
+        assert_eq!(find(&line, 3), None);
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode("rust vec"), "rust%20vec");
+        assert_eq!(encode("a&b=ü"), "a%26b%3D%C3%BC");
+    }
+}
@@ -0,0 +1,43 @@
+use crate::config::WidthTable;
+
+use unicode_width::UnicodeWidthChar;
+
+
+// blocks whose emoji only became wide with unicode 9, older wcwidth implementations still count them as one cell
+
+const LEGACY_NARROW: [(u32, u32); 5] = [
+    (0x231a, 0x23ff),
+    (0x2600, 0x27bf),
+    (0x1f300, 0x1f64f),
+    (0x1f680, 0x1f6ff),
+    (0x1f900, 0x1f9ff),
+];
+
+pub fn char_width(c: char, table: WidthTable, ambiguous_wide: bool) -> i32 {
+    let width = if ambiguous_wide { c.width_cjk() } else { c.width() };
+
+    match width {
+        Some(2) if table == WidthTable::Legacy && LEGACY_NARROW.iter().any(|(start, end)| (*start..=*end).contains(&(c as u32))) => 1,
+        Some(2) => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths() {
+        assert_eq!(char_width('a', WidthTable::Current, false), 1);
+        assert_eq!(char_width('字', WidthTable::Current, false), 2);
+        assert_eq!(char_width('字', WidthTable::Legacy, false), 2);
+        assert_eq!(char_width('😀', WidthTable::Current, false), 2);
+        assert_eq!(char_width('😀', WidthTable::Legacy, false), 1);
+
+        // U+00B1 is east asian ambiguous
+
+        assert_eq!(char_width('±', WidthTable::Current, false), 1);
+        assert_eq!(char_width('±', WidthTable::Current, true), 2);
+    }
+}
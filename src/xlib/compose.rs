@@ -0,0 +1,155 @@
+use x11::keysym;
+
+
+// only used without an input method, xim reads ~/.XCompose itself. the accent char is how the accent is typed after the multi key
+
+const ACCENTS: [(u32, char, &str, &str); 6] = [
+    (keysym::XK_dead_grave, '`', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    (keysym::XK_dead_acute, '\'', "aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+    (keysym::XK_dead_circumflex, '^', "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    (keysym::XK_dead_tilde, '~', "anoANO", "ãñõÃÑÕ"),
+    (keysym::XK_dead_diaeresis, '"', "aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+    (keysym::XK_dead_cedilla, ',', "cC", "çÇ"),
+];
+
+const LIGATURES: [(&str, char); 8] = [
+    ("ss", 'ß'),
+    ("ae", 'æ'),
+    ("AE", 'Æ'),
+    ("oe", 'œ'),
+    ("o/", 'ø'),
+    ("O/", 'Ø'),
+    ("oa", 'å'),
+    ("OA", 'Å'),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Feed {
+    Pass,
+    Pending,
+    Commit(char),
+    Cancel,
+}
+
+enum State {
+    Idle,
+    Dead(usize),
+    Multi(Vec<char>),
+}
+
+pub struct Compose {
+    state: State,
+}
+
+impl Compose {
+    pub fn new() -> Compose {
+        Compose {
+            state: State::Idle,
+        }
+    }
+
+    pub fn feed(&mut self, keysym: u32) -> Feed {
+        let modifier = (keysym::XK_Shift_L..=keysym::XK_Hyper_R).contains(&keysym) || (0xfe01..=0xfe0f).contains(&keysym);
+
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Idle if keysym == keysym::XK_Multi_key => {
+                self.state = State::Multi(Vec::new());
+
+                Feed::Pending
+            },
+            State::Idle => match ACCENTS.iter().position(|(dead, ..)| *dead == keysym) {
+                Some(index) => {
+                    self.state = State::Dead(index);
+
+                    Feed::Pending
+                },
+                None => Feed::Pass,
+            },
+            state if modifier => {
+                self.state = state;
+
+                Feed::Pending
+            },
+            State::Dead(index) => match keysym_char(keysym) {
+                Some(' ') => Feed::Commit(ACCENTS[index].1),
+                Some(c) => accent(index, c).map_or(Feed::Cancel, Feed::Commit),
+                None => Feed::Cancel,
+            },
+            State::Multi(mut chars) => {
+                let Some(c) = keysym_char(keysym) else { return Feed::Cancel };
+
+                chars.push(c);
+
+                match chars[..] {
+                    [_] => {
+                        self.state = State::Multi(chars);
+
+                        Feed::Pending
+                    },
+                    [first, second] => multi(first, second).map_or(Feed::Cancel, Feed::Commit),
+                    _ => Feed::Cancel,
+                }
+            },
+        }
+    }
+}
+
+// latin-1 keysyms are their code point, everything else in unicode is offset by 0x1000000
+
+pub fn keysym_char(keysym: u32) -> Option<char> {
+    match keysym {
+        0x20..=0x7e | 0xa0..=0xff => char::from_u32(keysym),
+        0x1000100..=0x110ffff => char::from_u32(keysym - 0x1000000),
+        _ => None,
+    }
+}
+
+fn accent(index: usize, c: char) -> Option<char> {
+    let (_, _, bases, accented) = ACCENTS[index];
+
+    bases.chars().position(|base| base == c).and_then(|position| accented.chars().nth(position))
+}
+
+fn multi(first: char, second: char) -> Option<char> {
+    let accented = [(first, second), (second, first)].into_iter().find_map(|(mark, c)| {
+        ACCENTS.iter().position(|(_, spacing, ..)| *spacing == mark).and_then(|index| accent(index, c))
+    });
+
+    accented.or_else(|| {
+        LIGATURES.iter()
+            .find(|(sequence, _)| sequence.chars().eq([first, second]))
+            .map(|(_, c)| *c)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences() {
+        let mut compose = Compose::new();
+
+        assert_eq!(compose.feed('a' as u32), Feed::Pass);
+        assert_eq!(compose.feed(keysym::XK_dead_acute), Feed::Pending);
+        assert_eq!(compose.feed(keysym::XK_Shift_L), Feed::Pending);
+        assert_eq!(compose.feed('E' as u32), Feed::Commit('É'));
+        assert_eq!(compose.feed(keysym::XK_dead_tilde), Feed::Pending);
+        assert_eq!(compose.feed(' ' as u32), Feed::Commit('~'));
+        assert_eq!(compose.feed(keysym::XK_dead_grave), Feed::Pending);
+        assert_eq!(compose.feed('x' as u32), Feed::Cancel);
+
+        assert_eq!(compose.feed(keysym::XK_Multi_key), Feed::Pending);
+        assert_eq!(compose.feed('"' as u32), Feed::Pending);
+        assert_eq!(compose.feed('u' as u32), Feed::Commit('ü'));
+        assert_eq!(compose.feed(keysym::XK_Multi_key), Feed::Pending);
+        assert_eq!(compose.feed('c' as u32), Feed::Pending);
+        assert_eq!(compose.feed(',' as u32), Feed::Commit('ç'));
+        assert_eq!(compose.feed(keysym::XK_Multi_key), Feed::Pending);
+        assert_eq!(compose.feed('s' as u32), Feed::Pending);
+        assert_eq!(compose.feed('s' as u32), Feed::Commit('ß'));
+
+        assert_eq!(keysym_char(0x10020ac), Some('€'));
+        assert_eq!(keysym_char(keysym::XK_Return), None);
+    }
+}
@@ -1,10 +1,25 @@
+mod compose;
+mod selection;
+
+use compose::{Compose, Feed};
+use selection::Selection;
+
+pub use compose::keysym_char;
+
+use crate::terminal::{Frontend, Window};
+
+use nix::libc;
+use arboard::Clipboard;
+
 use x11::xrender;
 use x11::xlib;
 use x11::xft;
 
+use std::collections::HashMap;
 use std::ffi;
 use std::ptr;
 use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Color {
@@ -40,9 +55,54 @@ impl Color {
         self.b + (self.g << 8) + (self.r << 16)
     }
 
+    pub fn blend(&self, other: &Color) -> Color {
+        Color::new((self.r + other.r) / 2, (self.g + other.g) / 2, (self.b + other.b) / 2)
+    }
+
     pub fn hex(&self) -> String {
         format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
     }
+
+    pub fn rgb(&self) -> (u64, u64, u64) {
+        (self.r, self.g, self.b)
+    }
+}
+
+pub const DEFAULT_CURSOR: u32 = 152;
+
+// missing from the x11 crate, see X11/extensions/XKB.h
+
+const XKB_USE_CORE_KBD: u32 = 0x0100;
+const XKB_GROUP_NAMES_MASK: u32 = 1 << 12;
+
+// set from the destroy callback when the input method server goes away, its input context is gone with it
+
+static IM_DESTROYED: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" fn im_destroyed(_xim: xlib::XIM, _client_data: xlib::XPointer, _call_data: xlib::XPointer) {
+    IM_DESTROYED.store(true, Ordering::Relaxed);
+}
+
+// cursor font glyphs by their x name, along with the css names kitty accepts for OSC 22
+
+pub fn cursor_shape(name: &str) -> Option<u32> {
+    match name {
+        "X_cursor" | "not-allowed" => Some(0),
+        "crosshair" => Some(34),
+        "fleur" | "move" => Some(52),
+        "hand1" => Some(58),
+        "hand2" | "pointer" => Some(60),
+        "left_ptr" | "default" | "arrow" => Some(68),
+        "pencil" => Some(86),
+        "plus" => Some(90),
+        "question_arrow" | "help" => Some(92),
+        "sb_h_double_arrow" | "ew-resize" | "col-resize" => Some(108),
+        "sb_v_double_arrow" | "ns-resize" | "row-resize" => Some(116),
+        "tcross" | "cell" => Some(130),
+        "watch" | "wait" | "progress" => Some(150),
+        "xterm" | "text" => Some(DEFAULT_CURSOR),
+        _ => None,
+    }
 }
 
 pub struct Display {
@@ -55,11 +115,31 @@ pub struct Display {
     back_buffer: u64,
     window: u64,
     screen: i32,
+    depth: u32,
+    masks: [u64; 3],
+    true_color: bool,
+    compose: Compose,
+
+    // glyph indices by font and character, fonts are only closed when the size changes so the entries stay valid until then
+    glyphs: HashMap<(usize, char), u32>,
+    specs: Vec<xft::XftGlyphFontSpec>,
+    clipboard: Clipboard,
+    selection: Selection,
 }
 
 impl Drop for Display {
     fn drop(&mut self) {
         unsafe {
+            self.check_input_method();
+
+            if !self.xic.is_null() {
+                xlib::XDestroyIC(self.xic);
+            }
+
+            if !self.xim.is_null() {
+                xlib::XCloseIM(self.xim);
+            }
+
             xlib::XFreePixmap(self.dpy, self.back_buffer);
             xlib::XFreeGC(self.dpy, self.gc);
             xlib::XDestroyWindow(self.dpy, self.window);
@@ -68,18 +148,36 @@ impl Drop for Display {
     }
 }
 
+// with several x screens on one display only the root window of the screen holding the pointer reports it as inside
+
+unsafe fn pointer_screen(dpy: *mut xlib::_XDisplay) -> i32 {
+    let (mut root, mut child) = (0, 0);
+    let (mut root_x, mut root_y, mut x, mut y, mut mask) = (0, 0, 0, 0, 0);
+
+    (0..xlib::XScreenCount(dpy))
+        .find(|screen| xlib::XQueryPointer(dpy, xlib::XRootWindow(dpy, *screen), &mut root, &mut child, &mut root_x, &mut root_y, &mut x, &mut y, &mut mask) == xlib::True)
+        .unwrap_or_else(|| xlib::XDefaultScreen(dpy))
+}
+
 impl Display {
-    pub fn open() -> Result<Display, Box<dyn std::error::Error>> {
-        let dpy = unsafe { xlib::XOpenDisplay(ptr::null()) };
+    pub fn open(follow_pointer: bool) -> Result<Display, Box<dyn std::error::Error>> {
+        // the selection is served from a second connection on its own thread
+
+        let dpy = unsafe {
+            xlib::XInitThreads();
+            xlib::XOpenDisplay(ptr::null())
+        };
 
         if dpy.is_null() {
             Err("failed to open display".into())
         } else {
             unsafe {
+                let screen = if follow_pointer { pointer_screen(dpy) } else { xlib::XDefaultScreen(dpy) };
+
                 let bg = Color::new(0, 0, 0).encode();
                 let window = xlib::XCreateSimpleWindow(
                     dpy,
-                    xlib::XDefaultRootWindow(dpy),
+                    xlib::XRootWindow(dpy, screen),
                     0,
                     0,
                     500,
@@ -89,27 +187,57 @@ impl Display {
                     bg
                 );
 
-                let screen = xlib::XDefaultScreen(dpy);
+                // the back buffer has to match the window, which inherits the default visual from the root window
+
+                let depth = xlib::XDefaultDepth(dpy, screen) as u32;
+                let visual = xlib::XDefaultVisual(dpy, screen);
 
                 let mut values: xlib::XGCValues = mem::zeroed();
 
                 let gc = xlib::XCreateGC(dpy, window, 0, &mut values);
-                let back_buffer = xlib::XCreatePixmap(dpy, window, 945, 1020, 24);
+                let back_buffer = xlib::XCreatePixmap(dpy, window, 945, 1020, depth);
                 let draw = xft::XftDrawCreate(dpy, back_buffer, xlib::XDefaultVisual(dpy, screen), xlib::XDefaultColormap(dpy, screen));
 
-                xlib::XSetLocaleModifiers("\0".as_ptr() as *const i8);
+                // the locale decides which compose table xlib loads, the local input method still reads ~/.XCompose when the configured one is missing
 
-                let xim = xlib::XOpenIM(dpy, 0 as xlib::XrmDatabase, 0 as *mut ffi::c_char, 0 as *mut ffi::c_char);
+                libc::setlocale(libc::LC_CTYPE, c"".as_ptr());
+
+                xlib::XSetLocaleModifiers(c"".as_ptr());
+
+                let mut xim = xlib::XOpenIM(dpy, 0 as xlib::XrmDatabase, 0 as *mut ffi::c_char, 0 as *mut ffi::c_char);
+
+                if xim.is_null() {
+                    xlib::XSetLocaleModifiers(c"@im=local".as_ptr());
+
+                    xim = xlib::XOpenIM(dpy, 0 as xlib::XrmDatabase, 0 as *mut ffi::c_char, 0 as *mut ffi::c_char);
+                }
+
+                if !xim.is_null() {
+                    let mut destroy = xlib::XIMCallback {
+                        client_data: ptr::null_mut(),
+                        callback: Some(im_destroyed),
+                    };
+
+                    xlib::XSetIMValues(xim, xlib::XNDestroyCallback_0.as_ptr(), &mut destroy as *mut xlib::XIMCallback, ptr::null_mut::<ffi::c_void>());
+                }
 
                 let xn_input_style = ffi::CString::new(xlib::XNInputStyle)?;
                 let xn_client_window = ffi::CString::new(xlib::XNClientWindow)?;
 
-                let xic = xlib::XCreateIC(
-                    xim,
-                    xn_input_style.as_ptr(), xlib::XIMPreeditNothing | xlib::XIMStatusNothing,
-                    xn_client_window.as_ptr(), window as ffi::c_ulong,
-                    ptr::null_mut::<ffi::c_void>()
-                );
+                let xic = if xim.is_null() {
+                    ptr::null_mut()
+                } else {
+                    xlib::XCreateIC(
+                        xim,
+                        xn_input_style.as_ptr(), xlib::XIMPreeditNothing | xlib::XIMStatusNothing,
+                        xn_client_window.as_ptr(), window as ffi::c_ulong,
+                        ptr::null_mut::<ffi::c_void>()
+                    )
+                };
+
+                if xic.is_null() {
+                    println!("[+] no input method available, using the builtin compose table");
+                }
 
                 xlib::XSync(dpy, xlib::False);
 
@@ -122,73 +250,304 @@ impl Display {
                     back_buffer,
                     window,
                     screen,
+                    depth,
+                    masks: [(*visual).red_mask, (*visual).green_mask, (*visual).blue_mask],
+                    true_color: (*visual).class == xlib::TrueColor,
+                    compose: Compose::new(),
+                    glyphs: HashMap::new(),
+                    specs: Vec::new(),
+                    clipboard: Clipboard::new()?,
+                    selection: Selection::spawn()?,
                 })
             }
         }
     }
 
-    pub fn resize_back_buffer(&mut self, window: &crate::terminal::Window) {
+    pub fn get_window_attributes(&mut self) -> xlib::XWindowAttributes {
+        unsafe {
+            let mut attr: xlib::XWindowAttributes = mem::zeroed();
+
+            xlib::XGetWindowAttributes(self.dpy, self.window, &mut attr);
+
+            attr
+        }
+    }
+
+    pub fn window_id(&self) -> u64 {
+        self.window
+    }
+
+    fn change_state(&mut self, property: &ffi::CStr, action: i64) {
+        // https://specifications.freedesktop.org/wm-spec/latest/ar01s05.html#id-1.6.8, action 0 removes, 1 adds and 2 toggles
+
+        unsafe {
+            let state = xlib::XInternAtom(self.dpy, c"_NET_WM_STATE".as_ptr(), xlib::False);
+            let property = xlib::XInternAtom(self.dpy, property.as_ptr(), xlib::False);
+
+            let mut event: xlib::XEvent = mem::zeroed();
+
+            event.client_message.type_ = xlib::ClientMessage;
+            event.client_message.window = self.window;
+            event.client_message.message_type = state;
+            event.client_message.format = 32;
+            event.client_message.data.set_long(0, action);
+            event.client_message.data.set_long(1, property as i64);
+            event.client_message.data.set_long(3, 1);
+
+            xlib::XSendEvent(
+                self.dpy,
+                xlib::XRootWindow(self.dpy, self.screen),
+                xlib::False,
+                xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask,
+                &mut event,
+            );
+        }
+    }
+
+    fn check_input_method(&mut self) {
+        if IM_DESTROYED.swap(false, Ordering::Relaxed) {
+            println!("[+] input method went away, using the builtin compose table");
+
+            self.xim = ptr::null_mut();
+            self.xic = ptr::null_mut();
+        }
+    }
+
+    fn null_terminate(&self, string: &str) -> String {
+        format!("{}\0", string)
+    }
+
+    pub fn xft_draw_string_32(
+        &mut self,
+        text: &[char],
+        x: i32,
+        y: i32,
+        font: *mut xft::XftFont,
+        color: *const xft::XftColor,
+    ) {
+        unsafe {
+            xft::XftDrawString32(self.draw, color, font, x, y, [text, &['\0']].concat().as_ptr() as *const u32, text.len() as i32);
+        }
+    }
+
+    pub fn xft_measure_string(&self, text: &str, font: *mut xft::XftFont) -> xrender::_XGlyphInfo {
+        unsafe {
+            let mut extents: xrender::_XGlyphInfo = mem::zeroed();
+
+            xft::XftTextExtentsUtf8(self.dpy, font, self.null_terminate(text).as_ptr(), text.len() as i32, &mut extents);
+
+            extents
+        }
+    }
+}
+
+impl Frontend for Display {
+    fn resize_back_buffer(&mut self, window: &Window) {
         unsafe {
             xlib::XFreePixmap(self.dpy, self.back_buffer);
             xft::XftDrawDestroy(self.draw);
 
-            self.back_buffer = xlib::XCreatePixmap(self.dpy, self.window, window.width, window.height, 24);
+            self.back_buffer = xlib::XCreatePixmap(self.dpy, self.window, window.width, window.height, self.depth);
             self.draw = xft::XftDrawCreate(self.dpy, self.back_buffer, xlib::XDefaultVisual(self.dpy, self.screen), xlib::XDefaultColormap(self.dpy, self.screen));
         }
     }
 
-    pub fn get_window_attributes(&mut self) -> xlib::XWindowAttributes {
+    fn flush(&mut self) {
         unsafe {
-            let mut attr: xlib::XWindowAttributes = mem::zeroed();
+            xlib::XFlush(self.dpy);
+        }
+    }
 
-            xlib::XGetWindowAttributes(self.dpy, self.window, &mut attr);
+    fn pending(&mut self) -> i32 {
+        unsafe {
+            xlib::XPending(self.dpy)
+        }
+    }
 
-            attr
+    fn connection_number(&self) -> i32 {
+        unsafe {
+            xlib::XConnectionNumber(self.dpy)
+        }
+    }
+
+    fn select_layout_events(&mut self) -> Option<i32> {
+        unsafe {
+            let (mut opcode, mut event, mut error, mut major, mut minor) = (0, 0, 0, 1, 0);
+
+            if xlib::XkbQueryExtension(self.dpy, &mut opcode, &mut event, &mut error, &mut major, &mut minor) == 0 {
+                return None;
+            }
+
+            xlib::XkbSelectEventDetails(self.dpy, XKB_USE_CORE_KBD, xlib::XkbStateNotify as u32, xlib::XkbGroupStateMask, xlib::XkbGroupStateMask);
+
+            Some(event)
+        }
+    }
+
+    fn layout(&mut self) -> Option<String> {
+        unsafe {
+            let mut state: xlib::XkbStateRec = mem::zeroed();
+
+            if xlib::XkbGetState(self.dpy, XKB_USE_CORE_KBD, &mut state) != 0 {
+                return None;
+            }
+
+            let keyboard = xlib::XkbGetKeyboard(self.dpy, XKB_GROUP_NAMES_MASK, XKB_USE_CORE_KBD);
+
+            if keyboard.is_null() || (*keyboard).names.is_null() {
+                return None;
+            }
+
+            let atom = (*(*keyboard).names).groups[state.group as usize % xlib::XkbNumKbdGroups];
+
+            xlib::XkbFreeKeyboard(keyboard, 0, xlib::True);
+
+            let name = xlib::XGetAtomName(self.dpy, atom);
+
+            if name.is_null() {
+                None
+            } else {
+                let layout = ffi::CStr::from_ptr(name).to_string_lossy().to_string();
+
+                xlib::XFree(name as *mut ffi::c_void);
+
+                Some(layout)
+            }
+        }
+    }
+
+    fn set_urgency_hint(&mut self, urgent: bool) {
+        unsafe {
+            let hints = xlib::XGetWMHints(self.dpy, self.window);
+            let mut fallback: xlib::XWMHints = mem::zeroed();
+
+            let wm_hints = if hints.is_null() { &mut fallback } else { &mut *hints };
+
+            if urgent {
+                wm_hints.flags |= xlib::XUrgencyHint;
+            } else {
+                wm_hints.flags &= !xlib::XUrgencyHint;
+            }
+
+            xlib::XSetWMHints(self.dpy, self.window, wm_hints);
+
+            if !hints.is_null() {
+                xlib::XFree(hints as *mut ffi::c_void);
+            }
         }
     }
 
-    pub fn flush(&mut self) {
+    fn set_demands_attention(&mut self, urgent: bool) {
+        self.change_state(c"_NET_WM_STATE_DEMANDS_ATTENTION", urgent as i64);
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        self.change_state(c"_NET_WM_STATE_FULLSCREEN", 2);
+    }
+
+    // https://specifications.freedesktop.org/wm-spec/latest/ar01s03.html#id-1.4.10, source 2 asks as a pager so focus stealing prevention lets it through
+
+    fn activate(&mut self) {
         unsafe {
+            let mut event: xlib::XEvent = mem::zeroed();
+
+            event.client_message.type_ = xlib::ClientMessage;
+            event.client_message.window = self.window;
+            event.client_message.message_type = xlib::XInternAtom(self.dpy, c"_NET_ACTIVE_WINDOW".as_ptr(), xlib::False);
+            event.client_message.format = 32;
+            event.client_message.data.set_long(0, 2);
+            event.client_message.data.set_long(1, xlib::CurrentTime as i64);
+
+            xlib::XMapRaised(self.dpy, self.window);
+
+            xlib::XSendEvent(
+                self.dpy,
+                xlib::XRootWindow(self.dpy, self.screen),
+                xlib::False,
+                xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask,
+                &mut event,
+            );
+
             xlib::XFlush(self.dpy);
         }
     }
 
-    pub fn map_window(&mut self) {
+    fn map_window(&mut self) {
         unsafe {
             xlib::XMapWindow(self.dpy, self.window);
         }
     }
 
-    pub fn lookup_string(&mut self, mut event: xlib::XKeyEvent) -> Result<String, Box<dyn std::error::Error>> {
+    fn resize_window(&mut self, width: u32, height: u32) {
+        unsafe {
+            xlib::XResizeWindow(self.dpy, self.window, width, height);
+        }
+    }
+
+    fn iconify(&mut self) {
+        unsafe {
+            xlib::XIconifyWindow(self.dpy, self.window, self.screen);
+        }
+    }
+
+    fn lookup_string(&mut self, mut event: xlib::XKeyEvent) -> Result<String, Box<dyn std::error::Error>> {
         unsafe {
             let mut buf: [i8; 32] = [0; 32];
             let mut keysym = 0;
 
-            xlib::Xutf8LookupString(self.xic, &mut event, buf.as_mut_ptr(), 32, &mut keysym, ptr::null_mut());
+            self.check_input_method();
 
-            Ok(String::from_utf8(buf.map(|x| x as u8).to_vec())?)
-        }
-    }
+            if self.xic.is_null() {
+                // without an input method the text is latin-1, which maps byte for byte onto the first code points
 
-    fn null_terminate(&self, string: &str) -> String {
-        format!("{}\0", string)
+                let len = xlib::XLookupString(&mut event, buf.as_mut_ptr(), 32, &mut keysym, ptr::null_mut());
+
+                return Ok(match self.compose.feed(keysym as u32) {
+                    Feed::Pass => buf[..len.max(0) as usize].iter().map(|x| *x as u8 as char).collect(),
+                    Feed::Commit(c) => c.to_string(),
+                    Feed::Pending | Feed::Cancel => String::new(),
+                });
+            }
+
+            let mut status = 0;
+            let mut text = buf.to_vec();
+            let mut len = xlib::Xutf8LookupString(self.xic, &mut event, text.as_mut_ptr(), text.len() as i32, &mut keysym, &mut status);
+
+            // committed text from an input method can be longer than a single key
+
+            if status == xlib::XBufferOverflow {
+                text.resize(len as usize, 0);
+
+                len = xlib::Xutf8LookupString(self.xic, &mut event, text.as_mut_ptr(), text.len() as i32, &mut keysym, &mut status);
+            }
+
+            Ok(String::from_utf8_lossy(&text[..len.max(0) as usize].iter().map(|x| *x as u8).collect::<Vec<u8>>()).to_string())
+        }
     }
 
-    pub fn set_window_name(&mut self, name: &str) {
+    fn set_window_name(&mut self, name: &str) {
         unsafe {
             xlib::XStoreName(self.dpy, self.window, self.null_terminate(name).as_ptr() as *const i8);
         }
     }
 
-    pub fn poll_event(&mut self) -> Option<Vec<xlib::XEvent>> {
+    fn poll_event(&mut self) -> Option<Vec<xlib::XEvent>> {
         unsafe {
             let mut events: Vec<xlib::XEvent> = Vec::new();
 
+            self.check_input_method();
+
             while xlib::XPending(self.dpy) > 0 {
                 let mut event: xlib::XEvent = mem::zeroed();
 
                 xlib::XNextEvent(self.dpy, &mut event);
 
+                // the input method swallows the keys of a compose sequence and sends the result as a key press with keycode 0
+
+                if !self.xic.is_null() && xlib::XFilterEvent(&mut event, 0) == xlib::True {
+                    continue;
+                }
+
                 events.push(event);
             }
 
@@ -200,16 +559,21 @@ impl Display {
         }
     }
 
-    pub fn keycode_to_keysym(&mut self, keycode: u8) -> u64 {
+    fn keycode_to_keysym(&mut self, keycode: u8) -> u64 {
         unsafe {
             xlib::XKeycodeToKeysym(self.dpy, keycode, 0)
         }
     }
 
-    pub fn select_input(&mut self) {
+    fn select_input(&mut self) {
         unsafe {
+            // key releases are only reported for real releases, held keys just repeat the press
+
+            xlib::XkbSetDetectableAutoRepeat(self.dpy, xlib::True, ptr::null_mut());
+
             xlib::XSelectInput(self.dpy, self.window,
                                  xlib::KeyPressMask
+                               | xlib::KeyReleaseMask
                                | xlib::ExposureMask
                                | xlib::FocusChangeMask
                                | xlib::VisibilityChangeMask
@@ -220,22 +584,33 @@ impl Display {
         }
     }
 
-    pub fn define_cursor(&mut self) {
+    fn define_cursor(&mut self, shape: u32) {
         unsafe {
             // https://tronche.com/gui/x/xlib/appendix/b/
 
-            let cursor = xlib::XCreateFontCursor(self.dpy, 152);
+            let cursor = xlib::XCreateFontCursor(self.dpy, shape);
             xlib::XDefineCursor(self.dpy, self.window, cursor);
+            xlib::XFreeCursor(self.dpy, cursor);
         }
     }
 
-    pub fn swap_buffers(&mut self, window: &crate::terminal::Window) {
+    // moves pixels within the back buffer, x handles the source and destination overlapping
+
+    fn copy_area(&mut self, (x, y): (i32, i32), (width, height): (u32, u32), (dest_x, dest_y): (i32, i32)) {
+        unsafe {
+            xlib::XCopyArea(self.dpy, self.back_buffer, self.back_buffer, self.gc, x, y, width, height, dest_x, dest_y);
+        }
+    }
+
+    fn swap_buffers(&mut self, window: &Window) {
         unsafe {
             xlib::XCopyArea(self.dpy, self.back_buffer, self.window, self.gc, 0, 0, window.width, window.height, 0, 0);
         }
     }
 
-    pub fn xft_draw_string(
+    // xft takes the length so the text is passed as is without a nul terminated copy
+
+    fn xft_draw_string(
         &mut self,
         text: &str,
         x: i32,
@@ -253,58 +628,80 @@ impl Display {
                 width: width as u16,
             };
 
-            xft::XftDrawSetClipRectangles(self.draw, x, y - 15, &rectangle, 1);
+            xft::XftDrawSetClipRectangles(self.draw, x, y - (*font).ascent, &rectangle, 1);
 
-            xft::XftDrawStringUtf8(self.draw, color, font, x, y, self.null_terminate(text).as_ptr(), text.len() as i32);
+            xft::XftDrawStringUtf8(self.draw, color, font, x, y, text.as_ptr(), text.len() as i32);
 
             xft::XftDrawSetClip(self.draw, ptr::null_mut());
         }
     }
 
-    pub fn xft_draw_string_32(
+    fn xft_draw_string_clipped(
         &mut self,
-        text: &[char],
+        text: &str,
         x: i32,
         y: i32,
+        clip: (i32, i32, u32, u32),
         font: *mut xft::XftFont,
         color: *const xft::XftColor,
     ) {
         unsafe {
-            xft::XftDrawString32(self.draw, color, font, x, y, [text, &['\0']].concat().as_ptr() as *const u32, text.len() as i32);
+            let rectangle = xlib::XRectangle {
+                x: 0,
+                y: 0,
+                width: clip.2 as u16,
+                height: clip.3 as u16,
+            };
+
+            xft::XftDrawSetClipRectangles(self.draw, clip.0, clip.1, &rectangle, 1);
+
+            xft::XftDrawStringUtf8(self.draw, color, font, x, y, text.as_ptr(), text.len() as i32);
+
+            xft::XftDrawSetClip(self.draw, ptr::null_mut());
         }
     }
 
-    pub fn xft_draw_glyph(
+    // a run of glyphs in one face and color drawn with a single request, each glyph is placed at the x of its own cell
+    // so fonts whose advance is off from the cell width still line up
+
+    fn xft_draw_glyphs(
         &mut self,
-        glyph: u32,
-        x: i32,
+        glyphs: &[(char, i32)],
         y: i32,
+        clip: (i32, i32, u32, u32),
         font: *mut xft::XftFont,
         color: *const xft::XftColor,
     ) {
         unsafe {
-            let specs = xft::XftGlyphFontSpec {
-                font,
-                glyph,
-                x: x as i16,
-                y: y as i16,
-            };
+            self.specs.clear();
 
-            xft::XftDrawGlyphFontSpec(self.draw, color, &specs, 1);
-        }
-    }
+            for (glyph, x) in glyphs {
+                let index = *self.glyphs.entry((font as usize, *glyph)).or_insert_with(|| xft::XftCharIndex(self.dpy, font, *glyph as u32));
 
-    pub fn xft_measure_string(&self, text: &str, font: *mut xft::XftFont) -> xrender::_XGlyphInfo {
-        unsafe {
-            let mut extents: xrender::_XGlyphInfo = mem::zeroed();
+                self.specs.push(xft::XftGlyphFontSpec {
+                    font,
+                    glyph: index,
+                    x: *x as i16,
+                    y: y as i16,
+                });
+            }
 
-            xft::XftTextExtentsUtf8(self.dpy, font, self.null_terminate(text).as_ptr(), text.len() as i32, &mut extents);
+            let rectangle = xlib::XRectangle {
+                x: 0,
+                y: 0,
+                width: clip.2 as u16,
+                height: clip.3 as u16,
+            };
 
-            extents
+            xft::XftDrawSetClipRectangles(self.draw, clip.0, clip.1, &rectangle, 1);
+
+            xft::XftDrawGlyphFontSpec(self.draw, color, self.specs.as_ptr(), self.specs.len() as i32);
+
+            xft::XftDrawSetClip(self.draw, ptr::null_mut());
         }
     }
 
-    pub fn xft_color_alloc_value(&self, rgb: Color) -> Result<xft::XftColor, Box<dyn std::error::Error>> {
+    fn xft_color_alloc_value(&self, rgb: Color) -> Result<xft::XftColor, Box<dyn std::error::Error>> {
         // convert 8bit rgb to 16bit rgb
 
         let xrender_color = x11::xrender::XRenderColor {
@@ -333,7 +730,7 @@ impl Display {
         }
     }
 
-    pub fn load_font(&mut self, font: &str) -> Result<*mut xft::XftFont, Box<dyn std::error::Error>> {
+    fn load_font(&mut self, font: &str) -> Result<*mut xft::XftFont, Box<dyn std::error::Error>> {
         unsafe {
             let font = xft::XftFontOpenName(self.dpy, self.screen, self.null_terminate(font).as_ptr() as *const i8);
 
@@ -346,19 +743,86 @@ impl Display {
         }
     }
 
-    pub fn outline_rec(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
+    fn close_font(&mut self, font: *mut xft::XftFont) {
+        // a font opened later may get the same address
+
+        self.glyphs.retain(|(cached, _), _| *cached != font as usize);
+
+        unsafe {
+            xft::XftFontClose(self.dpy, font);
+        }
+    }
+
+    fn font_metrics(&self, font: *mut xft::XftFont) -> (i32, i32, i32) {
+        let font = unsafe { &*font };
+
+        (font.max_advance_width, font.ascent + font.descent, font.ascent)
+    }
+
+    fn outline_rec(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
         unsafe {
-            xlib::XSetForeground(self.dpy, self.gc, color.encode());
+            xlib::XSetForeground(self.dpy, self.gc, self.pixel(color));
             xlib::XDrawRectangle(self.dpy, self.back_buffer, self.gc, x, y, width, height);
         }
     }
 
-    pub fn draw_rec(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
+    fn pixel(&self, color: Color) -> u64 {
+        // scale each 8bit channel into its mask, 16bit and 30bit visuals dont use the 0xrrggbb layout
+
+        [color.r, color.g, color.b].iter().zip(self.masks).fold(0, |pixel, (channel, mask)| {
+            let max = (1u64 << mask.count_ones()) - 1;
+
+            pixel | (channel * max / 255) << mask.trailing_zeros()
+        })
+    }
+
+    fn draw_lines(&mut self, points: &[(i32, i32)], color: Color) {
+        let mut points = points.iter().map(|(x, y)| xlib::XPoint { x: *x as i16, y: *y as i16 }).collect::<Vec<xlib::XPoint>>();
+
         unsafe {
-            xlib::XSetForeground(self.dpy, self.gc, color.encode());
+            xlib::XSetForeground(self.dpy, self.gc, self.pixel(color));
+            xlib::XDrawLines(self.dpy, self.back_buffer, self.gc, points.as_mut_ptr(), points.len() as i32, xlib::CoordModeOrigin);
+        }
+    }
+
+    // the pixels come from pixel and are handed over as 32 bit words, which only 24 and 32 bit visuals use. source holds
+    // the first pixel row to draw and how many rows follow it
+
+    fn draw_image(&mut self, pixels: &[u32], (width, height): (u32, u32), source: (i32, u32), x: i32, y: i32) {
+        if !self.true_color || self.depth < 24 {
+            return;
+        }
+
+        unsafe {
+            let visual = xlib::XDefaultVisual(self.dpy, self.screen);
+            let image = xlib::XCreateImage(self.dpy, visual, self.depth, xlib::ZPixmap, 0, pixels.as_ptr() as *mut ffi::c_char, width, height, 32, 0);
+
+            if image.is_null() {
+                return;
+            }
+
+            xlib::XPutImage(self.dpy, self.back_buffer, self.gc, image, 0, source.0, x, y, width, source.1);
+
+            // the pixels are still borrowed, destroying the image would free them
+
+            (*image).data = ptr::null_mut();
+
+            xlib::XDestroyImage(image);
+        }
+    }
+
+    fn draw_rec(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
+        unsafe {
+            xlib::XSetForeground(self.dpy, self.gc, self.pixel(color));
             xlib::XFillRectangle(self.dpy, self.back_buffer, self.gc, x, y, width, height);
         }
     }
-}
 
+    fn clipboard(&mut self) -> Option<String> {
+        self.clipboard.get_text().ok()
+    }
 
+    fn set_clipboard(&mut self, text: String) -> Result<(), Box<dyn std::error::Error>> {
+        self.selection.set(self.dpy, text)
+    }
+}
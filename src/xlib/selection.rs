@@ -0,0 +1,263 @@
+use x11::xlib;
+
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::OnceLock;
+use std::ffi;
+use std::ptr;
+use std::mem;
+use std::rc::Rc;
+use std::thread;
+
+// the connection of the owner thread, errors on it come from requestors that went away mid transfer
+
+static OWNER_DISPLAY: AtomicPtr<xlib::_XDisplay> = AtomicPtr::new(ptr::null_mut());
+static PREVIOUS_HANDLER: OnceLock<xlib::XErrorHandler> = OnceLock::new();
+
+unsafe extern "C" fn ignore_requestor_errors(dpy: *mut xlib::_XDisplay, event: *mut xlib::XErrorEvent) -> ffi::c_int {
+    if dpy == OWNER_DISPLAY.load(Ordering::Relaxed) {
+        return 0;
+    }
+
+    match PREVIOUS_HANDLER.get().copied().flatten() {
+        Some(handler) => handler(dpy, event),
+        None => 0,
+    }
+}
+
+
+// https://x.org/releases/X11R7.7/doc/xorg-docs/icccm/icccm.html#Use_of_Selection_Atoms, the clipboard is owned by a window
+// on a connection of its own so a slow requestor never stalls drawing. anything larger than one request is sent with INCR
+
+pub struct Selection {
+    window: u64,
+    texts: Sender<String>,
+}
+
+impl Selection {
+    pub fn spawn() -> Result<Selection, Box<dyn std::error::Error>> {
+        let (texts, received) = mpsc::channel::<String>();
+        let (opened, window) = mpsc::sync_channel::<Result<u64, String>>(1);
+
+        thread::spawn(move || {
+            match unsafe { Owner::open() } {
+                Ok(owner) => {
+                    let _ = opened.send(Ok(owner.window));
+
+                    unsafe { owner.run(received) }
+                },
+                Err(err) => {
+                    let _ = opened.send(Err(err.to_string()));
+                },
+            }
+        });
+
+        Ok(Selection {
+            window: window.recv()??,
+            texts,
+        })
+    }
+
+    // the owner thread blocks on its connection, the client message wakes it up to claim the clipboard for the new text
+
+    pub fn set(&self, dpy: *mut xlib::_XDisplay, text: String) -> Result<(), Box<dyn std::error::Error>> {
+        self.texts.send(text)?;
+
+        unsafe {
+            let mut event: xlib::XEvent = mem::zeroed();
+
+            event.client_message.type_ = xlib::ClientMessage;
+            event.client_message.window = self.window;
+            event.client_message.format = 32;
+
+            xlib::XSendEvent(dpy, self.window, xlib::False, xlib::NoEventMask, &mut event);
+            xlib::XFlush(dpy);
+        }
+
+        Ok(())
+    }
+}
+
+struct Atoms {
+    clipboard: u64,
+    targets: u64,
+    incr: u64,
+    text: u64,
+    utf8_string: u64,
+    utf8_plain: u64,
+}
+
+// a selection larger than one request, the requestor asks for each chunk by deleting the property
+
+struct Transfer {
+    data: Rc<[u8]>,
+    kind: u64,
+    offset: usize,
+}
+
+struct Owner {
+    dpy: *mut xlib::_XDisplay,
+    window: u64,
+    atoms: Atoms,
+    chunk: usize,
+    text: Option<Rc<[u8]>>,
+    transfers: HashMap<(u64, u64), Transfer>,
+}
+
+impl Owner {
+    unsafe fn open() -> Result<Owner, Box<dyn std::error::Error>> {
+        let dpy = xlib::XOpenDisplay(ptr::null());
+
+        if dpy.is_null() {
+            return Err("failed to open display for the selection".into());
+        }
+
+        OWNER_DISPLAY.store(dpy, Ordering::Relaxed);
+        PREVIOUS_HANDLER.get_or_init(|| xlib::XSetErrorHandler(Some(ignore_requestor_errors)));
+
+        let window = xlib::XCreateSimpleWindow(dpy, xlib::XDefaultRootWindow(dpy), 0, 0, 1, 1, 0, 0, 0);
+        let atom = |name: &ffi::CStr| xlib::XInternAtom(dpy, name.as_ptr(), xlib::False);
+
+        // the change property header takes 24 bytes of the request, big requests are left alone as icccm asks
+
+        let chunk = (xlib::XMaxRequestSize(dpy).max(0) as usize * 4).saturating_sub(24);
+
+        Ok(Owner {
+            dpy,
+            window,
+            atoms: Atoms {
+                clipboard: atom(c"CLIPBOARD"),
+                targets: atom(c"TARGETS"),
+                incr: atom(c"INCR"),
+                text: atom(c"TEXT"),
+                utf8_string: atom(c"UTF8_STRING"),
+                utf8_plain: atom(c"text/plain;charset=utf-8"),
+            },
+            chunk,
+            text: None,
+            transfers: HashMap::new(),
+        })
+    }
+
+    unsafe fn run(mut self, texts: Receiver<String>) {
+        loop {
+            let mut event: xlib::XEvent = mem::zeroed();
+
+            xlib::XNextEvent(self.dpy, &mut event);
+
+            match event.get_type() {
+                xlib::ClientMessage => {
+                    if let Some(text) = texts.try_iter().last() {
+                        self.text = Some(Rc::from(text.into_bytes()));
+
+                        xlib::XSetSelectionOwner(self.dpy, self.atoms.clipboard, self.window, xlib::CurrentTime);
+                    }
+                },
+                xlib::SelectionClear => {
+                    // a clear from before the last copy claimed the clipboard again is stale
+
+                    if xlib::XGetSelectionOwner(self.dpy, self.atoms.clipboard) != self.window {
+                        self.text = None;
+                    }
+                },
+                xlib::SelectionRequest => self.request(event.selection_request),
+                xlib::PropertyNotify => {
+                    if event.property.state == xlib::PropertyDelete {
+                        self.next_chunk(event.property.window, event.property.atom);
+                    }
+                },
+                xlib::DestroyNotify => {
+                    let window = event.destroy_window.window;
+
+                    self.transfers.retain(|(requestor, _), _| *requestor != window);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    unsafe fn request(&mut self, request: xlib::XSelectionRequestEvent) {
+        // obsolete clients leave out the property and expect the target to be used instead
+
+        let property = if request.property == 0 { request.target } else { request.property };
+        let utf8 = [self.atoms.utf8_string, self.atoms.utf8_plain, self.atoms.text];
+
+        let stored = match self.text.clone() {
+            _ if request.target == self.atoms.targets => {
+                let targets = [self.atoms.targets, self.atoms.utf8_string, self.atoms.utf8_plain, self.atoms.text, xlib::XA_STRING];
+
+                xlib::XChangeProperty(self.dpy, request.requestor, property, xlib::XA_ATOM, 32, xlib::PropModeReplace, targets.as_ptr() as *const u8, targets.len() as i32);
+
+                true
+            },
+            Some(text) if utf8.contains(&request.target) => {
+                let kind = if request.target == self.atoms.text { self.atoms.utf8_string } else { request.target };
+
+                self.send(request.requestor, property, kind, text);
+
+                true
+            },
+            Some(text) if request.target == xlib::XA_STRING => {
+                let latin1 = String::from_utf8_lossy(&text).chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect::<Vec<u8>>();
+
+                self.send(request.requestor, property, xlib::XA_STRING, Rc::from(latin1));
+
+                true
+            },
+            _ => false,
+        };
+
+        let mut event: xlib::XEvent = mem::zeroed();
+
+        event.selection = xlib::XSelectionEvent {
+            type_: xlib::SelectionNotify,
+            serial: 0,
+            send_event: xlib::True,
+            display: self.dpy,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property: if stored { property } else { 0 },
+            time: request.time,
+        };
+
+        xlib::XSendEvent(self.dpy, request.requestor, xlib::False, xlib::NoEventMask, &mut event);
+    }
+
+    unsafe fn send(&mut self, requestor: u64, property: u64, kind: u64, data: Rc<[u8]>) {
+        if data.len() <= self.chunk {
+            xlib::XChangeProperty(self.dpy, requestor, property, kind, 8, xlib::PropModeReplace, data.as_ptr(), data.len() as i32);
+        } else {
+            // the requestor deletes the INCR property once it read the notify, every delete after that asks for the next chunk
+
+            let size = data.len() as ffi::c_long;
+
+            xlib::XSelectInput(self.dpy, requestor, xlib::PropertyChangeMask | xlib::StructureNotifyMask);
+            xlib::XChangeProperty(self.dpy, requestor, property, self.atoms.incr, 32, xlib::PropModeReplace, &size as *const ffi::c_long as *const u8, 1);
+
+            self.transfers.insert((requestor, property), Transfer { data, kind, offset: 0 });
+        }
+    }
+
+    unsafe fn next_chunk(&mut self, requestor: u64, property: u64) {
+        let Some(transfer) = self.transfers.get_mut(&(requestor, property)) else { return };
+
+        let end = (transfer.offset + self.chunk).min(transfer.data.len());
+        let chunk = &transfer.data[transfer.offset..end];
+
+        xlib::XChangeProperty(self.dpy, requestor, property, transfer.kind, 8, xlib::PropModeReplace, chunk.as_ptr(), chunk.len() as i32);
+
+        // the zero length chunk after the last one ends the transfer
+
+        if chunk.is_empty() {
+            self.transfers.remove(&(requestor, property));
+
+            if !self.transfers.keys().any(|(window, _)| *window == requestor) {
+                xlib::XSelectInput(self.dpy, requestor, xlib::NoEventMask);
+            }
+        } else {
+            transfer.offset = end;
+        }
+    }
+}
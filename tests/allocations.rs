@@ -0,0 +1,63 @@
+// a counting allocator replaces the allocator of the whole binary, so the allocation checks get a test binary of their
+// own instead of sharing one with the unit tests. the modules are built into it like main.rs does
+
+#![allow(dead_code)]
+
+#[path = "../src/terminal/mod.rs"]
+mod terminal;
+#[path = "../src/keybind/mod.rs"]
+mod keybind;
+#[path = "../src/escape/mod.rs"]
+mod escape;
+#[path = "../src/config/mod.rs"]
+mod config;
+#[path = "../src/xlib/mod.rs"]
+mod xlib;
+#[path = "../src/pty/mod.rs"]
+mod pty;
+#[path = "../src/a11y/mod.rs"]
+mod a11y;
+#[path = "../src/ipc/mod.rs"]
+mod ipc;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::process;
+
+
+// counted per thread, whatever other threads allocate in the meantime doesn't show up
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn main() {
+    match terminal::headless::frame_allocations(|| ALLOCATIONS.with(Cell::get)) {
+        Ok(0) => println!("[+] redrawing allocated nothing"),
+        Ok(count) => {
+            println!("[+] redrawing allocated {} times", count);
+            process::exit(1);
+        },
+        Err(err) => {
+            println!("[+] failed to draw: {}", err);
+            process::exit(1);
+        },
+    }
+}